@@ -0,0 +1,539 @@
+use crate::context::{self, DEFAULT_CONTEXT_TOKENS};
+use crate::llm::{ChatClient, Message};
+use crate::registry::ToolRegistry;
+use crate::tools::ToolContext;
+use futures::future::join_all;
+use wavs_hats_common::{debug, error, warn};
+
+/// Default cap on model round trips spent executing tool calls
+pub const DEFAULT_MAX_TOOL_ITERATIONS: usize = 5;
+
+/// Default cap on the tool calls executed from a single model response
+pub const DEFAULT_MAX_TOOL_CALLS_PER_TURN: usize = 8;
+
+/// Settings of the tool calling loop
+#[derive(Debug, Clone)]
+pub struct AgentOptions {
+    /// Cap on model round trips spent executing tool calls
+    pub max_iterations: usize,
+    /// Send failed tool calls back to the model as results instead of aborting
+    pub return_tool_errors: bool,
+    /// Approximate token budget of the messages sent with each completion
+    pub context_tokens: usize,
+    /// Cap on the tool calls executed from a single response, further calls are dropped
+    pub max_tool_calls_per_turn: usize,
+}
+
+impl Default for AgentOptions {
+    fn default() -> Self {
+        Self {
+            max_iterations: DEFAULT_MAX_TOOL_ITERATIONS,
+            return_tool_errors: true,
+            context_tokens: DEFAULT_CONTEXT_TOKENS,
+            max_tool_calls_per_turn: DEFAULT_MAX_TOOL_CALLS_PER_TURN,
+        }
+    }
+}
+
+impl AgentOptions {
+    /// Read `WAVS_ENV_MAX_TOOL_ITERATIONS`, `WAVS_ENV_RETURN_TOOL_ERRORS`,
+    /// `WAVS_ENV_CONTEXT_TOKENS` and `WAVS_ENV_MAX_TOOL_CALLS_PER_TURN`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_iterations: std::env::var("WAVS_ENV_MAX_TOOL_ITERATIONS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(defaults.max_iterations),
+            return_tool_errors: wavs_hats_common::env::flag(
+                "WAVS_ENV_RETURN_TOOL_ERRORS",
+                defaults.return_tool_errors,
+            ),
+            context_tokens: std::env::var("WAVS_ENV_CONTEXT_TOKENS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(defaults.context_tokens),
+            max_tool_calls_per_turn: std::env::var("WAVS_ENV_MAX_TOOL_CALLS_PER_TURN")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(defaults.max_tool_calls_per_turn),
+        }
+    }
+}
+
+/// Execute the tool calls of `response` and feed the results back to the model
+///
+/// The tool calls of a response are sorted by ID, so every operator builds the
+/// same conversation from the same calls whatever order the provider listed
+/// them in. They run concurrently, and their results are added in that
+/// order. Calls without an ID, as from Ollama, keep the provider order. The
+/// tools are offered again on every round so the model can keep calling them
+/// after seeing results. Only the first `max_tool_calls_per_turn` calls of a
+/// response run, and the model is told how many were dropped. Stops when the
+/// model answers without tool calls, or
+/// after `max_iterations` rounds, in which case the last content is returned
+/// with a note appended.
+///
+/// Each follow-up request sends the messages trimmed to the context budget,
+/// while `messages` keeps the full conversation.
+///
+/// A failed tool call is reported to the model as an `Error: ...` result so it
+/// can retry or explain, unless `return_tool_errors` is off, in which case the
+/// run aborts once every call of the round has finished.
+pub async fn process_tool_calls<C: ChatClient>(
+    client: &C,
+    messages: &mut Vec<Message>,
+    mut response: Message,
+    registry: &ToolRegistry,
+    ctx: &ToolContext,
+    options: &AgentOptions,
+) -> Result<String, String> {
+    let tools = registry.tools();
+    for iteration in 0..options.max_iterations {
+        let mut tool_calls = match response.tool_calls.take() {
+            Some(tool_calls) if !tool_calls.is_empty() => tool_calls,
+            _ => return Ok(response.content.unwrap_or_default()),
+        };
+        tool_calls.sort_by(|a, b| a.id.cmp(&b.id));
+        let dropped = tool_calls.len().saturating_sub(options.max_tool_calls_per_turn);
+        if dropped > 0 {
+            warn!(
+                "Dropping {} of {} tool calls, over the limit of {} per turn",
+                dropped,
+                tool_calls.len(),
+                options.max_tool_calls_per_turn
+            );
+            tool_calls.truncate(options.max_tool_calls_per_turn);
+        }
+
+        debug!("Tool iteration {}: {} tool call(s)", iteration + 1, tool_calls.len());
+
+        // OpenAI requires a content string on assistant messages with tool calls
+        messages.push(Message::new_assistant_with_tools(
+            response.content.take().unwrap_or_default(),
+            tool_calls.clone(),
+        ));
+
+        let results =
+            join_all(tool_calls.iter().map(|tool_call| registry.dispatch(tool_call, ctx))).await;
+
+        let mut errors = Vec::new();
+        for (tool_call, result) in tool_calls.iter().zip(results) {
+            let content = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    let error = format!(
+                        "Tool call {} ({}) failed: {}",
+                        tool_call.id, tool_call.function.name, e
+                    );
+                    error!("{}", error);
+                    errors.push(error);
+                    format!("Error: {}", e).into()
+                }
+            };
+            messages.push(Message::new_tool_result(tool_call.id.clone(), content));
+        }
+        if dropped > 0 {
+            messages.push(Message::new_system(format!(
+                "{} further tool calls were dropped, at most {} run per turn",
+                dropped, options.max_tool_calls_per_turn
+            )));
+        }
+        if !options.return_tool_errors && !errors.is_empty() {
+            return Err(errors.join("; "));
+        }
+
+        let window = context::trim_messages(client.model(), messages, options.context_tokens);
+        response = client.chat_completion(&window, Some(&tools)).await?;
+    }
+
+    let content = response.content.unwrap_or_default();
+    match response.tool_calls {
+        Some(tool_calls) if !tool_calls.is_empty() => Ok(format!(
+            "{}\n\n[Stopped after {} tool iterations without a final answer]",
+            content, options.max_iterations
+        )),
+        _ => Ok(content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{
+        parse_mock_responses, Function, LLMClient, Tool, ToolCall, ToolCallFunction, MOCK_MODEL,
+    };
+    use crate::registry::ToolFuture;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::task::{Context, Poll};
+    use wstd::runtime::block_on;
+
+    /// Replays canned responses and records what it was sent
+    struct FakeClient {
+        responses: RefCell<VecDeque<Message>>,
+        calls: RefCell<Vec<(usize, bool)>>,
+    }
+
+    impl FakeClient {
+        fn new(responses: Vec<Message>) -> Self {
+            Self { responses: RefCell::new(responses.into()), calls: RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl ChatClient for FakeClient {
+        fn model(&self) -> &str {
+            MOCK_MODEL
+        }
+
+        async fn chat_completion(
+            &self,
+            messages: &[Message],
+            tools: Option<&[Tool]>,
+        ) -> Result<Message, String> {
+            self.calls.borrow_mut().push((messages.len(), tools.is_some_and(|t| !t.is_empty())));
+            self.responses.borrow_mut().pop_front().ok_or_else(|| "No more responses".to_string())
+        }
+    }
+
+    /// Returns pending once so other futures get polled in between
+    ///
+    /// Natively the wstd reactor panics on a pending future without a pollable,
+    /// so tests awaiting this use the futures executor instead.
+    #[derive(Default)]
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    fn call(id: &str, name: &str) -> ToolCall {
+        ToolCall {
+            id: id.to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction { name: name.to_string(), arguments: "{}".to_string() },
+        }
+    }
+
+    fn tool_calls_response(calls: Vec<ToolCall>) -> Message {
+        Message::new_assistant_with_tools(String::new(), calls)
+    }
+
+    /// Registry of `names` tools that log when they start and finish
+    fn logging_registry(names: &[&'static str], log: &Rc<RefCell<Vec<String>>>) -> ToolRegistry {
+        let mut registry = ToolRegistry::new();
+        for &name in names {
+            let log = log.clone();
+            let tool = Tool {
+                tool_type: "function".to_string(),
+                function: Function {
+                    name: name.to_string(),
+                    description: None,
+                    parameters: None,
+                    output_schema: None,
+                },
+            };
+            registry
+                .register(name, tool, move |_, _| -> ToolFuture<'_> {
+                    let log = log.clone();
+                    Box::pin(async move {
+                        log.borrow_mut().push(format!("{} start", name));
+                        YieldNow::default().await;
+                        log.borrow_mut().push(format!("{} end", name));
+                        if name == "broken" {
+                            return Err("unavailable".to_string());
+                        }
+                        Ok(format!("{} result", name).into())
+                    })
+                })
+                .unwrap();
+        }
+        registry
+    }
+
+    fn tool_call_response(id: &str, a: u32, b: u32) -> Message {
+        Message {
+            content: None,
+            ..Message::new_assistant_with_tools(
+                String::new(),
+                vec![ToolCall {
+                    id: id.to_string(),
+                    tool_type: "function".to_string(),
+                    function: ToolCallFunction {
+                        name: "calculator".to_string(),
+                        arguments: format!(r#"{{"operation":"add","a":{},"b":{}}}"#, a, b),
+                    },
+                }],
+            )
+        }
+    }
+
+    fn answer(content: &str) -> Message {
+        Message::new_assistant(content.to_string())
+    }
+
+    #[test]
+    fn test_tool_loop_runs_until_final_answer() {
+        let client =
+            FakeClient::new(vec![tool_call_response("call_2", 4, 4), answer("The answer is 8")]);
+        let registry = ToolRegistry::builtin();
+        let mut messages = vec![Message::new_user("What is (2+2)+4?".to_string())];
+
+        let result = block_on(process_tool_calls(
+            &client,
+            &mut messages,
+            tool_call_response("call_1", 2, 2),
+            &registry,
+            &ToolContext::default(),
+            &AgentOptions::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(result, "The answer is 8");
+        // Tools are re-sent on every follow-up request
+        assert_eq!(*client.calls.borrow(), vec![(3, true), (5, true)]);
+
+        let tool_results: Vec<_> = messages.iter().filter(|m| m.role == "tool").collect();
+        assert_eq!(tool_results.len(), 2);
+        assert_eq!(tool_results[0].tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(tool_results[0].content.as_deref(), Some("The result of 2 + 2 is 4"));
+        assert_eq!(tool_results[1].content.as_deref(), Some("The result of 4 + 4 is 8"));
+    }
+
+    #[test]
+    fn test_tool_calls_per_turn_are_capped() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let registry = logging_registry(&["lookup"], &log);
+        let calls = (0..12).map(|i| call(&format!("call_{:02}", i), "lookup")).collect();
+        let client = FakeClient::new(vec![answer("Done")]);
+        let mut messages = vec![Message::new_user("Look up everything".to_string())];
+
+        let result = futures::executor::block_on(process_tool_calls(
+            &client,
+            &mut messages,
+            tool_calls_response(calls),
+            &registry,
+            &ToolContext::default(),
+            &AgentOptions { max_tool_calls_per_turn: 3, ..AgentOptions::default() },
+        ))
+        .unwrap();
+        assert_eq!(result, "Done");
+
+        // Only the capped calls ran, and the assistant message lists only those
+        assert_eq!(log.borrow().iter().filter(|entry| entry.ends_with("start")).count(), 3);
+        assert_eq!(messages[1].tool_calls.as_ref().unwrap().len(), 3);
+        let tool_results: Vec<_> = messages.iter().filter(|m| m.role == "tool").collect();
+        assert_eq!(tool_results.len(), 3);
+        assert_eq!(tool_results[2].tool_call_id.as_deref(), Some("call_02"));
+        assert_eq!(messages[5].role, "system");
+        assert_eq!(
+            messages[5].content.as_deref(),
+            Some("9 further tool calls were dropped, at most 3 run per turn")
+        );
+    }
+
+    #[test]
+    fn test_tool_loop_stops_at_cap() {
+        let client = FakeClient::new(vec![
+            tool_call_response("call_2", 1, 1),
+            tool_call_response("call_3", 1, 1),
+        ]);
+        let registry = ToolRegistry::builtin();
+        let mut messages = vec![Message::new_user("Keep adding".to_string())];
+
+        let result = block_on(process_tool_calls(
+            &client,
+            &mut messages,
+            tool_call_response("call_1", 1, 1),
+            &registry,
+            &ToolContext::default(),
+            &AgentOptions { max_iterations: 2, ..AgentOptions::default() },
+        ))
+        .unwrap();
+
+        assert_eq!(client.calls.borrow().len(), 2);
+        assert!(result.contains("[Stopped after 2 tool iterations"));
+    }
+
+    #[test]
+    fn test_tool_calls_run_concurrently_in_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let registry = logging_registry(&["first", "second"], &log);
+        let client = FakeClient::new(vec![answer("Done")]);
+        let mut messages = vec![Message::new_user("Use both tools".to_string())];
+
+        let result = futures::executor::block_on(process_tool_calls(
+            &client,
+            &mut messages,
+            tool_calls_response(vec![call("call_1", "first"), call("call_2", "second")]),
+            &registry,
+            &ToolContext::default(),
+            &AgentOptions::default(),
+        ))
+        .unwrap();
+        assert_eq!(result, "Done");
+
+        // Both tools started before either finished
+        assert_eq!(*log.borrow(), ["first start", "second start", "first end", "second end"]);
+
+        let tool_results: Vec<_> = messages
+            .iter()
+            .filter(|m| m.role == "tool")
+            .map(|m| (m.tool_call_id.as_deref().unwrap(), m.content.as_deref().unwrap()))
+            .collect();
+        assert_eq!(tool_results, [("call_1", "first result"), ("call_2", "second result")]);
+    }
+
+    #[test]
+    fn test_tool_calls_are_ordered_by_id() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let registry = logging_registry(&["first", "second", "third"], &log);
+        let client = FakeClient::new(vec![answer("Done")]);
+        let mut messages = vec![Message::new_user("Use every tool".to_string())];
+
+        futures::executor::block_on(process_tool_calls(
+            &client,
+            &mut messages,
+            tool_calls_response(vec![
+                call("call_c", "third"),
+                call("call_a", "first"),
+                call("call_b", "second"),
+            ]),
+            &registry,
+            &ToolContext::default(),
+            &AgentOptions::default(),
+        ))
+        .unwrap();
+
+        let requested: Vec<_> =
+            messages[1].tool_calls.as_ref().unwrap().iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(requested, ["call_a", "call_b", "call_c"]);
+        let tool_results: Vec<_> = messages
+            .iter()
+            .filter(|m| m.role == "tool")
+            .map(|m| (m.tool_call_id.as_deref().unwrap(), m.content.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            tool_results,
+            [("call_a", "first result"), ("call_b", "second result"), ("call_c", "third result")]
+        );
+    }
+
+    #[test]
+    fn test_failing_tool_call_aborts_after_others_finish() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let registry = logging_registry(&["broken", "second"], &log);
+        let client = FakeClient::new(vec![]);
+        let mut messages = vec![Message::new_user("Use both tools".to_string())];
+
+        let err = futures::executor::block_on(process_tool_calls(
+            &client,
+            &mut messages,
+            tool_calls_response(vec![call("call_1", "broken"), call("call_2", "second")]),
+            &registry,
+            &ToolContext::default(),
+            &AgentOptions { return_tool_errors: false, ..AgentOptions::default() },
+        ))
+        .unwrap_err();
+
+        assert_eq!(err, "Tool call call_1 (broken) failed: unavailable");
+        assert!(log.borrow().contains(&"second end".to_string()));
+    }
+
+    #[test]
+    fn test_tool_error_is_returned_to_model() {
+        let client = FakeClient::new(vec![answer("Sorry, I cannot divide by zero")]);
+        let registry = ToolRegistry::builtin();
+        let mut messages = vec![Message::new_user("What is 1/0?".to_string())];
+        let mut response = tool_call_response("call_1", 1, 0);
+        response.tool_calls.as_mut().unwrap()[0].function.arguments =
+            r#"{"operation":"divide","a":1,"b":0}"#.to_string();
+
+        let result = block_on(process_tool_calls(
+            &client,
+            &mut messages,
+            response,
+            &registry,
+            &ToolContext::default(),
+            &AgentOptions::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(result, "Sorry, I cannot divide by zero");
+        let tool_result = messages.iter().find(|m| m.role == "tool").unwrap();
+        assert_eq!(tool_result.tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(tool_result.content.as_deref(), Some("Error: Cannot divide by zero"));
+        // The follow-up request carried the error result
+        assert_eq!(*client.calls.borrow(), vec![(3, true)]);
+    }
+
+    #[test]
+    fn test_tool_loop_with_mock_client() {
+        let client = LLMClient::mock(
+            parse_mock_responses(
+                r#"[{"tool_calls":[{"id":"call_1","function":{"name":"calculator",
+                    "arguments":"{\"operation\":\"multiply\",\"a\":6,\"b\":7}"}}]},
+                "The answer is 42"]"#,
+            )
+            .unwrap(),
+        );
+        let registry = ToolRegistry::builtin();
+        let mut messages = vec![Message::new_user("What is 6*7?".to_string())];
+
+        let result = block_on(async {
+            let response = client.chat_completion(&messages, Some(&registry.tools())).await?;
+            process_tool_calls(
+                &client,
+                &mut messages,
+                response,
+                &registry,
+                &ToolContext::default(),
+                &AgentOptions::default(),
+            )
+            .await
+        })
+        .unwrap();
+
+        assert_eq!(result, "The answer is 42");
+        let tool_result = messages.iter().find(|m| m.role == "tool").unwrap();
+        assert_eq!(tool_result.content.as_deref(), Some("The result of 6 * 7 is 42"));
+    }
+
+    #[test]
+    fn test_tool_loop_trims_context() {
+        let client = FakeClient::new(vec![answer("The answer is 4")]);
+        let registry = ToolRegistry::builtin();
+        let mut messages = vec![
+            Message::new_system("Be brief.".to_string()),
+            Message::new_user("x".repeat(400)),
+            answer(&"y".repeat(400)),
+            Message::new_user("What is 2+2?".to_string()),
+        ];
+
+        block_on(process_tool_calls(
+            &client,
+            &mut messages,
+            tool_call_response("call_1", 2, 2),
+            &registry,
+            &ToolContext::default(),
+            &AgentOptions { context_tokens: 100, ..AgentOptions::default() },
+        ))
+        .unwrap();
+
+        // The two long turns were left out of the request, but not the history
+        assert_eq!(*client.calls.borrow(), vec![(4, true)]);
+        assert_eq!(messages.len(), 6);
+    }
+}
@@ -0,0 +1,279 @@
+//! Agent configuration, optionally read from the metadata of a configuration hat
+
+use crate::llm::{check_model_allowed, Message};
+use alloy_network::Ethereum;
+use alloy_primitives::U256;
+use alloy_provider::RootProvider;
+use alloy_rpc_types::BlockId;
+use serde_json::Value;
+use std::ops::RangeInclusive;
+use wavs_hats_common::metadata::{self, HatMetadata};
+use wavs_hats_common::warn;
+
+/// Model used when the configuration hat does not name one
+pub const DEFAULT_MODEL: &str = "llama3.2";
+
+/// System prompt used when the configuration hat does not provide one
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful assistant for Hats Protocol, \
+    answering questions about hats, their wearers, and their admins. \
+    Use the available tools to look up hats on chain and to perform calculations.";
+
+/// Temperatures a configuration hat may set
+pub const TEMPERATURE_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+
+/// Model and prompt settings for a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgentConfig {
+    pub model: String,
+    /// Segments of the system prompt, each sent as its own system message
+    pub system_prompts: Vec<String>,
+    pub temperature: Option<f32>,
+    /// Built-in tools the agent offers, every enabled tool when unset
+    pub tools: Option<Vec<String>>,
+    /// Whether example tool calls open the conversation, see [`AgentConfig::few_shot`]
+    pub few_shot: Option<bool>,
+    /// JSON schema the answer is checked against, see [`crate::structured`]
+    pub output_schema: Option<Value>,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            model: DEFAULT_MODEL.to_string(),
+            system_prompts: vec![DEFAULT_SYSTEM_PROMPT.to_string()],
+            temperature: None,
+            tools: None,
+            few_shot: None,
+            output_schema: None,
+        }
+    }
+}
+
+impl AgentConfig {
+    /// Build a config from hat metadata JSON, using defaults for missing fields
+    ///
+    /// Metadata is untrusted, so a model off the `WAVS_ENV_ALLOWED_MODELS`
+    /// list or a temperature outside [`TEMPERATURE_RANGE`] is replaced by its
+    /// default with a warning.
+    pub fn from_metadata(metadata: &[u8]) -> Result<Self, String> {
+        Self::from_metadata_allowing(
+            metadata,
+            std::env::var("WAVS_ENV_ALLOWED_MODELS").ok().as_deref(),
+        )
+    }
+
+    /// [`AgentConfig::from_metadata`] with the comma separated `allowed` models
+    fn from_metadata_allowing(metadata: &[u8], allowed: Option<&str>) -> Result<Self, String> {
+        let metadata = HatMetadata::from_json(metadata)?.agent.unwrap_or_default();
+        let defaults = Self::default();
+
+        let model = metadata.model.filter(|m| !m.trim().is_empty()).filter(|model| {
+            check_model_allowed(model, allowed)
+                .map_err(|e| warn!("Ignoring the metadata model: {}", e))
+                .is_ok()
+        });
+        let temperature = metadata.temperature.filter(|temperature| {
+            let valid = TEMPERATURE_RANGE.contains(temperature);
+            if !valid {
+                warn!(
+                    "Ignoring the metadata temperature {}: outside {:?}",
+                    temperature, TEMPERATURE_RANGE
+                );
+            }
+            valid
+        });
+        let output_schema = metadata.output_schema.filter(|schema| {
+            if !schema.is_object() {
+                warn!("Ignoring the metadata output schema {}: not an object", schema);
+            }
+            schema.is_object()
+        });
+
+        Ok(Self {
+            model: model.unwrap_or(defaults.model),
+            system_prompts: Some(
+                metadata
+                    .system_prompt
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|p| !p.trim().is_empty())
+                    .collect::<Vec<_>>(),
+            )
+            .filter(|prompts| !prompts.is_empty())
+            .unwrap_or(defaults.system_prompts),
+            temperature: temperature.or(defaults.temperature),
+            tools: metadata.tools.or(defaults.tools),
+            few_shot: metadata.few_shot.or(defaults.few_shot),
+            output_schema: output_schema.or(defaults.output_schema),
+        })
+    }
+
+    /// Load the config from the hat set in `WAVS_ENV_AGENT_HAT_ID`, if any
+    ///
+    /// The hat's metadata URI is read as of `block` and resolved through IPFS
+    /// or HTTPS. A hat without a URI uses the defaults.
+    pub async fn load(
+        provider: Option<&RootProvider<Ethereum>>,
+        block: BlockId,
+    ) -> Result<Self, String> {
+        let Ok(raw_id) = std::env::var("WAVS_ENV_AGENT_HAT_ID") else {
+            return Ok(Self::default());
+        };
+        let hat_id: U256 =
+            raw_id.trim().parse().map_err(|e| format!("Invalid agent hat ID {}: {}", raw_id, e))?;
+        let provider =
+            provider.ok_or_else(|| "No provider available to read agent hat".to_string())?;
+
+        match metadata::hat_metadata(provider, hat_id, block).await? {
+            Some(metadata) => Self::from_metadata(&metadata),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Whether example tool calls of [`crate::fewshot`] open the conversation,
+    /// as set by the hat or else by `WAVS_ENV_AGENT_FEW_SHOT`
+    pub fn few_shot(&self) -> bool {
+        self.few_shot
+            .unwrap_or_else(|| wavs_hats_common::env::flag("WAVS_ENV_AGENT_FEW_SHOT", false))
+    }
+
+    /// System messages sent to the model, one per segment, in order
+    ///
+    /// The resolved segments are wrapped in the operator's guardrails from
+    /// `WAVS_ENV_SYSTEM_PROMPT_PREFIX` and `WAVS_ENV_SYSTEM_PROMPT_SUFFIX`, so
+    /// a configuration hat can't drop them.
+    pub fn system_messages(&self) -> Vec<Message> {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        self.wrapped_system_messages(
+            &var("WAVS_ENV_SYSTEM_PROMPT_PREFIX"),
+            &var("WAVS_ENV_SYSTEM_PROMPT_SUFFIX"),
+        )
+    }
+
+    /// System messages of the segments between `prefix` and `suffix`, each
+    /// skipped when blank
+    fn wrapped_system_messages(&self, prefix: &str, suffix: &str) -> Vec<Message> {
+        std::iter::once(prefix.trim())
+            .chain(self.system_prompts.iter().map(String::as_str))
+            .chain(std::iter::once(suffix.trim()))
+            .filter(|part| !part.is_empty())
+            .map(|part| Message::new_system(part.to_string()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_metadata() {
+        let config = AgentConfig::from_metadata(
+            br#"{"name": "Agent Hat", "model": "gpt-4", "systemPrompt": "Be brief.", "temperature": 0.7}"#,
+        )
+        .unwrap();
+        assert_eq!(config.model, "gpt-4");
+        assert_eq!(config.system_prompts, ["Be brief."]);
+        assert_eq!(config.temperature, Some(0.7));
+        assert_eq!(config.tools, None);
+
+        let config = AgentConfig::from_metadata(
+            br#"{"name": "Agent Hat", "agent": {"model": "gpt-4", "tools": ["calculator"],
+                "outputSchema": {"type": "object"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.model, "gpt-4");
+        assert_eq!(config.system_prompts, [DEFAULT_SYSTEM_PROMPT]);
+        assert_eq!(config.tools, Some(vec!["calculator".to_string()]));
+        assert_eq!(config.output_schema, Some(serde_json::json!({ "type": "object" })));
+    }
+
+    #[test]
+    fn test_missing_fields_use_defaults() {
+        assert_eq!(AgentConfig::from_metadata(b"{}").unwrap(), AgentConfig::default());
+
+        let config = AgentConfig::from_metadata(br#"{"model": "", "systemPrompt": "Hi"}"#).unwrap();
+        assert_eq!(config.model, DEFAULT_MODEL);
+        assert_eq!(config.system_prompts, ["Hi"]);
+
+        let config = AgentConfig::from_metadata(br#"{"systemPrompt": ["", " "]}"#).unwrap();
+        assert_eq!(config.system_prompts, [DEFAULT_SYSTEM_PROMPT]);
+
+        // A schema that isn't an object is ignored
+        let config = AgentConfig::from_metadata(br#"{"outputSchema": "object"}"#).unwrap();
+        assert_eq!(config.output_schema, None);
+    }
+
+    #[test]
+    fn test_out_of_range_temperature_uses_default() {
+        for temperature in ["-0.5", "2.5"] {
+            let metadata = format!(r#"{{"model": "gpt-4", "temperature": {}}}"#, temperature);
+            let config = AgentConfig::from_metadata_allowing(metadata.as_bytes(), None).unwrap();
+            assert_eq!(config.temperature, None);
+            assert_eq!(config.model, "gpt-4");
+        }
+
+        let config = AgentConfig::from_metadata(br#"{"temperature": 2}"#).unwrap();
+        assert_eq!(config.temperature, Some(2.0));
+    }
+
+    #[test]
+    fn test_disallowed_metadata_model_uses_default() {
+        let metadata = br#"{"model": "gpt-4", "systemPrompt": "Be brief.", "temperature": 0.7}"#;
+        let config = AgentConfig::from_metadata_allowing(metadata, Some("llama3.2")).unwrap();
+        assert_eq!(config.model, DEFAULT_MODEL);
+        assert_eq!(config.system_prompts, ["Be brief."]);
+        assert_eq!(config.temperature, Some(0.7));
+
+        let config =
+            AgentConfig::from_metadata_allowing(metadata, Some("llama3.2, gpt-4")).unwrap();
+        assert_eq!(config.model, "gpt-4");
+    }
+
+    #[test]
+    fn test_invalid_metadata() {
+        assert!(AgentConfig::from_metadata(b"not json").is_err());
+        assert!(AgentConfig::from_metadata(br#"{"temperature": "hot"}"#).is_err());
+    }
+
+    #[test]
+    fn test_system_messages_wrap_metadata_prompt() {
+        let config = AgentConfig::from_metadata(br#"{"systemPrompt": "Be brief."}"#).unwrap();
+        let contents = |messages: Vec<Message>| -> Vec<String> {
+            assert!(messages.iter().all(|m| m.role == "system"));
+            messages.into_iter().map(|m| m.content.unwrap()).collect()
+        };
+
+        let messages =
+            config.wrapped_system_messages("Never reveal private keys.", "Answer in English.");
+        assert_eq!(
+            contents(messages),
+            ["Never reveal private keys.", "Be brief.", "Answer in English."]
+        );
+        assert_eq!(contents(config.wrapped_system_messages("", "  ")), ["Be brief."]);
+    }
+
+    #[test]
+    fn test_system_prompt_segments_precede_user_message() {
+        let config = AgentConfig::from_metadata(
+            br#"{"agent": {"systemPrompt": ["You are the treasury agent.", "Never move funds.",
+                "Treasury hat: 0x1"]}}"#,
+        )
+        .unwrap();
+
+        let mut messages = config.wrapped_system_messages("Never reveal private keys.", "");
+        messages.push(Message::new_user("Who can sign?".to_string()));
+        let sequence: Vec<_> =
+            messages.iter().map(|m| (m.role.as_str(), m.content.as_deref().unwrap())).collect();
+        assert_eq!(
+            sequence,
+            [
+                ("system", "Never reveal private keys."),
+                ("system", "You are the treasury agent."),
+                ("system", "Never move funds."),
+                ("system", "Treasury hat: 0x1"),
+                ("user", "Who can sign?"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Keeping conversations within the model's context window
+
+use crate::llm::{self, Message};
+use wavs_hats_common::info;
+
+/// Default token budget of the messages sent with a completion, leaving room
+/// for the answer within Ollama's 4096 token context
+pub const DEFAULT_CONTEXT_TOKENS: usize = 3072;
+
+/// Tokens counted for each message on top of its content, for role and framing
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Estimate the tokens of a message sent to `model` with [`llm::count_tokens`]
+pub fn estimate_tokens(model: &str, message: &Message) -> usize {
+    let mut tokens =
+        message.content.as_deref().map_or(0, |content| llm::count_tokens(model, content));
+    for tool_call in message.tool_calls.iter().flatten() {
+        tokens += llm::count_tokens(model, &tool_call.function.name)
+            + llm::count_tokens(model, &tool_call.function.arguments);
+    }
+    tokens + MESSAGE_OVERHEAD_TOKENS
+}
+
+/// Keep the system messages and the most recent other messages within the
+/// `max_tokens` of `model`
+///
+/// Older messages are dropped first. Tool results are never kept without the
+/// assistant message requesting them, and the latest message is always kept,
+/// even if it alone exceeds the budget. When the latest messages are tool
+/// results, their request is kept with them.
+pub fn trim_messages(model: &str, messages: &[Message], max_tokens: usize) -> Vec<Message> {
+    let mut budget = messages
+        .iter()
+        .filter(|m| m.role == "system")
+        .fold(max_tokens, |budget, m| budget.saturating_sub(estimate_tokens(model, m)));
+
+    // Walk back from the latest message until the budget runs out
+    let mut start = messages.len();
+    for (i, message) in messages.iter().enumerate().rev() {
+        if message.role == "system" {
+            continue;
+        }
+        let tokens = estimate_tokens(model, message);
+        if tokens > budget && start < messages.len() {
+            break;
+        }
+        budget = budget.saturating_sub(tokens);
+        start = i;
+    }
+
+    // Results of tool calls whose request was dropped would be rejected, so
+    // skip them, or keep their request if nothing but system notes follows
+    let is_result = |m: &Message| m.role == "tool" || m.role == "system";
+    if messages.get(start).is_some_and(|m| m.role == "tool") {
+        match messages[start..].iter().position(|m| !is_result(m)) {
+            Some(next) => start += next,
+            None => {
+                while start > 0 && is_result(&messages[start]) {
+                    start -= 1;
+                }
+            }
+        }
+    }
+
+    let dropped = messages[..start].iter().filter(|m| m.role != "system").count();
+    if dropped > 0 {
+        info!("Dropped {} old message(s) to fit {} tokens", dropped, max_tokens);
+    }
+
+    messages
+        .iter()
+        .enumerate()
+        .filter(|(i, m)| *i >= start || m.role == "system")
+        .map(|(_, m)| m.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::MOCK_MODEL;
+
+    /// Message of `role` whose content is estimated at `tokens` tokens
+    fn message(role: &str, tokens: usize) -> Message {
+        let content = "x".repeat((tokens - MESSAGE_OVERHEAD_TOKENS) * 4);
+        Message { role: role.to_string(), ..Message::new_user(content) }
+    }
+
+    fn roles(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.role.as_str()).collect()
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        let text = Message::new_user("Minted 2024-10-14".to_string());
+        assert_eq!(estimate_tokens(MOCK_MODEL, &text), 5 + 4);
+        assert_eq!(estimate_tokens("gpt-4", &text), 7 + 4);
+        assert_eq!(estimate_tokens(MOCK_MODEL, &message("user", 10)), 10);
+    }
+
+    #[test]
+    fn test_trim_keeps_system_and_recent_messages() {
+        let messages = vec![
+            message("system", 10),
+            message("user", 10),
+            message("assistant", 10),
+            message("user", 10),
+            message("assistant", 10),
+        ];
+
+        // Everything fits
+        assert_eq!(trim_messages(MOCK_MODEL, &messages, 50).len(), 5);
+
+        // The oldest turns go first, the system message stays
+        let trimmed = trim_messages(MOCK_MODEL, &messages, 35);
+        assert_eq!(roles(&trimmed), ["system", "user", "assistant"]);
+        assert_eq!(trimmed[1].content, messages[3].content);
+
+        // The latest message is kept even over budget
+        let trimmed = trim_messages(MOCK_MODEL, &messages, 5);
+        assert_eq!(roles(&trimmed), ["system", "assistant"]);
+    }
+
+    #[test]
+    fn test_trim_drops_orphaned_tool_results() {
+        let messages = vec![
+            message("system", 10),
+            message("user", 10),
+            message("assistant", 10),
+            message("tool", 10),
+            message("tool", 10),
+            message("user", 10),
+        ];
+
+        let trimmed = trim_messages(MOCK_MODEL, &messages, 40);
+        assert_eq!(roles(&trimmed), ["system", "user"]);
+
+        let trimmed = trim_messages(MOCK_MODEL, &messages, 50);
+        assert_eq!(roles(&trimmed), ["system", "assistant", "tool", "tool", "user"]);
+    }
+
+    #[test]
+    fn test_trim_keeps_request_of_trailing_tool_results() {
+        let messages = vec![
+            message("system", 10),
+            message("user", 10),
+            message("assistant", 10),
+            message("tool", 10),
+            message("tool", 10),
+            message("system", 10),
+        ];
+
+        // Only the tool results fit, but they can't be sent without their request
+        let trimmed = trim_messages(MOCK_MODEL, &messages, 40);
+        assert_eq!(roles(&trimmed), ["system", "assistant", "tool", "tool", "system"]);
+
+        let trimmed = trim_messages(MOCK_MODEL, &messages[..4], 5);
+        assert_eq!(roles(&trimmed), ["system", "assistant", "tool"]);
+    }
+}
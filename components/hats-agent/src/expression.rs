@@ -0,0 +1,158 @@
+//! Evaluation of arithmetic expressions such as `(3 + 4) * 2`
+
+/// Deepest nesting of parentheses and unary minus, so a hostile expression
+/// can't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// Evaluate an expression with `+ - * /`, parentheses, unary minus and the
+/// usual operator precedence
+pub fn evaluate(expression: &str) -> Result<f64, String> {
+    let mut parser = Parser { chars: expression.chars().collect(), pos: 0, depth: 0 };
+    let value = parser.expression()?;
+    parser.skip_whitespace();
+    if let Some(c) = parser.peek() {
+        return Err(format!("Unexpected '{}' at position {} in expression", c, parser.pos));
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consume `c` if it is the next non-whitespace character
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<f64, String> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value += self.term()?;
+            } else if self.eat('-') {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// term := factor (('*' | '/') factor)*
+    fn term(&mut self) -> Result<f64, String> {
+        let mut value = self.factor()?;
+        loop {
+            if self.eat('*') {
+                value *= self.factor()?;
+            } else if self.eat('/') {
+                let divisor = self.factor()?;
+                if divisor == 0.0 {
+                    return Err("Cannot divide by zero".to_string());
+                }
+                value /= divisor;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// factor := '-' factor | '(' expression ')' | number
+    fn factor(&mut self) -> Result<f64, String> {
+        if self.depth == MAX_DEPTH {
+            return Err("Expression nested too deeply".to_string());
+        }
+        self.depth += 1;
+        let value = self.nested_factor();
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_factor(&mut self) -> Result<f64, String> {
+        if self.eat('-') {
+            return Ok(-self.factor()?);
+        }
+        if self.eat('(') {
+            let value = self.expression()?;
+            if !self.eat(')') {
+                return Err(format!("Expected ')' at position {} in expression", self.pos));
+            }
+            return Ok(value);
+        }
+        self.number()
+    }
+
+    fn number(&mut self) -> Result<f64, String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            self.pos += 1;
+        }
+
+        if start == self.pos {
+            return match self.peek() {
+                Some(c) => Err(format!("Unexpected '{}' at position {} in expression", c, start)),
+                None => Err("Unexpected end of expression".to_string()),
+            };
+        }
+
+        let literal: String = self.chars[start..self.pos].iter().collect();
+        literal.parse().map_err(|_| format!("Invalid number '{}' in expression", literal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(evaluate("2 + 3 * 4").unwrap(), 14.0);
+        assert_eq!(evaluate("10 - 4 - 3").unwrap(), 3.0);
+        assert_eq!(evaluate("8 / 4 / 2").unwrap(), 1.0);
+        assert_eq!(evaluate("1.5 * 2 - -1").unwrap(), 4.0);
+    }
+
+    #[test]
+    fn test_parentheses() {
+        assert_eq!(evaluate("(3 + 4) * 2").unwrap(), 14.0);
+        assert_eq!(evaluate("-(2 * (1 + 2))").unwrap(), -6.0);
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert_eq!(evaluate("1 / (2 - 2)").unwrap_err(), "Cannot divide by zero");
+        assert_eq!(evaluate("(1 + 2").unwrap_err(), "Expected ')' at position 6 in expression");
+        assert_eq!(evaluate("2 +").unwrap_err(), "Unexpected end of expression");
+        assert_eq!(evaluate("2 $ 3").unwrap_err(), "Unexpected '$' at position 2 in expression");
+        assert_eq!(evaluate("1.2.3").unwrap_err(), "Invalid number '1.2.3' in expression");
+        assert!(evaluate("").is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(evaluate(&nested(MAX_DEPTH - 1)).unwrap(), 1.0);
+        assert_eq!(evaluate(&nested(100_000)).unwrap_err(), "Expression nested too deeply");
+        assert_eq!(evaluate(&"-".repeat(100_000)).unwrap_err(), "Expression nested too deeply");
+    }
+}
@@ -0,0 +1,97 @@
+//! Example tool calls shown to the model before the prompt
+//!
+//! Smaller local models often answer without calling tools until they have
+//! seen a call. With `WAVS_ENV_AGENT_FEW_SHOT`, or `fewShot` in the metadata
+//! of the configuration hat, the conversation opens with an example round
+//! trip for each offered tool, built from the tool's parameter schema.
+
+use crate::llm::{Message, Tool, ToolCall, ToolCallFunction};
+use serde_json::{json, Map, Value};
+
+/// Example conversation calling each of `tools` once
+///
+/// Each example is a request, the assistant's call with placeholder
+/// arguments, a placeholder result and an answer, so the model sees the whole
+/// round trip.
+pub fn examples(tools: &[Tool]) -> Vec<Message> {
+    let mut messages = Vec::new();
+    for (i, tool) in tools.iter().enumerate() {
+        let name = &tool.function.name;
+        let request = match &tool.function.description {
+            Some(description) => format!("Example request: {}", description),
+            None => format!("Example request for the {} tool", name),
+        };
+        let call = ToolCall {
+            id: format!("example_{}", i + 1),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: name.clone(),
+                arguments: example_arguments(tool.function.parameters.as_ref()).to_string(),
+            },
+        };
+        messages.push(Message::new_user(request));
+        messages.push(Message::new_assistant_with_tools(String::new(), vec![call.clone()]));
+        messages.push(Message::new_tool_result(call.id, format!("Example result of {}", name)));
+        messages.push(Message::new_assistant(format!("Example answer using the {} result", name)));
+    }
+    messages
+}
+
+/// Arguments for an object schema, with a placeholder for each required
+/// property, or every property when none are required
+fn example_arguments(schema: Option<&Value>) -> Value {
+    let Some(properties) = schema.and_then(|schema| schema["properties"].as_object()) else {
+        return json!({});
+    };
+    let required: Vec<&str> = schema
+        .and_then(|schema| schema["required"].as_array())
+        .map(|required| required.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let arguments: Map<String, Value> = properties
+        .iter()
+        .filter(|(name, _)| required.is_empty() || required.contains(&name.as_str()))
+        .map(|(name, property)| (name.clone(), example_value(property)))
+        .collect();
+    Value::Object(arguments)
+}
+
+/// Placeholder value of a property schema, its first allowed value if it has any
+fn example_value(schema: &Value) -> Value {
+    if let Some(first) = schema["enum"].as_array().and_then(|values| values.first()) {
+        return first.clone();
+    }
+    match schema["type"].as_str() {
+        Some("integer") | Some("number") => json!(1),
+        Some("boolean") => json!(true),
+        Some("array") => json!([]),
+        Some("object") => example_arguments(Some(schema)),
+        _ => json!("example"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::builders;
+
+    #[test]
+    fn test_examples_follow_tool_schemas() {
+        let messages = examples(&[builders::calculator(), builders::evaluate()]);
+        let roles: Vec<_> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(
+            roles,
+            ["user", "assistant", "tool", "assistant", "user", "assistant", "tool", "assistant"]
+        );
+
+        let call = &messages[1].tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.function.name, "calculator");
+        let arguments: Value = serde_json::from_str(&call.function.arguments).unwrap();
+        assert_eq!(arguments, json!({ "operation": "add", "a": 1, "b": 1 }));
+        assert_eq!(messages[2].tool_call_id.as_deref(), Some(call.id.as_str()));
+
+        let call = &messages[5].tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.function.arguments, r#"{"expression":"example"}"#);
+
+        assert!(examples(&[]).is_empty());
+    }
+}
@@ -1,10 +1,21 @@
+mod agent;
 #[allow(warnings)]
 mod bindings;
-mod evm;
-mod image;
-mod ipfs;
+mod config;
+mod context;
+mod expression;
+mod fewshot;
+// Client modules expose more API than `run` currently uses
+#[allow(dead_code)]
 mod llm;
+mod minting;
+#[allow(dead_code)]
 mod nft;
+mod registry;
+mod sanitize;
+mod selftest;
+mod structured;
+mod tools;
 
 use alloy_sol_macro::sol;
 use alloy_sol_types::SolValue;
@@ -13,16 +24,28 @@ use bindings::{
     wavs::worker::layer_types::{TriggerData, TriggerDataEthContractEvent},
     Guest, TriggerAction,
 };
-use wavs_wasi_chain::decode_event_log_data;
+use std::future::Future;
+use wavs_hats_common::warn;
+use wstd::future::FutureExt;
 use wstd::runtime::block_on;
+use wstd::time::Duration;
 
 // Use the sol! macro to import needed solidity types
 // You can write solidity code in the macro and it will be available in the component
 // Or you can import the types from a solidity file.
 sol!("../../src/interfaces/IHatsAvsTypes.sol");
 
-use crate::llm::{LLMClient, Message};
-use crate::IHatsAvsTypes::{DataWithId, NewTrigger};
+use crate::config::AgentConfig;
+use crate::llm::{LLMClient, Message, Tool};
+use crate::registry::ToolRegistry;
+use crate::tools::ToolContext;
+use crate::IHatsAvsTypes::{
+    AgentResult, AgentResultWithTrace, DataWithId, NewTrigger, ToolCallTrace,
+};
+use alloy_network::Ethereum;
+use alloy_provider::RootProvider;
+use alloy_rpc_types::BlockId;
+use wavs_hats_common::{dry_run, evm, idempotency};
 
 #[derive(Default)]
 pub struct Component;
@@ -30,65 +53,586 @@ pub struct Component;
 impl Guest for Component {
     /// @dev This function is called when a WAVS trigger action is fired.
     fn run(action: TriggerAction) -> std::result::Result<Option<Vec<u8>>, String> {
-        // Decode the trigger event
-        let trigger_info = match action.data {
+        // Decode the trigger event, along with the block on-chain reads are pinned to
+        // and whether the trigger ID seeds sampling
+        let (trigger_info, block, seeded) = match action.data {
             // Fired from an Ethereum contract event.
-            TriggerData::EthContractEvent(TriggerDataEthContractEvent { log, .. }) => {
-                let event: NewTrigger = decode_event_log_data!(log)
-                    .map_err(|e| format!("Failed to decode event log data: {}", e))?;
+            TriggerData::EthContractEvent(TriggerDataEthContractEvent {
+                log,
+                block_height,
+                ..
+            }) => {
+                let event: NewTrigger =
+                    wavs_hats_common::trigger::decode_event(&log.topics, &log.data)?;
 
                 // Decode the trigger info bytes into DataWithId
-                DataWithId::abi_decode(&event._triggerInfo, false)
-                    .map_err(|e| format!("Failed to decode trigger info: {}", e))?
+                let trigger_info = DataWithId::abi_decode(&event._triggerInfo, false)
+                    .map_err(|e| format!("Failed to decode trigger info: {}", e))?;
+                (trigger_info, BlockId::number(block_height), true)
             }
             // Fired from a raw data event (e.g. from a CLI command or from another component).
-            TriggerData::Raw(data) => {
-                let prompt = std::str::from_utf8(&data)
-                    .map_err(|e| format!("Failed to decode prompt from bytes: {}", e))?;
-                DataWithId { triggerId: 0, data: prompt.to_string().into() }
-            }
-            _ => Err("Unsupported trigger data type".to_string())?,
+            // There is no trigger block, so reads use the latest block, and sampling
+            // keeps the fixed seed.
+            TriggerData::Raw(data) => (decode_raw_trigger(&data), BlockId::latest(), false),
+            data => Err(wavs_hats_common::trigger::unsupported(&data))?,
         };
 
+        if let Some(output) = idempotency::cached(env!("CARGO_PKG_NAME"), trigger_info.triggerId) {
+            return Ok(Some(output));
+        }
+
         // The data field contains the actual prompt/message to be processed
-        let prompt = std::str::from_utf8(&trigger_info.data)
-            .map_err(|e| format!("Failed to decode prompt from bytes: {}", e))?;
+        let strict = wavs_hats_common::env::flag("WAVS_ENV_PROMPT_STRICT_UTF8", false);
+        let prompt = decode_prompt(&trigger_info.data, strict)?;
+        let prompt = prompt.as_str();
 
-        // TODO get system prompt and user prompt from hats nfts tokenURI
+        // Chain tools report the error to the model if the provider is unavailable
+        let provider = wavs_hats_common::eth_provider!(&evm::hats_chain())
+            .map_err(|e| warn!("On-chain tools unavailable: {}", e))
+            .ok();
 
-        // Process the prompt using the LLM client
-        let result = block_on(async {
-            let client = LLMClient::new("llama3.2")
+        if selftest::requested(prompt) {
+            return run_selftest(trigger_info.triggerId, provider.as_ref(), block)
+                .map(unless_dry_run);
+        }
+
+        // Process the prompt using the LLM client, executing any requested tools
+        let minting = minting::enabled();
+        let mut messages = Vec::new();
+        let completion = async {
+            let config = AgentConfig::load(provider.as_ref(), block).await?;
+            let mut client = LLMClient::with_fallbacks(&config.model, fallback_models())
                 .map_err(|e| format!("Failed to initialize LLM client: {}", e))?;
-            let messages = vec![Message { role: "user".to_string(), content: prompt.to_string() }];
-            client.chat_completion(&messages).await
+            if let Some(temperature) = config.temperature {
+                client.set_temperature(temperature);
+            }
+            // Reproducible per trigger on every operator, yet varying across triggers
+            if seeded {
+                client.set_seed(trigger_info.triggerId);
+            }
+            if config.output_schema.is_some() {
+                client.set_json_mode(true);
+            }
+            if wavs_hats_common::env::flag("WAVS_ENV_OLLAMA_PRECHECK", false) {
+                client.check_model_available().await?;
+            }
+            let registry = config
+                .tools
+                .as_deref()
+                .map_or_else(ToolRegistry::builtin, ToolRegistry::builtin_allowing);
+            let tools = registry.tools();
+            let few_shot = config.few_shot();
+            messages.extend(config.system_messages());
+            if let Some(schema) = &config.output_schema {
+                messages.push(Message::new_system(structured::instructions(schema)));
+            }
+            messages.extend(opening_messages(&tools, minting, few_shot));
+            messages.push(Message::new_user(sanitize::sanitize_user_prompt(prompt)));
+            let options = agent::AgentOptions::from_env();
+            let window = context::trim_messages(client.model(), &messages, options.context_tokens);
+            let response = client.chat_completion(&window, Some(&tools)).await?;
+            let answer = agent::process_tool_calls(
+                &client,
+                &mut messages,
+                response,
+                &registry,
+                &ToolContext::new(block, provider),
+                &options,
+            )
+            .await?;
+            let answer = match &config.output_schema {
+                Some(schema) => {
+                    structured::checked_answer(
+                        &client,
+                        &mut messages,
+                        answer,
+                        schema,
+                        options.context_tokens,
+                    )
+                    .await?
+                }
+                None => answer,
+            };
+            Ok::<_, String>((answer, config.model, tool_trace(&messages)))
+        };
+
+        // Stop waiting on the model at the soft deadline, rather than being killed at the hard one
+        let deadline = deadline_secs();
+        let timer = deadline.map(|secs| wstd::task::sleep(Duration::from_secs(secs)));
+        let output = match block_on(with_deadline(completion, timer)) {
+            Some(completion) => {
+                let (answer, model, trace) =
+                    completion.map_err(|e| format!("Failed to get chat completion: {}", e))?;
+                if minting {
+                    let decision = minting::MintingDecision::from_answer(&answer)?;
+                    return submit(trigger_info.triggerId, decision.abi_encode());
+                }
+                let answer = truncate_answer(answer, max_result_bytes());
+                encode_output(
+                    trigger_info.triggerId,
+                    answer,
+                    model,
+                    trace,
+                    OutputFormat::from_env(),
+                )
+            }
+            None => {
+                let secs = deadline.unwrap_or_default();
+                warn!("Deadline of {}s reached, cancelling the completion", secs);
+                // A partial answer is no decision, and the minter can't decode one
+                if minting {
+                    return Err(format!("Deadline of {}s reached before a minting decision", secs));
+                }
+                deadline_output(trigger_info.triggerId, &messages, secs)
+            }
+        };
+        submit(trigger_info.triggerId, output)
+    }
+}
+
+/// Messages between the system prompt and the user's prompt: the minting
+/// instructions and the example tool calls of `tools`, when enabled
+fn opening_messages(tools: &[Tool], minting: bool, few_shot: bool) -> Vec<Message> {
+    let mut messages = Vec::new();
+    if minting {
+        messages.push(Message::new_system(minting::INSTRUCTIONS.to_string()));
+    }
+    if few_shot {
+        messages.extend(fewshot::examples(tools));
+    }
+    messages
+}
+
+/// Submit the output of `trigger_id`, storing it for deduplication
+fn submit(trigger_id: u64, output: Vec<u8>) -> Result<Option<Vec<u8>>, String> {
+    let output = unless_dry_run(output);
+    if let Some(output) = &output {
+        idempotency::record(env!("CARGO_PKG_NAME"), trigger_id, output);
+    }
+    Ok(output)
+}
+
+/// `output`, or nothing in a dry run
+///
+/// Outputs aren't marked as simulated, so a dry run only logs them.
+fn unless_dry_run(output: Vec<u8>) -> Option<Vec<u8>> {
+    if dry_run::enabled() {
+        dry_run::log("output", &alloy_primitives::Bytes::from(output));
+        return None;
+    }
+    Some(output)
+}
+
+/// Check the configured model answers, without the agent loop, see [`selftest`]
+///
+/// The status is submitted as the JSON answer of an `AgentResult`, or of a
+/// `DataWithId` for consumers of the legacy output. It is not stored for
+/// deduplication, so every probe sends a fresh request, and a dry run only
+/// logs it. The latency and error are those of each operator's own request,
+/// so the statuses of operators differ and can't be aggregated: read the
+/// status as the report of a single operator.
+fn run_selftest(
+    trigger_id: u64,
+    provider: Option<&RootProvider<Ethereum>>,
+    block: BlockId,
+) -> Result<Vec<u8>, String> {
+    let status = block_on(async {
+        let config = AgentConfig::load(provider, block).await?;
+        let client = LLMClient::new(&config.model)
+            .map_err(|e| format!("Failed to initialize LLM client: {}", e))?;
+        Ok::<_, String>(selftest::run(&client).await)
+    })?;
+    Ok(encode_output(
+        trigger_id,
+        status.to_json(),
+        status.model,
+        Vec::new(),
+        OutputFormat::from_env(),
+    ))
+}
+
+/// Decode raw trigger data, either the `DataWithId` output of another
+/// component or a plain prompt with trigger ID 0
+fn decode_raw_trigger(data: &[u8]) -> DataWithId {
+    DataWithId::abi_decode(data, true)
+        .unwrap_or_else(|_| DataWithId { triggerId: 0, data: data.to_vec().into() })
+}
+
+/// Share of replaced characters above which invalid UTF-8 is taken as binary
+const MAX_REPLACED_SHARE: f64 = 0.1;
+
+/// Decode the prompt bytes of a trigger
+///
+/// Invalid UTF-8 fails when `strict`, set with `WAVS_ENV_PROMPT_STRICT_UTF8`.
+/// Otherwise it is decoded lossily, unless it looks like binary data, with
+/// control characters or many invalid bytes, which is hex encoded instead.
+fn decode_prompt(data: &[u8], strict: bool) -> Result<String, String> {
+    let error = match std::str::from_utf8(data) {
+        Ok(prompt) => return Ok(prompt.to_string()),
+        Err(e) if strict => return Err(format!("Failed to decode prompt from bytes: {}", e)),
+        Err(e) => e,
+    };
+
+    let lossy = String::from_utf8_lossy(data);
+    let chars = lossy.chars().count();
+    let replaced = lossy.chars().filter(|&c| c == char::REPLACEMENT_CHARACTER).count();
+    let control = lossy.chars().any(|c| c.is_control() && !c.is_whitespace());
+    if control || replaced as f64 > chars as f64 * MAX_REPLACED_SHARE {
+        warn!("Prompt is binary data ({}), hex encoding it", error);
+        return Ok(alloy_primitives::hex::encode_prefixed(data));
+    }
+    warn!("Prompt is not valid UTF-8 ({}), replacing the invalid bytes", error);
+    Ok(lossy.into_owned())
+}
+
+/// Models tried in order when the configured model fails, from the comma
+/// separated `WAVS_ENV_FALLBACK_MODELS`
+fn fallback_models() -> Vec<String> {
+    std::env::var("WAVS_ENV_FALLBACK_MODELS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|model| !model.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Soft deadline of a run in seconds, from `WAVS_ENV_DEADLINE_SECS`
+fn deadline_secs() -> Option<u64> {
+    std::env::var("WAVS_ENV_DEADLINE_SECS")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|&secs| secs > 0)
+}
+
+/// Await `completion` until `deadline` fires, returning `None` if it fires first
+///
+/// The completion is dropped when the deadline fires, cancelling any request
+/// in flight.
+async fn with_deadline<F: Future>(
+    completion: F,
+    deadline: Option<impl Future>,
+) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => completion.timeout(deadline).await.ok(),
+        None => Some(completion.await),
+    }
+}
+
+/// Messages after the user's prompt, leaving out the examples before it
+fn since_prompt(messages: &[Message]) -> &[Message] {
+    let start = messages.iter().rposition(|m| m.role == "user").map_or(0, |i| i + 1);
+    &messages[start..]
+}
+
+/// Name and arguments hash of each tool call run for the prompt, in order
+///
+/// Calls dropped past the per-turn cap are removed from the assistant
+/// message, so only executed calls are traced.
+fn tool_trace(messages: &[Message]) -> Vec<ToolCallTrace> {
+    since_prompt(messages)
+        .iter()
+        .filter(|m| m.role == "assistant")
+        .flat_map(|m| m.tool_calls.iter().flatten())
+        .map(|call| ToolCallTrace {
+            name: call.function.name.clone(),
+            argsHash: alloy_primitives::keccak256(call.function.arguments.as_bytes()),
         })
-        .map_err(|e| format!("Failed to get chat completion: {}", e))?;
+        .collect()
+}
 
-        // Return the result encoded as DataWithId
-        let encoded = DataWithId {
-            triggerId: trigger_info.triggerId,
-            data: result.as_bytes().to_vec().into(),
+/// Output of a run cancelled at a deadline of `secs`, as a `DataWithId`
+///
+/// Carries the last content the model wrote on the way to its answer, if it
+/// wrote any, and a timeout error otherwise.
+fn deadline_output(trigger_id: u64, messages: &[Message], secs: u64) -> Vec<u8> {
+    let partial = since_prompt(messages)
+        .iter()
+        .rev()
+        .filter(|m| m.role == "assistant")
+        .find_map(|m| m.content.as_deref().filter(|content| !content.trim().is_empty()));
+    let data = match partial {
+        Some(partial) => {
+            let partial = truncate_answer(partial.to_string(), max_result_bytes());
+            format!("{}\n\n[Stopped at the deadline of {}s]", partial, secs)
         }
-        .abi_encode();
+        None => format!("Error: deadline of {}s reached before the model answered", secs),
+    };
+    DataWithId { triggerId: trigger_id, data: data.into_bytes().into() }.abi_encode()
+}
+
+/// Longest answer submitted, in bytes, unless `WAVS_ENV_MAX_RESULT_BYTES` is set
+const DEFAULT_MAX_RESULT_BYTES: usize = 8192;
 
-        Ok(Some(encoded))
+/// Appended to an answer cut to fit the maximum result size
+const TRUNCATION_MARKER: &str = "…[truncated]";
+
+fn max_result_bytes() -> usize {
+    std::env::var("WAVS_ENV_MAX_RESULT_BYTES")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_MAX_RESULT_BYTES)
+}
+
+/// Cut `answer` to at most `max_bytes`, marker included, on a character boundary
+///
+/// A limit too small for the marker cuts the answer without one.
+fn truncate_answer(answer: String, max_bytes: usize) -> String {
+    if answer.len() <= max_bytes {
+        return answer;
+    }
+
+    let marker = if max_bytes < TRUNCATION_MARKER.len() { "" } else { TRUNCATION_MARKER };
+    let mut end = max_bytes - marker.len();
+    while !answer.is_char_boundary(end) {
+        end -= 1;
+    }
+    warn!("Truncating answer of {} bytes to {} bytes", answer.len(), max_bytes);
+    format!("{}{}", &answer[..end], marker)
+}
+
+/// How the agent's answer is encoded for submission
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// An `AgentResult` with the number of tool calls
+    Result,
+    /// The answer bytes in a `DataWithId`, for consumers of the legacy output
+    Legacy,
+    /// An `AgentResultWithTrace` with the name and arguments hash of each tool call
+    Trace,
+}
+
+impl OutputFormat {
+    /// Format set by `WAVS_ENV_AGENT_LEGACY_OUTPUT` or `WAVS_ENV_AGENT_TRACE_OUTPUT`,
+    /// the legacy output taking precedence
+    fn from_env() -> Self {
+        if wavs_hats_common::env::flag("WAVS_ENV_AGENT_LEGACY_OUTPUT", false) {
+            Self::Legacy
+        } else if wavs_hats_common::env::flag("WAVS_ENV_AGENT_TRACE_OUTPUT", false) {
+            Self::Trace
+        } else {
+            Self::Result
+        }
+    }
+}
+
+/// Encode the answer in `format`, with the tool calls traced for it
+fn encode_output(
+    trigger_id: u64,
+    answer: String,
+    model: String,
+    trace: Vec<ToolCallTrace>,
+    format: OutputFormat,
+) -> Vec<u8> {
+    match format {
+        OutputFormat::Legacy => {
+            DataWithId { triggerId: trigger_id, data: answer.into_bytes().into() }.abi_encode()
+        }
+        OutputFormat::Result => AgentResult {
+            triggerId: trigger_id,
+            answer,
+            model,
+            toolCallsCount: trace.len().try_into().unwrap_or(u32::MAX),
+        }
+        .abi_encode(),
+        OutputFormat::Trace => {
+            AgentResultWithTrace { triggerId: trigger_id, answer, model, trace }.abi_encode()
+        }
     }
 }
 
 export!(Component with_types_in bindings);
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use anyhow::Result;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_raw_trigger_data_with_id() {
+        let encoded =
+            DataWithId { triggerId: 42, data: b"What hats exist?".to_vec().into() }.abi_encode();
+        let trigger_info = decode_raw_trigger(&encoded);
+        assert_eq!(trigger_info.triggerId, 42);
+        assert_eq!(trigger_info.data.as_ref(), b"What hats exist?");
+    }
+
+    #[test]
+    fn test_decode_raw_trigger_plain_prompt() {
+        let trigger_info = decode_raw_trigger(b"What hats exist?");
+        assert_eq!(trigger_info.triggerId, 0);
+        assert_eq!(trigger_info.data.as_ref(), b"What hats exist?");
+
+        // Bytes that are not UTF-8 are still a prompt, decoded by `decode_prompt`
+        assert_eq!(decode_raw_trigger(&[0xff, 0xfe]).data.as_ref(), [0xff, 0xfe]);
+    }
+
+    #[test]
+    fn test_decode_prompt() {
+        assert_eq!(
+            decode_prompt("What hats exist? ✓".as_bytes(), true).unwrap(),
+            "What hats exist? ✓"
+        );
+        assert_eq!(decode_prompt(b"What hats exist?", false).unwrap(), "What hats exist?");
+
+        // A stray byte is replaced, unless decoding is strict
+        let data = b"Who wears hat \xff 1 in the treasury?";
+        assert_eq!(
+            decode_prompt(data, false).unwrap(),
+            "Who wears hat \u{fffd} 1 in the treasury?"
+        );
+        let err = decode_prompt(data, true).unwrap_err();
+        assert!(err.starts_with("Failed to decode prompt from bytes"));
 
-//     // Test helper functions
-//     fn setup_test_component() -> Component {
-//         Component::default()
-//     }
+        // Binary data is hex encoded
+        assert_eq!(decode_prompt(&[0xff, 0xfe, 0x01], false).unwrap(), "0xfffe01");
+        assert_eq!(decode_prompt(b"PNG\x00\xff\x10", false).unwrap(), "0x504e4700ff10");
+    }
 
-//     // fn create_test_trigger() -> TriggerAction {
-//     //     mock_trigger(b"test data")
-//     // }
-// }
+    #[test]
+    fn test_encode_output() {
+        let trace = vec![
+            ToolCallTrace { name: "get_hat".to_string(), argsHash: Default::default() },
+            ToolCallTrace { name: "get_wearers".to_string(), argsHash: Default::default() },
+        ];
+        let encoded = encode_output(
+            7,
+            "Four".to_string(),
+            "llama3.2".to_string(),
+            trace.clone(),
+            OutputFormat::Result,
+        );
+        let result = AgentResult::abi_decode(&encoded, true).unwrap();
+        assert_eq!(result.triggerId, 7);
+        assert_eq!(result.answer, "Four");
+        assert_eq!(result.model, "llama3.2");
+        assert_eq!(result.toolCallsCount, 2);
+
+        let encoded = encode_output(
+            7,
+            "Four".to_string(),
+            "llama3.2".to_string(),
+            trace,
+            OutputFormat::Legacy,
+        );
+        let result = DataWithId::abi_decode(&encoded, true).unwrap();
+        assert_eq!(result.triggerId, 7);
+        assert_eq!(result.data.as_ref(), b"Four");
+    }
+
+    #[test]
+    fn test_trace_output_encodes_tool_calls() {
+        use crate::llm::{ToolCall, ToolCallFunction};
+
+        let call = |name: &str, arguments: &str| ToolCall {
+            id: String::new(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction { name: name.to_string(), arguments: arguments.to_string() },
+        };
+        let messages = vec![
+            Message::new_user("Who wears hat 1?".to_string()),
+            Message::new_assistant_with_tools(
+                String::new(),
+                vec![
+                    call("get_hat", r#"{"hat_id":"1"}"#),
+                    call("get_wearers", r#"{"hat_id":"1"}"#),
+                ],
+            ),
+            Message::new_tool_result("call_0".to_string(), "{}".to_string()),
+            Message::new_tool_result("call_1".to_string(), "[]".to_string()),
+            Message::new_assistant_with_tools(String::new(), vec![call("is_wearer", "{}")]),
+            Message::new_tool_result("call_2".to_string(), "true".to_string()),
+            Message::new_assistant("Alice".to_string()),
+        ];
+
+        let trace = tool_trace(&messages);
+        let encoded = encode_output(
+            7,
+            "Alice".to_string(),
+            "llama3.2".to_string(),
+            trace,
+            OutputFormat::Trace,
+        );
+        let result = AgentResultWithTrace::abi_decode(&encoded, true).unwrap();
+        assert_eq!(result.triggerId, 7);
+        assert_eq!(result.answer, "Alice");
+        let names: Vec<_> = result.trace.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["get_hat", "get_wearers", "is_wearer"]);
+        assert_eq!(result.trace[0].argsHash, alloy_primitives::keccak256(br#"{"hat_id":"1"}"#));
+        assert_eq!(result.trace[2].argsHash, alloy_primitives::keccak256(b"{}"));
+    }
+
+    #[test]
+    fn test_deadline_cancels_pending_completion() {
+        use futures::executor::block_on;
+        use std::future::{pending, ready, Ready};
+
+        let completed = block_on(with_deadline(ready("answer"), Some(pending::<()>())));
+        assert_eq!(completed, Some("answer"));
+        assert_eq!(block_on(with_deadline(ready("answer"), None::<Ready<()>>)), Some("answer"));
+
+        // The completion never finishes, so the deadline fires first
+        let mut messages = vec![Message::new_user("What hats exist?".to_string())];
+        let completion = async {
+            messages.push(Message::new_assistant("Looking up the top hat".to_string()));
+            pending::<()>().await
+        };
+        assert_eq!(block_on(with_deadline(completion, Some(ready(())))), None);
+
+        let output = DataWithId::abi_decode(&deadline_output(7, &messages, 30), true).unwrap();
+        assert_eq!(output.triggerId, 7);
+        assert_eq!(
+            output.data.as_ref(),
+            b"Looking up the top hat\n\n[Stopped at the deadline of 30s]"
+        );
+
+        let output = DataWithId::abi_decode(&deadline_output(7, &messages[..1], 30), true).unwrap();
+        assert_eq!(
+            output.data.as_ref(),
+            b"Error: deadline of 30s reached before the model answered"
+        );
+    }
+
+    #[test]
+    fn test_few_shot_examples_open_conversation() {
+        let tools = vec![tools::builders::calculator()];
+        let messages = opening_messages(&tools, false, true);
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0].role, "user");
+        assert_eq!(messages[1].tool_calls.as_ref().unwrap()[0].function.name, "calculator");
+
+        // The minting instructions stay ahead of the examples
+        let messages = opening_messages(&tools, true, true);
+        assert_eq!(messages[0].content.as_deref(), Some(minting::INSTRUCTIONS));
+        assert_eq!(messages.len(), 5);
+
+        assert!(opening_messages(&tools, false, false).is_empty());
+
+        // Example calls don't count towards the run, and aren't partial answers
+        let mut messages = opening_messages(&tools, false, true);
+        messages.push(Message::new_user("What is 2+2?".to_string()));
+        assert!(since_prompt(&messages).is_empty());
+        let output = DataWithId::abi_decode(&deadline_output(7, &messages, 30), true).unwrap();
+        assert!(output.data.starts_with(b"Error: deadline of 30s reached"));
+        assert!(opening_messages(&tools, true, false).iter().all(|m| m.role == "system"));
+    }
+
+    #[test]
+    fn test_truncate_answer() {
+        assert_eq!(truncate_answer("Four".to_string(), 8192), "Four");
+        let exact = "a".repeat(64);
+        assert_eq!(truncate_answer(exact.clone(), 64), exact);
+
+        // "é" is two bytes, so a cut at an odd offset would split one
+        let answer = "é".repeat(100);
+        let truncated = truncate_answer(answer, 65);
+        assert!(truncated.len() <= 65);
+        assert!(truncated.ends_with(TRUNCATION_MARKER));
+        let kept = truncated.strip_suffix(TRUNCATION_MARKER).unwrap();
+        assert!(!kept.is_empty() && kept.chars().all(|c| c == 'é'));
+        assert_eq!(kept.len(), 65 - TRUNCATION_MARKER.len() - 1);
+
+        // Limits below the marker's length leave no room for it
+        assert_eq!(truncate_answer("é".repeat(100), 5), "éé");
+        assert_eq!(truncate_answer("Four".to_string(), 0), "");
+    }
+}
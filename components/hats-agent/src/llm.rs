@@ -1,28 +1,647 @@
 use anyhow::Result;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::env;
+use std::future::Future;
+use std::time::Duration;
+use wavs_hats_common::{debug, error, warn};
 use wstd::{
-    http::{Client, HeaderValue, IntoBody, Request},
+    http::{
+        body::{BoundedBody, IncomingBody},
+        error::{ErrorVariant, WasiHttpErrorCode as ErrorCode},
+        Client, HeaderName, HeaderValue, IntoBody, Request, Response,
+    },
     io::AsyncRead,
 };
 
 /// Common message structure for chat completions
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Unset fields are left out when serialized, except the content of a message
+/// with tool calls, which is sent as `""` since OpenAI requires a string.
+#[derive(Debug, Clone, Deserialize)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCall>>,
+    pub tool_call_id: Option<String>,
+    pub name: Option<String>,
+}
+
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Wire<'a> {
+            role: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            content: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            tool_calls: Option<&'a [ToolCall]>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            tool_call_id: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            name: Option<&'a str>,
+        }
+
+        Wire {
+            role: &self.role,
+            content: self.content.as_deref().or(self.tool_calls.as_ref().map(|_| "")),
+            tool_calls: self.tool_calls.as_deref(),
+            tool_call_id: self.tool_call_id.as_deref(),
+            name: self.name.as_deref(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl Message {
+    /// Create a new user message
+    pub fn new_user(content: String) -> Self {
+        Self { role: "user".to_string(), content: Some(content), ..Self::empty() }
+    }
+
+    /// Create a new system message
+    pub fn new_system(content: String) -> Self {
+        Self { role: "system".to_string(), content: Some(content), ..Self::empty() }
+    }
+
+    /// Create a new assistant message, such as an example answer
+    pub fn new_assistant(content: String) -> Self {
+        Self { role: "assistant".to_string(), content: Some(content), ..Self::empty() }
+    }
+
+    /// Create an assistant message requesting `tool_calls`
+    pub fn new_assistant_with_tools(content: String, tool_calls: Vec<ToolCall>) -> Self {
+        Self { tool_calls: Some(tool_calls), ..Self::new_assistant(content) }
+    }
+
+    /// Create a message carrying the result of a tool call
+    pub fn new_tool_result(tool_call_id: String, result: impl Into<ToolResult>) -> Self {
+        Self {
+            role: "tool".to_string(),
+            content: Some(result.into().content()),
+            tool_call_id: Some(tool_call_id),
+            ..Self::empty()
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
+            role: String::new(),
+            content: None,
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        }
+    }
+}
+
+/// Tool definition offered to the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tool {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub function: Function,
+}
+
+/// Function signature of a tool, with `parameters` as a JSON schema
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Function {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+    /// JSON schema of the `json` of the tool's results, see [`ToolResult`]
+    ///
+    /// Not sent to providers, whose tool definitions have no output schema.
+    #[serde(default, skip_serializing)]
+    pub output_schema: Option<serde_json::Value>,
+}
+
+/// Result of a tool call, as text, JSON, or both
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolResult {
+    pub text: Option<String>,
+    /// Structured result, described by the tool's `output_schema`
+    pub json: Option<Value>,
+}
+
+impl ToolResult {
+    pub fn json(json: Value) -> Self {
+        Self { text: None, json: Some(json) }
+    }
+
+    /// Content of the tool message, the text followed by the JSON on its own line
+    pub fn content(&self) -> String {
+        let json = self.json.as_ref().map(Value::to_string);
+        match (self.text.as_deref(), json) {
+            (Some(text), Some(json)) => format!("{}\n{}", text, json),
+            (Some(text), None) => text.to_string(),
+            (None, Some(json)) => json,
+            (None, None) => String::new(),
+        }
+    }
+}
+
+impl From<String> for ToolResult {
+    fn from(text: String) -> Self {
+        Self { text: Some(text), json: None }
+    }
+}
+
+/// Tool call requested by the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    // Ollama omits the id and type of tool calls
+    #[serde(default)]
+    pub id: String,
+    #[serde(rename = "type", default = "default_tool_type")]
+    pub tool_type: String,
+    pub function: ToolCallFunction,
+}
+
+/// Name and JSON encoded arguments of a requested tool call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallFunction {
+    pub name: String,
+    // Newer Ollama versions send the arguments as an object
+    #[serde(deserialize_with = "deserialize_arguments")]
+    pub arguments: String,
+}
+
+/// Tool call arguments given either JSON encoded or as a JSON value, kept in
+/// the encoded form the handlers parse
+fn deserialize_arguments<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(arguments) => arguments,
+        arguments => arguments.to_string(),
+    })
+}
+
+fn default_tool_type() -> String {
+    "function".to_string()
+}
+
+/// Longest tool name accepted by the providers
+pub const MAX_TOOL_NAME_LENGTH: usize = 64;
+
+fn is_tool_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Check a tool name against the `^[a-zA-Z0-9_-]{1,64}$` pattern providers enforce
+pub fn validate_tool_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.len() > MAX_TOOL_NAME_LENGTH || !name.chars().all(is_tool_name_char)
+    {
+        return Err(Error::Other(format!(
+            "Invalid tool name '{}': must be 1 to {} letters, digits, '_' or '-'",
+            name, MAX_TOOL_NAME_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+/// Make a valid tool name by replacing invalid characters with `_` and truncating
+pub fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if is_tool_name_char(c) { c } else { '_' })
+        .take(MAX_TOOL_NAME_LENGTH)
+        .collect();
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name
+    }
+}
+
+/// Estimate the tokens `model` splits `text` into, for budgeting prompts
+///
+/// This is an estimate, not the model's tokenizer. OpenAI models approximate
+/// their BPE tokenizer: a word is a token, with another for every eight
+/// letters, digits are grouped in threes and punctuation and non-ASCII
+/// characters count one each. Other models count four characters per token.
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    if Provider::for_model(model) != Provider::OpenAI {
+        return text.chars().count().div_ceil(4);
+    }
+
+    let mut tokens = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphabetic() {
+            let mut len = 1;
+            while chars.next_if(char::is_ascii_alphabetic).is_some() {
+                len += 1;
+            }
+            tokens += 1 + (len - 1) / 8;
+        } else if c.is_ascii_digit() {
+            let mut len: usize = 1;
+            while chars.next_if(char::is_ascii_digit).is_some() {
+                len += 1;
+            }
+            tokens += len.div_ceil(3);
+        } else if c.is_whitespace() {
+            // A single space joins the next word, longer runs are a token of their own
+            let mut len = 1;
+            while chars.next_if(|c| c.is_whitespace()).is_some() {
+                len += 1;
+            }
+            if len > 1 || c != ' ' || chars.peek().is_none() {
+                tokens += 1;
+            }
+        } else {
+            tokens += 1;
+        }
+    }
+    tokens
+}
+
+/// Reject `model` unless it is on the comma separated `allowed` list
+///
+/// Every model is allowed when there is no list, or it is blank.
+pub fn check_model_allowed(model: &str, allowed: Option<&str>) -> Result<(), Error> {
+    let Some(allowed) = allowed.filter(|allowed| !allowed.trim().is_empty()) else {
+        return Ok(());
+    };
+    if allowed.split(',').map(str::trim).any(|name| name == model) {
+        Ok(())
+    } else {
+        Err(Error::Other(format!("model {} not allowed", model)))
+    }
+}
+
+/// Chat backend driving the agent loop, implemented by [`LLMClient`] and by fakes in tests
+pub trait ChatClient {
+    /// Model the completions are requested from, which decides how tokens are counted
+    fn model(&self) -> &str;
+
+    async fn chat_completion(
+        &self,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+    ) -> Result<Message, String>;
+}
+
+/// Model name selecting the offline mock backend
+pub const MOCK_MODEL: &str = "mock";
+
+/// Backend serving a model, which decides the request and response formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    OpenAI,
+    /// Self-hosted server speaking the OpenAI format, such as vLLM, TGI or
+    /// LocalAI, at `WAVS_ENV_OPENAI_COMPAT_URL`
+    OpenAICompatible,
+    Ollama,
+    Anthropic,
+    /// Replays canned responses, see [`LLMClient::mock`]
+    Mock,
+}
+
+impl Provider {
+    /// Infer the provider of a model name, defaulting to a local Ollama, or
+    /// to the OpenAI compatible server when `WAVS_ENV_OPENAI_COMPAT_URL` is set
+    pub fn for_model(model: &str) -> Self {
+        let compatible =
+            env::var("WAVS_ENV_OPENAI_COMPAT_URL").is_ok_and(|url| !url.trim().is_empty());
+        Self::infer(model, compatible)
+    }
+
+    fn infer(model: &str, openai_compatible: bool) -> Self {
+        if model == MOCK_MODEL {
+            Provider::Mock
+        } else if matches!(model, "gpt-3.5-turbo" | "gpt-4") || model.starts_with("text-embedding-")
+        {
+            Provider::OpenAI
+        } else if model.starts_with("claude-") {
+            Provider::Anthropic
+        } else if openai_compatible {
+            Provider::OpenAICompatible
+        } else {
+            Provider::Ollama
+        }
+    }
+
+    /// Whether requests and responses use the OpenAI format
+    pub fn is_openai_format(self) -> bool {
+        matches!(self, Provider::OpenAI | Provider::OpenAICompatible)
+    }
+}
+
+/// Chat completions and embeddings URLs of an OpenAI compatible server at `base_url`,
+/// such as `http://localhost:8000/v1`
+fn openai_compatible_urls(base_url: &str) -> (String, String) {
+    let base_url = base_url.trim().trim_end_matches('/');
+    (format!("{}/chat/completions", base_url), format!("{}/embeddings", base_url))
+}
+
+/// Sampling overrides of a client, each unset value keeping the deterministic default
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SamplingParams {
+    /// Defaults to 0
+    pub temperature: Option<f32>,
+    /// Defaults to 1 for OpenAI and 0.1 for Ollama, and is unset for Anthropic
+    pub top_p: Option<f32>,
+    /// Defaults to 42, Anthropic has no seed
+    pub seed: Option<u64>,
+    /// Defaults to the client's [`MaxTokens`]
+    pub max_tokens: Option<u32>,
+}
+
+/// Which of the offered tools the model must call, see [`LLMClient::set_tool_choice`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolChoice {
+    /// The model decides whether to call tools, the default
+    Auto,
+    /// The model answers without calling tools
+    None,
+    /// The model calls the named tool
+    Function(String),
+}
+
+impl ToolChoice {
+    /// `tool_choice` of an OpenAI request
+    fn openai(&self) -> Value {
+        match self {
+            ToolChoice::Auto => json!("auto"),
+            ToolChoice::None => json!("none"),
+            ToolChoice::Function(name) => {
+                json!({ "type": "function", "function": { "name": name } })
+            }
+        }
+    }
+
+    /// `tool_choice` of an Anthropic request
+    fn anthropic(&self) -> Value {
+        match self {
+            ToolChoice::Auto => json!({ "type": "auto" }),
+            ToolChoice::None => json!({ "type": "none" }),
+            ToolChoice::Function(name) => json!({ "type": "tool", "name": name }),
+        }
+    }
+
+    /// Tools offered to a provider without `tool_choice`, such as Ollama,
+    /// which are only the chosen tool, or none
+    fn narrow(&self, tools: &[Tool]) -> Vec<Tool> {
+        match self {
+            ToolChoice::Auto => tools.to_vec(),
+            ToolChoice::None => Vec::new(),
+            ToolChoice::Function(name) => {
+                tools.iter().filter(|tool| &tool.function.name == name).cloned().collect()
+            }
+        }
+    }
+}
+
+/// Default answer length limits, in tokens
+///
+/// Configured through `WAVS_ENV_MAX_TOKENS`, 100 by default, and
+/// `WAVS_ENV_MAX_TOKENS_TOOLS` for requests offering tools, 1024 by default,
+/// as tool calls need more room than plain answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxTokens {
+    pub plain: u32,
+    pub tools: u32,
+}
+
+impl Default for MaxTokens {
+    fn default() -> Self {
+        Self { plain: 100, tools: 1024 }
+    }
+}
+
+impl MaxTokens {
+    /// Read the limits from the environment, failing on a value that is not a
+    /// positive integer
+    pub fn from_env() -> Result<Self, String> {
+        let defaults = Self::default();
+        let var = |name| env::var(name).ok();
+        Ok(Self {
+            plain: parse_max_tokens("WAVS_ENV_MAX_TOKENS", var("WAVS_ENV_MAX_TOKENS"))?
+                .unwrap_or(defaults.plain),
+            tools: parse_max_tokens("WAVS_ENV_MAX_TOKENS_TOOLS", var("WAVS_ENV_MAX_TOKENS_TOOLS"))?
+                .unwrap_or(defaults.tools),
+        })
+    }
+}
+
+/// Ollama context window used unless `WAVS_ENV_OLLAMA_NUM_CTX` overrides it
+pub const DEFAULT_OLLAMA_NUM_CTX: u32 = 4096;
+
+/// Parse an Ollama context window size, warning and using the default when
+/// `value` is not a positive integer
+fn parse_num_ctx(value: Option<String>) -> u32 {
+    let Some(value) = value else {
+        return DEFAULT_OLLAMA_NUM_CTX;
+    };
+    match value.trim().parse::<u32>() {
+        Ok(num_ctx) if num_ctx > 0 => num_ctx,
+        _ => {
+            warn!("Invalid WAVS_ENV_OLLAMA_NUM_CTX {:?}, using {}", value, DEFAULT_OLLAMA_NUM_CTX);
+            DEFAULT_OLLAMA_NUM_CTX
+        }
+    }
+}
+
+/// Connection attempts Ollama gets again unless `WAVS_ENV_OLLAMA_CONNECT_RETRIES` overrides it
+pub const DEFAULT_OLLAMA_CONNECT_RETRIES: usize = 3;
+
+/// Wait between connection attempts to Ollama, which sends no `Retry-After`
+const OLLAMA_CONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Parse a connection retry count, warning and using the default when `value`
+/// is not a number
+fn parse_connect_retries(value: Option<String>) -> usize {
+    let Some(value) = value else {
+        return DEFAULT_OLLAMA_CONNECT_RETRIES;
+    };
+    value.trim().parse().unwrap_or_else(|_| {
+        warn!(
+            "Invalid WAVS_ENV_OLLAMA_CONNECT_RETRIES {:?}, using {}",
+            value, DEFAULT_OLLAMA_CONNECT_RETRIES
+        );
+        DEFAULT_OLLAMA_CONNECT_RETRIES
+    })
+}
+
+/// Whether a request failed before reaching the server, such as while Ollama
+/// is still starting, rather than with an error status
+fn is_connection_error(error: &wstd::http::Error) -> bool {
+    matches!(
+        error.variant(),
+        ErrorVariant::WasiHttp(
+            ErrorCode::ConnectionRefused
+                | ErrorCode::ConnectionTerminated
+                | ErrorCode::DestinationUnavailable
+        )
+    )
+}
+
+/// Send the request `send` builds, sending it again up to `retries` times
+/// after a fixed `backoff` while the connection fails
+///
+/// Errors building the request and error statuses are returned immediately.
+async fn send_with_connect_retries<F, Fut, T>(
+    retries: usize,
+    backoff: Duration,
+    mut send: F,
+) -> Result<T, String>
+where
+    F: FnMut() -> Result<Fut, String>,
+    Fut: Future<Output = wstd::http::Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match send()?.await {
+            Ok(res) => return Ok(res),
+            Err(e) if attempt < retries && is_connection_error(&e) => {
+                attempt += 1;
+                warn!("Connection failed, retrying ({}/{}): {}", attempt, retries, e);
+                if !backoff.is_zero() {
+                    wstd::task::sleep(backoff.into()).await;
+                }
+            }
+            Err(e) => return Err(format!("Request failed: {}", e)),
+        }
+    }
+}
+
+/// Failure of a single request to a provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RequestError {
+    /// The provider couldn't be reached, or its response couldn't be read
+    Transport(String),
+    /// The provider answered with a `status` other than 200
+    Status { status: u16, message: String },
+    /// The request couldn't be built or the response couldn't be parsed,
+    /// which would fail the same way against any model
+    Local(String),
+}
+
+impl RequestError {
+    /// Whether another model may succeed where this request failed: on
+    /// transport failures, server errors and rate limits
+    fn is_retryable(&self) -> bool {
+        match self {
+            RequestError::Transport(_) => true,
+            RequestError::Status { status, .. } => *status == 429 || *status >= 500,
+            RequestError::Local(_) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Transport(message)
+            | RequestError::Status { message, .. }
+            | RequestError::Local(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<RequestError> for String {
+    fn from(error: RequestError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Run `attempt` with the primary model, `None`, then with each of the
+/// `fallbacks` while the previous model failed with a retryable error
+///
+/// Any other error is returned immediately, as is the last error once every
+/// model failed.
+async fn try_models<T, F, Fut>(
+    primary: &str,
+    fallbacks: &[String],
+    mut attempt: F,
+) -> Result<T, String>
+where
+    F: FnMut(Option<&str>) -> Fut,
+    Fut: Future<Output = Result<T, RequestError>>,
+{
+    let mut result = attempt(None).await;
+    let mut failed = primary;
+    for model in fallbacks {
+        match &result {
+            Err(e) if e.is_retryable() => {
+                warn!("Model {} failed, falling back to {}: {}", failed, model, e)
+            }
+            _ => break,
+        }
+        result = attempt(Some(model)).await;
+        failed = model;
+    }
+    result.map_err(String::from)
+}
+
+/// Names of the headers [`provider_headers`] sends to OpenAI
+const OPENAI_HEADERS: [&str; 2] = ["openai-organization", "openai-project"];
+
+/// Headers a `provider` needs on every request, from the OpenAI organization
+/// and project of `WAVS_ENV_OPENAI_ORG` and `WAVS_ENV_OPENAI_PROJECT`
+///
+/// Other providers get none, so the OpenAI account isn't sent to them.
+fn provider_headers(
+    provider: Provider,
+    org: Option<String>,
+    project: Option<String>,
+) -> Vec<(&'static str, String)> {
+    if provider != Provider::OpenAI {
+        return Vec::new();
+    }
+    OPENAI_HEADERS
+        .into_iter()
+        .zip([org, project])
+        .filter_map(|(name, value)| {
+            let value = value?.trim().to_string();
+            (!value.is_empty()).then_some((name, value))
+        })
+        .collect()
+}
+
+/// Parse the token limit `value` of the variable `name`, if set
+fn parse_max_tokens(name: &str, value: Option<String>) -> Result<Option<u32>, String> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    match value.trim().parse::<u32>() {
+        Ok(limit) if limit > 0 => Ok(Some(limit)),
+        _ => Err(format!("Invalid {} {:?}: expected a positive integer", name, value)),
+    }
 }
 
 /// Client for making LLM API requests
 #[derive(Debug)]
 pub struct LLMClient {
+    provider: Provider,
     model: String,
     api_url: String,
+    embeddings_url: String,
     api_key: Option<String>,
+    json_mode: bool,
+    sampling: SamplingParams,
+    max_tokens: MaxTokens,
+    /// Context window size of Ollama models
+    num_ctx: u32,
+    /// How long Ollama keeps the model loaded after a request, such as `"5m"`
+    keep_alive: Option<String>,
+    /// Attempts repeated when Ollama refuses or resets the connection
+    connect_retries: usize,
+    /// Sent with every request after the standard headers, see [`LLMClient::add_header`]
+    extra_headers: Vec<(HeaderName, HeaderValue)>,
+    /// Sent with requests offering tools, see [`LLMClient::set_tool_choice`]
+    tool_choice: Option<ToolChoice>,
+    /// Models tried in order when a chat completion fails, see [`LLMClient::with_fallbacks`]
+    fallbacks: Vec<String>,
+    /// Canned responses replayed instead of sending requests
+    mock: Option<RefCell<VecDeque<Message>>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     EmptyModelName,
     EmptyMessages,
@@ -31,6 +650,28 @@ pub enum Error {
     Other(String),
 }
 
+/// Category of an [`Error`], for matching without its message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    EmptyModelName,
+    EmptyMessages,
+    InvalidProvider,
+    RequestFailed,
+    Other,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::EmptyModelName => ErrorKind::EmptyModelName,
+            Error::EmptyMessages => ErrorKind::EmptyMessages,
+            Error::InvalidProvider => ErrorKind::InvalidProvider,
+            Error::RequestFailed(_) => ErrorKind::RequestFailed,
+            Error::Other(_) => ErrorKind::Other,
+        }
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -41,227 +682,2132 @@ impl std::fmt::Display for Error {
             Error::Other(msg) => write!(f, "Other error: {}", msg),
         }
     }
-}
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for String {
+    fn from(error: Error) -> Self {
+        error.to_string()
+    }
+}
+
+/// Parse the message of an error back into its variant
+///
+/// Messages not written by [`Error`] become [`Error::Other`] as they are.
+impl From<String> for Error {
+    fn from(error: String) -> Self {
+        match error.as_str() {
+            "Model name cannot be empty" => Error::EmptyModelName,
+            "Messages cannot be empty" => Error::EmptyMessages,
+            "Invalid provider configuration" => Error::InvalidProvider,
+            _ => {
+                if let Some(msg) = error.strip_prefix("Request failed: ") {
+                    Error::RequestFailed(msg.to_string())
+                } else if let Some(msg) = error.strip_prefix("Other error: ") {
+                    Error::Other(msg.to_string())
+                } else {
+                    Error::Other(error)
+                }
+            }
+        }
+    }
+}
+
+impl LLMClient {
+    /// Create a new LLM client for the provider inferred from the model name
+    pub fn new(model: &str) -> Result<Self, String> {
+        Self::with_provider(Provider::for_model(model), model)
+    }
+
+    /// Create a new LLM client for an explicitly selected provider
+    ///
+    /// The [`Provider::Mock`] replays the responses in
+    /// `WAVS_ENV_MOCK_LLM_RESPONSE`, see [`parse_mock_responses`].
+    pub fn with_provider(provider: Provider, model: &str) -> Result<Self, String> {
+        // Validate model name
+        if model.trim().is_empty() {
+            return Err("Model name cannot be empty".to_string());
+        }
+        // Operators can restrict the models a deployed agent may use
+        check_model_allowed(model, env::var("WAVS_ENV_ALLOWED_MODELS").ok().as_deref())?;
+
+        // Set API key and URLs based on the provider
+        let (api_key, api_url, embeddings_url) = match provider {
+            Provider::OpenAI => (
+                Some(wavs_hats_common::env::openai_api_key()?),
+                "https://api.openai.com/v1/chat/completions".to_string(),
+                "https://api.openai.com/v1/embeddings".to_string(),
+            ),
+            Provider::OpenAICompatible => {
+                // Self-hosted servers often run without a key
+                let base_url = wavs_hats_common::env::required_var("WAVS_ENV_OPENAI_COMPAT_URL")?;
+                let (api_url, embeddings_url) = openai_compatible_urls(&base_url);
+                let api_key = env::var("WAVS_ENV_OPENAI_COMPAT_API_KEY")
+                    .ok()
+                    .filter(|api_key| !api_key.trim().is_empty());
+                (api_key, api_url, embeddings_url)
+            }
+            Provider::Anthropic => (
+                Some(wavs_hats_common::env::required_var("WAVS_ENV_ANTHROPIC_API_KEY")?),
+                "https://api.anthropic.com/v1/messages".to_string(),
+                String::new(),
+            ),
+            Provider::Ollama => {
+                // Ollama doesn't need an API key
+                let base_url = env::var("WAVS_ENV_OLLAMA_API_URL")
+                    .unwrap_or_else(|_| "http://localhost:11434".to_string());
+                (None, format!("{}/api/chat", base_url), format!("{}/api/embeddings", base_url))
+            }
+            Provider::Mock => {
+                let responses = match env::var("WAVS_ENV_MOCK_LLM_RESPONSE") {
+                    Ok(raw) => parse_mock_responses(&raw)?,
+                    Err(_) => Vec::new(),
+                };
+                return Ok(Self { model: model.to_string(), ..Self::mock(responses) });
+            }
+        };
+
+        let mut client = Self {
+            provider,
+            model: model.to_string(),
+            api_url,
+            embeddings_url,
+            api_key,
+            json_mode: false,
+            sampling: SamplingParams::default(),
+            max_tokens: MaxTokens::from_env()?,
+            num_ctx: parse_num_ctx(env::var("WAVS_ENV_OLLAMA_NUM_CTX").ok()),
+            keep_alive: env::var("WAVS_ENV_OLLAMA_KEEP_ALIVE")
+                .ok()
+                .map(|keep_alive| keep_alive.trim().to_string())
+                .filter(|keep_alive| !keep_alive.is_empty()),
+            extra_headers: Vec::new(),
+            connect_retries: match provider {
+                Provider::Ollama => {
+                    parse_connect_retries(env::var("WAVS_ENV_OLLAMA_CONNECT_RETRIES").ok())
+                }
+                _ => 0,
+            },
+            tool_choice: None,
+            fallbacks: Vec::new(),
+            mock: None,
+        };
+        let headers = provider_headers(
+            provider,
+            env::var("WAVS_ENV_OPENAI_ORG").ok(),
+            env::var("WAVS_ENV_OPENAI_PROJECT").ok(),
+        );
+        for (name, value) in headers {
+            client.add_header(name, &value)?;
+        }
+        Ok(client)
+    }
+
+    /// Create a client for `primary` that falls back to `fallbacks` in order
+    ///
+    /// When a chat completion fails, the same messages and tools are sent to
+    /// the next model, until one succeeds or the last one fails. Fallback
+    /// clients are created when first needed, with the same JSON mode and
+    /// sampling parameters as the primary.
+    pub fn with_fallbacks(primary: &str, fallbacks: Vec<String>) -> Result<Self, String> {
+        Ok(Self { fallbacks, ..Self::new(primary)? })
+    }
+
+    /// Client answering chat completions with `responses`, without any network
+    ///
+    /// Responses are returned in order, and the last one is repeated once the
+    /// others are consumed.
+    pub fn mock(responses: Vec<Message>) -> Self {
+        Self {
+            provider: Provider::Mock,
+            model: MOCK_MODEL.to_string(),
+            api_url: String::new(),
+            embeddings_url: String::new(),
+            api_key: None,
+            json_mode: false,
+            sampling: SamplingParams::default(),
+            max_tokens: MaxTokens::default(),
+            num_ctx: DEFAULT_OLLAMA_NUM_CTX,
+            keep_alive: None,
+            connect_retries: 0,
+            extra_headers: Vec::new(),
+            tool_choice: None,
+            fallbacks: Vec::new(),
+            mock: Some(RefCell::new(responses.into())),
+        }
+    }
+
+    /// Force the model to respond with a valid JSON object
+    ///
+    /// OpenAI requires the word "json" to appear in at least one message when
+    /// `response_format` is set, which is checked when the request is built.
+    pub fn set_json_mode(&mut self, enabled: bool) {
+        self.json_mode = enabled;
+    }
+
+    /// Override the sampling temperature, 0 by default for deterministic output
+    pub fn set_temperature(&mut self, temperature: f32) {
+        self.sampling.temperature = Some(temperature);
+    }
+
+    /// Override the sampling seed, 42 by default
+    pub fn set_seed(&mut self, seed: u64) {
+        self.sampling.seed = Some(seed);
+    }
+
+    /// Override the sampling parameters sent with every request
+    pub fn set_sampling(&mut self, sampling: SamplingParams) {
+        self.sampling = sampling;
+    }
+
+    /// Set which tool the model must call when tools are offered, or `None`
+    /// to leave it to the provider's default
+    ///
+    /// OpenAI and Anthropic receive it as `tool_choice`. Ollama has no such
+    /// parameter, so it is offered only the chosen tool instead, or no tools
+    /// for [`ToolChoice::None`]. The choice applies to every request, so a
+    /// forced tool is called again on each round of the agent loop.
+    pub fn set_tool_choice(&mut self, tool_choice: Option<ToolChoice>) {
+        self.tool_choice = tool_choice;
+    }
+
+    /// Send the header `name` with every request, such as the routing key of
+    /// a gateway
+    ///
+    /// Extra headers are added after the standard headers and replace a
+    /// standard header of the same name. Invalid names and values are rejected.
+    pub fn add_header(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| format!("Invalid header name {:?}: {}", name, e))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|e| format!("Invalid value of header {}: {}", name, e))?;
+        self.extra_headers.push((name, value));
+        Ok(())
+    }
+
+    /// Provider the requests of this client are sent to
+    pub fn provider(&self) -> Provider {
+        self.provider
+    }
+
+    /// Model the requests of this client ask for
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Build the provider specific request body for a chat completion
+    fn build_request_body(
+        &self,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+    ) -> Result<serde_json::Value, String> {
+        if let (Some(ToolChoice::Function(name)), Some(tools)) = (&self.tool_choice, tools) {
+            if !tools.iter().any(|tool| &tool.function.name == name) {
+                return Err(format!("Tool choice {} is not among the offered tools", name));
+            }
+        }
+        let narrowed = match (&self.tool_choice, self.provider, tools) {
+            (Some(choice), Provider::Ollama | Provider::Mock, Some(tools)) => {
+                Some(choice.narrow(tools))
+            }
+            _ => None,
+        };
+
+        // Tool calls need more room than plain answers
+        let tools = narrowed.as_deref().or(tools).filter(|tools| !tools.is_empty());
+        for tool in tools.unwrap_or_default() {
+            validate_tool_name(&tool.function.name)?;
+        }
+        let max_tokens = self.sampling.max_tokens.unwrap_or(if tools.is_some() {
+            self.max_tokens.tools
+        } else {
+            self.max_tokens.plain
+        });
+        let temperature = self.sampling.temperature.unwrap_or(0.0);
+        let seed = self.sampling.seed.unwrap_or(42);
+
+        // Create request body with deterministic settings
+        let mut body = match self.provider {
+            Provider::OpenAI | Provider::OpenAICompatible => json!({
+                "model": self.model,
+                "messages": messages,
+                "temperature": temperature,
+                "top_p": self.sampling.top_p.unwrap_or(1.0),
+                "seed": seed,
+                "stream": false,
+                "max_tokens": max_tokens  // Limit response length
+            }),
+            Provider::Anthropic => {
+                return build_anthropic_body(
+                    &self.model,
+                    messages,
+                    tools,
+                    &SamplingParams { max_tokens: Some(max_tokens), ..self.sampling.clone() },
+                    self.tool_choice.as_ref(),
+                    self.json_mode,
+                )
+            }
+            // Ollama chat format
+            Provider::Ollama | Provider::Mock => json!({
+                "model": self.model,
+                "messages": messages,
+                "stream": false,
+                "options": {
+                    "temperature": temperature,
+                    "top_p": self.sampling.top_p.unwrap_or(0.1),
+                    "seed": seed,
+                    "num_ctx": self.num_ctx, // Context window size
+                    "num_predict": max_tokens  // Limit response length
+                }
+            }),
+        };
+
+        if let Some(tools) = tools {
+            body["tools"] = json!(tools);
+            if let Some(tool_choice) =
+                self.tool_choice.as_ref().filter(|_| self.provider.is_openai_format())
+            {
+                body["tool_choice"] = tool_choice.openai();
+            }
+        }
+
+        // Unset keeps Ollama's own default
+        if let Some(keep_alive) =
+            self.keep_alive.as_ref().filter(|_| self.provider == Provider::Ollama)
+        {
+            body["keep_alive"] = json!(keep_alive);
+        }
+
+        if self.json_mode {
+            if self.provider.is_openai_format() {
+                let mentions_json = messages.iter().any(|m| {
+                    m.content.as_deref().is_some_and(|c| c.to_lowercase().contains("json"))
+                });
+                if self.provider == Provider::OpenAI && !mentions_json {
+                    return Err(Error::Other(
+                        "JSON mode requires at least one message to mention JSON".to_string(),
+                    )
+                    .into());
+                }
+                body["response_format"] = json!({ "type": "json_object" });
+            } else {
+                body["format"] = json!("json");
+            }
+        }
+
+        Ok(body)
+    }
+
+    /// POST a JSON body to the given endpoint and return the raw response body
+    async fn send_request(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<String, RequestError> {
+        let mut res = self.open_request(url, body).await?;
+
+        // Read response body
+        let mut body_buf = Vec::new();
+        res.body_mut()
+            .read_to_end(&mut body_buf)
+            .await
+            .map_err(|e| RequestError::Transport(format!("Failed to read response body: {}", e)))?;
+
+        String::from_utf8(body_buf)
+            .map_err(|e| RequestError::Local(format!("Invalid UTF-8 in response: {}", e)))
+    }
+
+    /// JSON POST request to `url`, authorized with the client's API key
+    fn build_request(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<Request<BoundedBody<Vec<u8>>>, String> {
+        // Create request
+        let mut req = Request::post(url)
+            .body(serde_json::to_vec(body).unwrap().into_body())
+            .map_err(|e| format!("Failed to create request: {}", e))?;
+
+        // Add headers
+        req.headers_mut().insert("Content-Type", HeaderValue::from_static("application/json"));
+        req.headers_mut().insert("Accept", HeaderValue::from_static("application/json"));
+
+        // Add authorization if needed
+        if let Some(api_key) = &self.api_key {
+            let (name, value) = match self.provider {
+                Provider::Anthropic => ("x-api-key", api_key.clone()),
+                _ => ("Authorization", format!("Bearer {}", api_key)),
+            };
+            let mut value = HeaderValue::from_str(&value)
+                .map_err(|e| format!("Invalid API key format: {}", e))?;
+            value.set_sensitive(true);
+            req.headers_mut().insert(name, value);
+        }
+        if self.provider == Provider::Anthropic {
+            req.headers_mut()
+                .insert("anthropic-version", HeaderValue::from_static(ANTHROPIC_VERSION));
+        }
+        for (name, value) in &self.extra_headers {
+            req.headers_mut().insert(name.clone(), value.clone());
+        }
+        Ok(req)
+    }
+
+    /// POST a JSON body to the given endpoint and return the successful response
+    async fn open_request(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<Response<IncomingBody>, RequestError> {
+        let req = self.build_request(url, body).map_err(RequestError::Local)?;
+        debug!("{}", describe_request(&req, body, log_prompts()));
+
+        // Send request, rebuilding it for each retry
+        let client = Client::new();
+        let mut req = Some(req);
+        let mut res =
+            send_with_connect_retries(self.connect_retries, OLLAMA_CONNECT_BACKOFF, || {
+                let req = match req.take() {
+                    Some(req) => req,
+                    None => self.build_request(url, body)?,
+                };
+                Ok(client.send(req))
+            })
+            .await
+            .map_err(RequestError::Transport)?;
+
+        debug!("Received response with status: {}", res.status());
+
+        if res.status() != 200 {
+            let mut error_body = Vec::new();
+            res.body_mut().read_to_end(&mut error_body).await.map_err(|e| {
+                RequestError::Transport(format!("Failed to read error response: {}", e))
+            })?;
+            let ollama_error = match self.provider {
+                Provider::Ollama | Provider::Mock => ollama_error(&error_body),
+                _ => None,
+            };
+            let error_msg = match ollama_error {
+                Some(error) => Error::RequestFailed(error).to_string(),
+                None => format!(
+                    "API error: status {} - {}",
+                    res.status(),
+                    String::from_utf8_lossy(&error_body)
+                ),
+            };
+            error!("Error: {}", error_msg);
+            return Err(RequestError::Status { status: res.status().as_u16(), message: error_msg });
+        }
+
+        Ok(res)
+    }
+
+    /// Send a chat completion request, optionally offering tools to the model
+    ///
+    /// Returns the assistant message, which carries `tool_calls` when the
+    /// model wants tools executed before answering.
+    pub async fn chat_completion(
+        &self,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+    ) -> Result<Message, String> {
+        let candidates = self.chat_completion_candidates(messages, tools, 1).await?;
+        candidates.into_iter().next().ok_or_else(|| "No response choices returned".to_string())
+    }
+
+    /// Send a chat completion request for `n` candidate messages, with the
+    /// OpenAI `n` parameter
+    ///
+    /// Only OpenAI format providers return more than one candidate. The
+    /// candidates only differ with a temperature above 0, and the seed stays
+    /// fixed so every operator gets the same set.
+    pub async fn chat_completion_candidates(
+        &self,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+        n: u32,
+    ) -> Result<Vec<Message>, String> {
+        self.chat_completion_with_fallbacks(messages, tools, n, |model| self.fallback_client(model))
+            .await
+    }
+
+    /// Request `n` candidates without tools and return the one at the index
+    /// `pick` selects, such as the shortest valid JSON
+    pub async fn chat_completion_best_of(
+        &self,
+        messages: &[Message],
+        n: u32,
+        pick: impl Fn(&[Message]) -> usize,
+    ) -> Result<Message, String> {
+        let mut candidates = self.chat_completion_candidates(messages, None, n).await?;
+        let index = pick(&candidates);
+        if index >= candidates.len() {
+            return Err(format!("Picked candidate {} of only {}", index, candidates.len()));
+        }
+        Ok(candidates.swap_remove(index))
+    }
+
+    /// Client for a fallback `model` with the settings of this client
+    fn fallback_client(&self, model: &str) -> Result<LLMClient, String> {
+        let mut client = LLMClient::new(model)?;
+        client.json_mode = self.json_mode;
+        client.sampling = self.sampling.clone();
+        client.tool_choice = self.tool_choice.clone();
+        // The fallback has the provider headers of its own provider instead
+        client.extra_headers.extend(
+            self.extra_headers
+                .iter()
+                .filter(|(name, _)| !OPENAI_HEADERS.contains(&name.as_str()))
+                .cloned(),
+        );
+        Ok(client)
+    }
+
+    /// Send a chat completion for `n` candidates, retrying with the clients
+    /// `fallback` creates for each fallback model while the previous model
+    /// fails with a provider error, see [`try_models`]
+    async fn chat_completion_with_fallbacks(
+        &self,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+        n: u32,
+        fallback: impl Fn(&str) -> Result<LLMClient, String>,
+    ) -> Result<Vec<Message>, String> {
+        // Validate messages
+        if messages.is_empty() {
+            return Err("Messages cannot be empty".to_string());
+        }
+        if n == 0 {
+            return Err("At least one candidate must be requested".to_string());
+        }
+
+        let fallback = &fallback;
+        try_models(&self.model, &self.fallbacks, |model| {
+            let client = model.map(fallback);
+            async move {
+                match client {
+                    None => self.send_chat_completion(messages, tools, n).await,
+                    Some(Ok(client)) => client.send_chat_completion(messages, tools, n).await,
+                    // A fallback that can't be created is skipped like an unreachable one
+                    Some(Err(e)) => Err(RequestError::Transport(e)),
+                }
+            }
+        })
+        .await
+    }
+
+    /// Send a single chat completion request for `n` candidates to this
+    /// client's model
+    ///
+    /// The mock backend returns the next `n` canned responses.
+    async fn send_chat_completion(
+        &self,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+        n: u32,
+    ) -> Result<Vec<Message>, RequestError> {
+        if let Some(responses) = &self.mock {
+            let mut responses = responses.borrow_mut();
+            return (0..n)
+                .map(|_| {
+                    let response = if responses.len() > 1 { responses.pop_front() } else { None };
+                    response.or_else(|| responses.front().cloned()).ok_or_else(|| {
+                        RequestError::Local("Mock LLM has no responses configured".to_string())
+                    })
+                })
+                .collect();
+        }
+        if n > 1 && !self.provider.is_openai_format() {
+            return Err(RequestError::Local(format!(
+                "{:?} does not support more than one candidate",
+                self.provider
+            )));
+        }
+
+        debug!("Sending chat completion request:");
+        debug!("- Model: {}", self.model);
+        debug!("- Number of messages: {}", messages.len());
+
+        let mut body = self.build_request_body(messages, tools).map_err(RequestError::Local)?;
+        if n > 1 {
+            body["n"] = json!(n);
+        }
+        let body = self.send_request(&self.api_url, &body).await?;
+
+        if log_prompts() {
+            debug!("Raw response: {}", body);
+        } else {
+            debug!("Received response of {} bytes", body.len());
+        }
+
+        // Parse response based on provider
+        let messages = match self.provider {
+            Provider::OpenAI | Provider::OpenAICompatible => parse_openai_choices(&body),
+            Provider::Anthropic => parse_anthropic_response(&body).map(|message| vec![message]),
+            Provider::Ollama | Provider::Mock => {
+                parse_ollama_response(&body).map(|message| vec![message]).map_err(String::from)
+            }
+        }
+        .map_err(RequestError::Local)?;
+
+        for message in &messages {
+            debug!(
+                "Successfully received response of length: {}",
+                message.content.as_deref().unwrap_or_default().len()
+            );
+        }
+        Ok(messages)
+    }
+
+    /// Send a chat completion request without tools and return the text content
+    pub async fn chat_completion_text(&self, messages: &[Message]) -> Result<String, String> {
+        let message = self.chat_completion(messages, None).await?;
+        Ok(message.content.unwrap_or_default())
+    }
+
+    /// Send a chat completion request without tools in JSON mode and
+    /// deserialize the content into `T`
+    ///
+    /// JSON mode is only enabled for this request, see [`LLMClient::set_json_mode`].
+    pub async fn chat_completion_json<T: DeserializeOwned>(
+        &mut self,
+        messages: &[Message],
+    ) -> Result<T, String> {
+        let json_mode = std::mem::replace(&mut self.json_mode, true);
+        let content = self.chat_completion_text(messages).await;
+        self.json_mode = json_mode;
+        parse_json_content(&content?)
+    }
+
+    /// Stream a chat completion without tools, calling `on_token` with each
+    /// piece of content as it arrives, and return the full text
+    pub async fn chat_completion_stream(
+        &self,
+        messages: &[Message],
+        mut on_token: impl FnMut(&str),
+    ) -> Result<String, String> {
+        if messages.is_empty() {
+            return Err("Messages cannot be empty".to_string());
+        }
+
+        if self.provider == Provider::Mock {
+            let text = self.chat_completion_text(messages).await?;
+            on_token(&text);
+            return Ok(text);
+        }
+
+        let mut body = self.build_request_body(messages, None)?;
+        body["stream"] = json!(true);
+
+        let mut res = self.open_request(&self.api_url, &body).await?;
+        let mut stream = StreamParser::new(self.provider);
+        let mut buf = [0; 4096];
+        while !stream.done {
+            let read = res
+                .body_mut()
+                .read(&mut buf)
+                .await
+                .map_err(|e| format!("Failed to read response stream: {}", e))?;
+            if read == 0 {
+                break;
+            }
+            stream.feed(&buf[..read], &mut on_token)?;
+        }
+
+        stream.finish(&mut on_token)
+    }
+
+    /// Build the request bodies for an embeddings call
+    ///
+    /// OpenAI accepts the whole batch in one request, while Ollama's
+    /// `/api/embeddings` endpoint takes a single prompt per request.
+    fn build_embeddings_bodies(&self, input: &[String]) -> Vec<serde_json::Value> {
+        match self.provider {
+            Provider::OpenAI | Provider::OpenAICompatible => {
+                vec![json!({ "model": self.model, "input": input })]
+            }
+            _ => input.iter().map(|text| json!({ "model": self.model, "prompt": text })).collect(),
+        }
+    }
+
+    /// Check that Ollama has this client's model pulled, listing its models
+    /// with `/api/tags`
+    ///
+    /// Fails with a hint to pull the model, rather than the completion failing
+    /// mid-run. Other providers always pass, as do fallback models, which are
+    /// not checked.
+    pub async fn check_model_available(&self) -> Result<(), String> {
+        if self.provider != Provider::Ollama {
+            return Ok(());
+        }
+        let base_url = self.api_url.strip_suffix("/api/chat").unwrap_or(&self.api_url);
+        let body = wavs_hats_common::http::get(&format!("{}/api/tags", base_url)).await?;
+        if parse_ollama_tags(&body)?.iter().any(|name| is_same_model(name, &self.model)) {
+            Ok(())
+        } else {
+            Err(format!("model '{0}' not available; run 'ollama pull {0}'", self.model))
+        }
+    }
+
+    /// Compute embedding vectors for each input, returned in input order
+    pub async fn embeddings(&self, input: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        if input.is_empty() {
+            return Err("Embedding input cannot be empty".to_string());
+        }
+        match self.provider {
+            Provider::Anthropic => {
+                return Err("Embeddings are not supported by Anthropic models".to_string())
+            }
+            Provider::Mock => {
+                return Err("Embeddings are not supported by the mock model".to_string())
+            }
+            Provider::OpenAI | Provider::OpenAICompatible | Provider::Ollama => {}
+        }
+
+        debug!("Sending embeddings request:");
+        debug!("- Model: {}", self.model);
+        debug!("- Number of inputs: {}", input.len());
+
+        let mut responses = Vec::new();
+        for body in self.build_embeddings_bodies(input) {
+            responses.push(self.send_request(&self.embeddings_url, &body).await?);
+        }
+
+        let embeddings = match self.provider {
+            Provider::OpenAI | Provider::OpenAICompatible => {
+                parse_openai_embeddings(&responses[0])?
+            }
+            _ => responses
+                .iter()
+                .map(|body| parse_ollama_embedding(body))
+                .collect::<Result<_, _>>()?,
+        };
+
+        if embeddings.len() != input.len() {
+            return Err(format!(
+                "Expected {} embeddings but received {}",
+                input.len(),
+                embeddings.len()
+            ));
+        }
+
+        Ok(embeddings)
+    }
+}
+
+impl ChatClient for LLMClient {
+    fn model(&self) -> &str {
+        LLMClient::model(self)
+    }
+
+    async fn chat_completion(
+        &self,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+    ) -> Result<Message, String> {
+        LLMClient::chat_completion(self, messages, tools).await
+    }
+}
+
+/// Messages of every choice of an OpenAI chat completion, in order
+fn parse_openai_choices(body: &str) -> Result<Vec<Message>, String> {
+    #[derive(Deserialize)]
+    struct ChatResponse {
+        choices: Vec<Choice>,
+    }
+
+    #[derive(Deserialize)]
+    struct Choice {
+        message: Message,
+    }
+
+    let resp: ChatResponse = serde_json::from_str(body)
+        .map_err(|e| format!("Failed to parse OpenAI response: {}", e))?;
+    if resp.choices.is_empty() {
+        return Err("No response choices returned".to_string());
+    }
+    Ok(resp.choices.into_iter().map(|choice| choice.message).collect())
+}
+
+/// Deserialize the JSON `content` of a completion into `T`
+fn parse_json_content<T: DeserializeOwned>(content: &str) -> Result<T, String> {
+    serde_json::from_str(content.trim()).map_err(|e| {
+        format!(
+            "Failed to deserialize response into {}: {}, content: {}",
+            std::any::type_name::<T>(),
+            e,
+            content
+        )
+    })
+}
+
+/// Incremental parser of streamed chat completions
+///
+/// OpenAI and Anthropic stream server-sent events of `data: {...}` lines,
+/// OpenAI ending with `data: [DONE]` and Anthropic with a `message_stop` event.
+/// Ollama streams one JSON object per line until one has `"done": true`.
+/// Bytes are buffered until a full line arrives, so chunks may split lines
+/// and UTF-8 sequences anywhere.
+struct StreamParser {
+    provider: Provider,
+    buffer: Vec<u8>,
+    text: String,
+    done: bool,
+}
+
+impl StreamParser {
+    fn new(provider: Provider) -> Self {
+        Self { provider, buffer: Vec::new(), text: String::new(), done: false }
+    }
+
+    /// Parse the complete lines of `chunk` and pass their content to `on_token`
+    ///
+    /// Anything after the end of the stream is ignored.
+    fn feed(&mut self, chunk: &[u8], on_token: &mut impl FnMut(&str)) -> Result<(), String> {
+        self.buffer.extend_from_slice(chunk);
+        while !self.done {
+            let Some(end) = self.buffer.iter().position(|&b| b == b'\n') else {
+                break;
+            };
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            self.parse_line(&line, on_token)?;
+        }
+        Ok(())
+    }
+
+    /// Parse a final line without a trailing newline and return the full text
+    fn finish(mut self, on_token: &mut impl FnMut(&str)) -> Result<String, String> {
+        if !self.done {
+            let line = std::mem::take(&mut self.buffer);
+            self.parse_line(&line, on_token)?;
+        }
+        Ok(self.text)
+    }
+
+    fn parse_line(&mut self, line: &[u8], on_token: &mut impl FnMut(&str)) -> Result<(), String> {
+        let line = std::str::from_utf8(line)
+            .map_err(|e| format!("Invalid UTF-8 in response stream: {}", e))?
+            .trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let token = match self.provider {
+            Provider::Ollama | Provider::Mock => {
+                let chunk: Value = serde_json::from_str(line)
+                    .map_err(|e| format!("Failed to parse Ollama stream chunk: {}", e))?;
+                if let Some(error) = chunk["error"].as_str() {
+                    return Err(Error::RequestFailed(error.to_string()).into());
+                }
+                self.done = chunk["done"].as_bool().unwrap_or(false);
+                chunk["message"]["content"].as_str().map(str::to_string)
+            }
+            Provider::OpenAI | Provider::OpenAICompatible | Provider::Anthropic => {
+                // Event names, comments and other SSE fields carry no content
+                let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                    return Ok(());
+                };
+                if data == "[DONE]" {
+                    self.done = true;
+                    return Ok(());
+                }
+                let event: Value = serde_json::from_str(data)
+                    .map_err(|e| format!("Failed to parse stream event: {}", e))?;
+                match event["type"].as_str() {
+                    Some("message_stop") => {
+                        self.done = true;
+                        None
+                    }
+                    Some("error") => {
+                        return Err(format!("Stream error: {}", event["error"]["message"]))
+                    }
+                    Some("content_block_delta") => {
+                        event["delta"]["text"].as_str().map(str::to_string)
+                    }
+                    _ => event["choices"][0]["delta"]["content"].as_str().map(str::to_string),
+                }
+            }
+        };
+
+        if let Some(token) = token.filter(|token| !token.is_empty()) {
+            on_token(&token);
+            self.text.push_str(&token);
+        }
+        Ok(())
+    }
+}
+
+/// Version of the Anthropic Messages API the requests are written against
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Build an Anthropic Messages API body from OpenAI style messages
+///
+/// System messages move to the top level `system` field, tool calls become
+/// `tool_use` blocks, and consecutive tool results are grouped into a single
+/// user message of `tool_result` blocks.
+fn build_anthropic_body(
+    model: &str,
+    messages: &[Message],
+    tools: Option<&[Tool]>,
+    sampling: &SamplingParams,
+    tool_choice: Option<&ToolChoice>,
+    json_mode: bool,
+) -> Result<Value, String> {
+    if json_mode {
+        return Err(
+            Error::Other("JSON mode is not supported by Anthropic models".to_string()).into()
+        );
+    }
+
+    let mut system = Vec::new();
+    let mut turns: Vec<Value> = Vec::new();
+    for message in messages {
+        let content = message.content.clone().unwrap_or_default();
+        match message.role.as_str() {
+            "system" => system.push(content),
+            "tool" => {
+                let block = json!({
+                    "type": "tool_result",
+                    "tool_use_id": message.tool_call_id,
+                    "content": content,
+                });
+                match turns.last_mut() {
+                    Some(last) if last["role"] == "user" && last["content"].is_array() => {
+                        last["content"].as_array_mut().unwrap().push(block)
+                    }
+                    _ => turns.push(json!({ "role": "user", "content": [block] })),
+                }
+            }
+            role => {
+                let tool_calls = message.tool_calls.as_deref().unwrap_or_default();
+                if tool_calls.is_empty() {
+                    turns.push(json!({ "role": role, "content": content }));
+                    continue;
+                }
+
+                let mut blocks = Vec::new();
+                if !content.is_empty() {
+                    blocks.push(json!({ "type": "text", "text": content }));
+                }
+                for tool_call in tool_calls {
+                    let input: Value = serde_json::from_str(&tool_call.function.arguments)
+                        .map_err(|e| format!("Invalid arguments for {}: {}", tool_call.id, e))?;
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": tool_call.id,
+                        "name": tool_call.function.name,
+                        "input": input,
+                    }));
+                }
+                turns.push(json!({ "role": role, "content": blocks }));
+            }
+        }
+    }
+
+    let mut body = json!({
+        "model": model,
+        "messages": turns,
+        "temperature": sampling.temperature.unwrap_or(0.0),
+        "max_tokens": sampling.max_tokens,
+        "stream": false,
+    });
+    if let Some(top_p) = sampling.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if !system.is_empty() {
+        body["system"] = json!(system.join("\n\n"));
+    }
+    if let Some(tools) = tools {
+        body["tools"] = tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "name": tool.function.name,
+                    "description": tool.function.description,
+                    "input_schema": tool.function.parameters.clone()
+                        .unwrap_or_else(|| json!({ "type": "object" })),
+                })
+            })
+            .collect();
+        if let Some(tool_choice) = tool_choice {
+            body["tool_choice"] = tool_choice.anthropic();
+        }
+    }
+    Ok(body)
+}
+
+/// Parse an Anthropic Messages API response into an assistant message
+fn parse_anthropic_response(body: &str) -> Result<Message, String> {
+    #[derive(Deserialize)]
+    struct MessagesResponse {
+        content: Vec<ContentBlock>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum ContentBlock {
+        Text {
+            text: String,
+        },
+        ToolUse {
+            id: String,
+            name: String,
+            input: Value,
+        },
+        #[serde(other)]
+        Other,
+    }
+
+    let resp: MessagesResponse = serde_json::from_str(body)
+        .map_err(|e| format!("Failed to parse Anthropic response: {}", e))?;
+
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+    for block in resp.content {
+        match block {
+            ContentBlock::Text { text: part } => text.push_str(&part),
+            ContentBlock::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                id,
+                tool_type: default_tool_type(),
+                function: ToolCallFunction { name, arguments: input.to_string() },
+            }),
+            ContentBlock::Other => {}
+        }
+    }
+
+    Ok(Message {
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        ..Message::new_assistant(text)
+    })
+}
+
+/// Body of an Ollama `/api/chat` response, which reports failures as `{"error": ...}`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OllamaChatResponse {
+    Success { message: Message },
+    Error { error: String },
+}
+
+/// Parse an Ollama chat response, surfacing the error Ollama reports, such as
+/// a model that isn't pulled, as [`Error::RequestFailed`]
+fn parse_ollama_response(body: &str) -> Result<Message, Error> {
+    let resp: OllamaChatResponse = serde_json::from_str(body)
+        .map_err(|e| Error::Other(format!("Failed to parse Ollama response: {}", e)))?;
+    match resp {
+        OllamaChatResponse::Success { message } => Ok(message),
+        OllamaChatResponse::Error { error } => Err(Error::RequestFailed(error)),
+    }
+}
+
+/// Names of the models listed in an Ollama `/api/tags` response
+fn parse_ollama_tags(body: &[u8]) -> Result<Vec<String>, String> {
+    #[derive(Deserialize)]
+    struct Tags {
+        models: Vec<Model>,
+    }
+
+    #[derive(Deserialize)]
+    struct Model {
+        name: String,
+    }
+
+    let tags: Tags = serde_json::from_slice(body)
+        .map_err(|e| format!("Failed to parse Ollama model list: {}", e))?;
+    Ok(tags.models.into_iter().map(|model| model.name).collect())
+}
+
+/// Whether the Ollama model `name` is `model`, which means its `latest` tag
+/// when it has none
+fn is_same_model(name: &str, model: &str) -> bool {
+    name == model || (!model.contains(':') && name.strip_suffix(":latest") == Some(model))
+}
+
+/// Error Ollama reports in the body of a failed response, if it has one
+fn ollama_error(body: &[u8]) -> Option<String> {
+    match serde_json::from_slice(body).ok()? {
+        OllamaChatResponse::Error { error } => Some(error),
+        OllamaChatResponse::Success { .. } => None,
+    }
+}
+
+/// Whether logs may include prompts and answers, opted into with `WAVS_ENV_LOG_PROMPTS`
+fn log_prompts() -> bool {
+    wavs_hats_common::env::flag("WAVS_ENV_LOG_PROMPTS", false)
+}
+
+/// Headers whose values are never logged
+const SENSITIVE_HEADERS: [&str; 2] = ["authorization", "x-api-key"];
+
+/// Request body fields carrying prompts, answers or tool arguments
+const PROMPT_FIELDS: [&str; 5] = ["content", "system", "prompt", "input", "arguments"];
+
+/// Log line for a request, with credentials masked and prompts unless `include_prompts`
+fn describe_request<B>(req: &Request<B>, body: &Value, include_prompts: bool) -> String {
+    let headers: Vec<String> = req
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = if value.is_sensitive() || SENSITIVE_HEADERS.contains(&name.as_str()) {
+                "[redacted]".into()
+            } else {
+                String::from_utf8_lossy(value.as_bytes())
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect();
+    let mut body = body.clone();
+    if !include_prompts {
+        redact_prompts(&mut body);
+    }
+    format!("{} {} [{}] {}", req.method(), req.uri(), headers.join(", "), body)
+}
+
+/// Replace prompt fields of a request body with their size
+fn redact_prompts(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, field) in object.iter_mut() {
+                if PROMPT_FIELDS.contains(&key.as_str()) && !field.is_null() {
+                    *field = json!(format!("[redacted {} bytes]", field.to_string().len()));
+                } else {
+                    redact_prompts(field);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_prompts),
+        _ => {}
+    }
+}
+
+/// Send a single prompt to an Ollama model and return the answer text
+pub async fn query_ollama(model: &str, prompt: &str) -> Result<String, String> {
+    let client = LLMClient::new(model)?;
+    client.chat_completion_text(&[Message::new_user(prompt.to_string())]).await
+}
+
+/// Parse canned mock responses
+///
+/// Accepts a JSON array of responses, a single JSON message, or plain text used
+/// as the content of the answer. Each response is either a string or a message
+/// object, whose `role` defaults to `assistant`, so canned tool calls can be
+/// written as `{"tool_calls":[{"id":"call_1","function":{...}}]}`.
+pub fn parse_mock_responses(raw: &str) -> Result<Vec<Message>, String> {
+    fn parse_response(value: Value) -> Result<Message, String> {
+        match value {
+            Value::String(content) => Ok(Message::new_assistant(content)),
+            Value::Object(mut object) => {
+                object.entry("role").or_insert_with(|| json!("assistant"));
+                serde_json::from_value(Value::Object(object))
+                    .map_err(|e| format!("Invalid mock LLM response: {}", e))
+            }
+            other => Err(format!("Invalid mock LLM response: {}", other)),
+        }
+    }
+
+    match serde_json::from_str(raw) {
+        Ok(Value::Array(values)) => values.into_iter().map(parse_response).collect(),
+        Ok(value @ Value::Object(_)) => Ok(vec![parse_response(value)?]),
+        _ => Ok(vec![parse_response(Value::String(raw.to_string()))?]),
+    }
+}
+
+/// Parse an OpenAI embeddings response, ordering vectors by their `index`
+fn parse_openai_embeddings(body: &str) -> Result<Vec<Vec<f32>>, String> {
+    #[derive(Deserialize)]
+    struct EmbeddingsResponse {
+        data: Vec<EmbeddingData>,
+    }
+
+    #[derive(Deserialize)]
+    struct EmbeddingData {
+        embedding: Vec<f32>,
+        index: usize,
+    }
+
+    let mut resp: EmbeddingsResponse = serde_json::from_str(body)
+        .map_err(|e| format!("Failed to parse OpenAI embeddings response: {}", e))?;
+
+    resp.data.sort_by_key(|data| data.index);
+    Ok(resp.data.into_iter().map(|data| data.embedding).collect())
+}
+
+/// Parse a single Ollama `/api/embeddings` response
+fn parse_ollama_embedding(body: &str) -> Result<Vec<f32>, String> {
+    #[derive(Deserialize)]
+    struct OllamaEmbeddingResponse {
+        embedding: Vec<f32>,
+    }
+
+    let resp: OllamaEmbeddingResponse = serde_json::from_str(body)
+        .map_err(|e| format!("Failed to parse Ollama embeddings response: {}", e))?;
+
+    Ok(resp.embedding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wstd::runtime::block_on;
+
+    fn setup_test_env() {
+        env::set_var("WAVS_ENV_OLLAMA_API_URL", "http://localhost:11434");
+    }
+
+    fn openai_test_client(model: &str) -> LLMClient {
+        env::set_var("WAVS_ENV_OPENAI_API_KEY", "test-key");
+        LLMClient::new(model).unwrap()
+    }
+
+    // Unit tests that don't require HTTP requests
+    #[test]
+    fn test_llm_client_initialization() {
+        setup_test_env();
+
+        let client = LLMClient::new("llama3.2");
+        assert!(client.is_ok());
+        let client = client.unwrap();
+        assert_eq!(client.model, "llama3.2");
+        assert!(client.api_url.contains("localhost:11434"));
+        assert!(client.api_url.contains("/api/chat"));
+    }
+
+    #[test]
+    fn test_new_client_empty_model() {
+        let result = LLMClient::new("");
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), "Model name cannot be empty");
+
+        let result = LLMClient::new("   ");
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), "Model name cannot be empty");
+    }
+
+    #[test]
+    fn test_fallback_model_answers_when_primary_fails() {
+        use std::future::ready;
+
+        let fallbacks = ["unreachable".to_string(), "fallback".to_string()];
+        let tried = RefCell::new(Vec::new());
+        let result = block_on(try_models("primary", &fallbacks, |model| {
+            tried.borrow_mut().push(model.unwrap_or("primary").to_string());
+            ready(match model {
+                None => Err(RequestError::Status {
+                    status: 503,
+                    message: "API error: status 503".to_string(),
+                }),
+                Some("unreachable") => {
+                    Err(RequestError::Transport("unreachable is down".to_string()))
+                }
+                Some(model) => Ok(model.to_string()),
+            })
+        }));
+        assert_eq!(result.unwrap(), "fallback");
+        assert_eq!(*tried.borrow(), ["primary", "unreachable", "fallback"]);
+
+        // Rate limits fall back too, and the last error is returned once every model failed
+        let result = block_on(try_models("primary", &fallbacks[..1], |model| {
+            ready(Err::<(), _>(match model {
+                None => RequestError::Status { status: 429, message: "rate limited".to_string() },
+                Some(model) => RequestError::Transport(format!("{} is down", model)),
+            }))
+        }));
+        assert_eq!(result.unwrap_err(), "unreachable is down");
+    }
+
+    #[test]
+    fn test_validation_error_does_not_fall_back() {
+        let tried = RefCell::new(Vec::new());
+        let fallback = |model: &str| {
+            tried.borrow_mut().push(model.to_string());
+            Ok(LLMClient::mock(parse_mock_responses("from fallback").unwrap()))
+        };
+        let fallbacks = vec!["fallback".to_string()];
+
+        // JSON mode without a message mentioning JSON fails the same on any model
+        let mut client = LLMClient { fallbacks: fallbacks.clone(), ..openai_test_client("gpt-4") };
+        client.set_json_mode(true);
+        let messages = [Message::new_user("What is 2+2?".to_string())];
+        let result = block_on(client.chat_completion_with_fallbacks(&messages, None, 1, fallback));
+        assert!(result.unwrap_err().contains("JSON mode requires"));
+
+        let client = LLMClient { fallbacks, ..LLMClient::mock(Vec::new()) };
+        let result = block_on(client.chat_completion_with_fallbacks(&messages, None, 1, fallback));
+        assert_eq!(result.unwrap_err(), "Mock LLM has no responses configured");
+        assert!(tried.borrow().is_empty());
+
+        // Client errors other than rate limits are returned as they are
+        let error = RequestError::Status { status: 400, message: "bad request".to_string() };
+        assert!(!error.is_retryable());
+    }
+
+    #[test]
+    fn test_error_kind_and_string_round_trip() {
+        for (error, kind) in [
+            (Error::EmptyModelName, ErrorKind::EmptyModelName),
+            (Error::EmptyMessages, ErrorKind::EmptyMessages),
+            (Error::InvalidProvider, ErrorKind::InvalidProvider),
+            (Error::RequestFailed("HTTP 500".to_string()), ErrorKind::RequestFailed),
+            (Error::Other("no answer".to_string()), ErrorKind::Other),
+        ] {
+            assert_eq!(error.kind(), kind);
+            let message = String::from(error.clone());
+            assert_eq!(Error::from(message), error);
+        }
+
+        assert_eq!(
+            String::from(Error::RequestFailed("HTTP 500".to_string())),
+            "Request failed: HTTP 500"
+        );
+        assert_eq!(Error::from("timed out".to_string()), Error::Other("timed out".to_string()));
+    }
 
-impl std::error::Error for Error {}
+    #[test]
+    fn test_connection_errors_are_retried() {
+        // Ollama still starting refuses the first connection
+        let mut attempts = 0;
+        let result = block_on(send_with_connect_retries(2, Duration::ZERO, || {
+            attempts += 1;
+            let result =
+                if attempts == 1 { Err(ErrorCode::ConnectionRefused.into()) } else { Ok("ok") };
+            Ok(async move { result })
+        }));
+        assert_eq!(result.unwrap(), "ok");
+        assert_eq!(attempts, 2);
 
-impl From<Error> for String {
-    fn from(error: Error) -> Self {
-        error.to_string()
+        // Other failures are not retried
+        let mut attempts = 0;
+        let result = block_on(send_with_connect_retries(2, Duration::ZERO, || {
+            attempts += 1;
+            Ok(async { Err::<(), _>(ErrorCode::HttpRequestDenied.into()) })
+        }));
+        assert!(result.unwrap_err().starts_with("Request failed: "));
+        assert_eq!(attempts, 1);
+
+        // Retries run out
+        let mut attempts = 0;
+        let result = block_on(send_with_connect_retries(2, Duration::ZERO, || {
+            attempts += 1;
+            Ok(async { Err::<(), _>(ErrorCode::ConnectionTerminated.into()) })
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        assert_eq!(parse_connect_retries(None), DEFAULT_OLLAMA_CONNECT_RETRIES);
+        assert_eq!(parse_connect_retries(Some("0".to_string())), 0);
+        assert_eq!(parse_connect_retries(Some("x".to_string())), DEFAULT_OLLAMA_CONNECT_RETRIES);
     }
-}
 
-impl From<String> for Error {
-    fn from(error: String) -> Self {
-        Error::Other(error)
+    #[test]
+    fn test_count_tokens() {
+        // Within one token of the OpenAI tokenizer
+        for (text, expected) in [
+            ("Hello, world!", 4),
+            ("hello world", 2),
+            ("The quick brown fox jumps over the lazy dog.", 10),
+            ("1234567890", 4),
+        ] {
+            let tokens = count_tokens("gpt-4", text);
+            assert!(tokens.abs_diff(expected) <= 1, "{:?}: {} tokens", text, tokens);
+        }
+        assert_eq!(count_tokens("gpt-3.5-turbo", ""), 0);
+
+        // Other models count four characters per token
+        assert_eq!(count_tokens("llama3.2", "Hello, world!"), 4);
+        assert_eq!(count_tokens("llama3.2", ""), 0);
     }
-}
 
-fn get_required_var(name: &str) -> Result<String, String> {
-    std::env::var(name).map_err(|e| format!("Missing required variable {}: {}", name, e))
-}
+    #[test]
+    fn test_check_model_allowed() {
+        let allowed = Some("llama3.2, gpt-4");
+        assert!(check_model_allowed("gpt-4", allowed).is_ok());
+        assert!(check_model_allowed("llama3.2", allowed).is_ok());
+        assert_eq!(
+            String::from(check_model_allowed("claude-3-opus", allowed).unwrap_err()),
+            "Other error: model claude-3-opus not allowed"
+        );
+        // Without a list every model is allowed
+        assert!(check_model_allowed("claude-3-opus", None).is_ok());
+        assert!(check_model_allowed("claude-3-opus", Some(" ")).is_ok());
+    }
 
-impl LLMClient {
-    /// Create a new LLM client
-    pub fn new(model: &str) -> Result<Self, String> {
-        // Validate model name
-        if model.trim().is_empty() {
-            return Err("Model name cannot be empty".to_string());
+    #[test]
+    fn test_multiple_system_messages_request_body() {
+        let messages = vec![
+            Message::new_system("Persona".to_string()),
+            Message::new_system("Rules".to_string()),
+            Message::new_user("What is 2+2?".to_string()),
+        ];
+        for client in [LLMClient::new("llama3.2").unwrap(), openai_test_client("gpt-4")] {
+            let body = client.build_request_body(&messages, None).unwrap();
+            let roles: Vec<_> = body["messages"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|m| (m["role"].as_str().unwrap(), m["content"].as_str().unwrap()))
+                .collect();
+            assert_eq!(
+                roles,
+                [("system", "Persona"), ("system", "Rules"), ("user", "What is 2+2?")]
+            );
         }
+    }
 
-        // Get API key if using OpenAI models
-        let api_key = match model {
-            "gpt-3.5-turbo" | "gpt-4" => Some(get_required_var("WAVS_ENV_OPENAI_API_KEY")?),
-            _ => None, // Ollama doesn't need an API key
-        };
+    #[test]
+    fn test_json_mode_request_body() {
+        let messages = vec![Message::new_user("Reply in JSON".to_string())];
 
-        // Set API URL based on model type
-        let api_url = match model {
-            "gpt-3.5-turbo" | "gpt-4" => "https://api.openai.com/v1/chat/completions".to_string(),
-            _ => format!(
-                "{}/api/chat",
-                env::var("WAVS_ENV_OLLAMA_API_URL")
-                    .unwrap_or_else(|_| "http://localhost:11434".to_string())
-            ),
-        };
+        let mut client = LLMClient::new("llama3.2").unwrap();
+        client.set_json_mode(true);
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["format"], "json");
+        assert!(body.get("response_format").is_none());
 
-        Ok(Self { model: model.to_string(), api_url, api_key })
+        let mut client = openai_test_client("gpt-4");
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert!(body.get("response_format").is_none());
+
+        client.set_json_mode(true);
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["response_format"]["type"], "json_object");
+        assert!(body.get("format").is_none());
+
+        let messages = vec![Message::new_user("What is 2+2?".to_string())];
+        let result = client.build_request_body(&messages, None);
+        assert!(result.unwrap_err().contains("JSON mode requires"));
     }
 
-    /// Send a chat completion request
-    pub async fn chat_completion(&self, messages: &[Message]) -> Result<String, String> {
-        // Validate messages
-        if messages.is_empty() {
-            return Err("Messages cannot be empty".to_string());
-        }
+    #[test]
+    fn test_temperature_request_body() {
+        let messages = vec![Message::new_user("What is 2+2?".to_string())];
 
-        println!("Sending chat completion request:");
-        println!("- Model: {}", self.model);
-        println!("- Number of messages: {}", messages.len());
-        println!("- First message: {:?}", messages.first());
+        let mut client = LLMClient::new("llama3.2").unwrap();
+        assert_eq!(
+            client.build_request_body(&messages, None).unwrap()["options"]["temperature"],
+            0.0
+        );
+        client.set_temperature(0.5);
+        assert_eq!(
+            client.build_request_body(&messages, None).unwrap()["options"]["temperature"],
+            0.5
+        );
 
-        // Create request body with deterministic settings
-        let body = if self.api_key.is_some() {
-            // OpenAI format
-            json!({
-                "model": self.model,
-                "messages": messages,
-                "temperature": 0.0,
-                "top_p": 1.0,
-                "seed": 42,
-                "stream": false,
-                "max_tokens": 100  // Limit response length
-            })
-        } else {
-            // Ollama chat format
-            json!({
-                "model": self.model,
-                "messages": messages,
-                "stream": false,
-                "options": {
-                    "temperature": 0.0,
-                    "top_p": 0.1,
-                    "seed": 42,
-                    "num_ctx": 4096, // Context window size
-                    "num_predict": 100  // Limit response length
-                }
-            })
+        let mut client = openai_test_client("gpt-4");
+        client.set_temperature(0.5);
+        assert_eq!(client.build_request_body(&messages, None).unwrap()["temperature"], 0.5);
+    }
+
+    #[test]
+    fn test_sampling_request_body() {
+        let messages = vec![Message::new_user("What is 2+2?".to_string())];
+        let sampling = SamplingParams {
+            temperature: Some(0.3),
+            top_p: Some(0.9),
+            seed: Some(7),
+            max_tokens: Some(256),
         };
 
-        println!("Request body: {}", serde_json::to_string_pretty(&body).unwrap());
+        let mut client = LLMClient::new("llama3.2").unwrap();
+        let options = client.build_request_body(&messages, None).unwrap()["options"].clone();
+        assert_eq!(options["top_p"].as_f64(), Some(0.1f32 as f64));
+        assert_eq!(options["seed"], 42);
+        client.set_sampling(sampling.clone());
+        let options = client.build_request_body(&messages, None).unwrap()["options"].clone();
+        assert_eq!(options["temperature"].as_f64(), Some(0.3f32 as f64));
+        assert_eq!(options["top_p"].as_f64(), Some(0.9f32 as f64));
+        assert_eq!(options["seed"], 7);
+        assert_eq!(options["num_predict"], 256);
 
-        // Create request
-        let mut req = Request::post(&self.api_url)
-            .body(serde_json::to_vec(&body).unwrap().into_body())
-            .map_err(|e| format!("Failed to create request: {}", e))?;
+        let mut client = openai_test_client("gpt-4");
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["top_p"], 1.0);
+        assert_eq!(body["seed"], 42);
+        client.set_sampling(sampling.clone());
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["temperature"].as_f64(), Some(0.3f32 as f64));
+        assert_eq!(body["top_p"].as_f64(), Some(0.9f32 as f64));
+        assert_eq!(body["seed"], 7);
+        assert_eq!(body["max_tokens"], 256);
 
-        // Add headers
-        req.headers_mut().insert("Content-Type", HeaderValue::from_static("application/json"));
-        req.headers_mut().insert("Accept", HeaderValue::from_static("application/json"));
+        env::set_var("WAVS_ENV_ANTHROPIC_API_KEY", "test-key");
+        let mut client = LLMClient::new("claude-3-5-haiku-latest").unwrap();
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert!(body.get("top_p").is_none());
+        assert_eq!(body["max_tokens"], 100);
+        client.set_sampling(sampling);
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["top_p"].as_f64(), Some(0.9f32 as f64));
+        assert_eq!(body["max_tokens"], 256);
+        assert!(body.get("seed").is_none());
+    }
 
-        // Add authorization if needed
-        if let Some(api_key) = &self.api_key {
-            req.headers_mut().insert(
-                "Authorization",
-                HeaderValue::from_str(&format!("Bearer {}", api_key))
-                    .map_err(|e| format!("Invalid API key format: {}", e))?,
-            );
-        }
+    #[test]
+    fn test_seed_request_body() {
+        let messages = vec![Message::new_user("What is 2+2?".to_string())];
+        let trigger_id = 1234u64;
 
-        println!("Sending request to: {}", req.uri());
+        let mut client = LLMClient::new("llama3.2").unwrap();
+        client.set_seed(trigger_id);
+        let options = client.build_request_body(&messages, None).unwrap()["options"].clone();
+        assert_eq!(options["seed"], trigger_id);
 
-        // Send request
-        let mut res =
-            Client::new().send(req).await.map_err(|e| format!("Request failed: {}", e))?;
+        let mut client = openai_test_client("gpt-4");
+        client.set_seed(trigger_id);
+        assert_eq!(client.build_request_body(&messages, None).unwrap()["seed"], trigger_id);
+        assert_eq!(client.fallback_client("gpt-4o").unwrap().sampling.seed, Some(trigger_id));
+    }
 
-        println!("Received response with status: {}", res.status());
+    #[test]
+    fn test_max_tokens_request_body() {
+        let messages = vec![Message::new_user("What is 2+2?".to_string())];
+        let tools = [Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: "calculator".to_string(),
+                description: None,
+                parameters: None,
+                output_schema: None,
+            },
+        }];
+        let max_tokens = MaxTokens { plain: 500, tools: 2000 };
 
-        if res.status() != 200 {
-            let mut error_body = Vec::new();
-            res.body_mut()
-                .read_to_end(&mut error_body)
-                .await
-                .map_err(|e| format!("Failed to read error response: {}", e))?;
-            let error_msg = format!(
-                "API error: status {} - {}",
-                res.status(),
-                String::from_utf8_lossy(&error_body)
+        let client = LLMClient { max_tokens, ..LLMClient::new("llama3.2").unwrap() };
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["options"]["num_predict"], 500);
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert_eq!(body["options"]["num_predict"], 2000);
+
+        let client = LLMClient { max_tokens, ..openai_test_client("gpt-4") };
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["max_tokens"], 500);
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert_eq!(body["max_tokens"], 2000);
+    }
+
+    #[test]
+    fn test_ollama_num_ctx() {
+        let messages = vec![Message::new_user("What is 2+2?".to_string())];
+        let client = LLMClient::new("llama3.2").unwrap();
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["options"]["num_ctx"], DEFAULT_OLLAMA_NUM_CTX);
+
+        let client = LLMClient { num_ctx: parse_num_ctx(Some("32768".to_string())), ..client };
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["options"]["num_ctx"], 32768);
+
+        for value in ["0", "big"] {
+            assert_eq!(parse_num_ctx(Some(value.to_string())), DEFAULT_OLLAMA_NUM_CTX);
+        }
+        assert_eq!(parse_num_ctx(None), DEFAULT_OLLAMA_NUM_CTX);
+    }
+
+    #[test]
+    fn test_ollama_keep_alive() {
+        let messages = vec![Message::new_user("What is 2+2?".to_string())];
+        let client = LLMClient { keep_alive: None, ..LLMClient::new("llama3.2").unwrap() };
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert!(body.get("keep_alive").is_none());
+
+        let client = LLMClient { keep_alive: Some("5m".to_string()), ..client };
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["keep_alive"], "5m");
+    }
+
+    #[test]
+    fn test_parse_max_tokens() {
+        let parse = |value: &str| parse_max_tokens("WAVS_ENV_MAX_TOKENS", Some(value.to_string()));
+        assert_eq!(parse(" 512 "), Ok(Some(512)));
+        assert_eq!(parse_max_tokens("WAVS_ENV_MAX_TOKENS", None), Ok(None));
+        for value in ["0", "-5", "many"] {
+            assert_eq!(
+                parse(value).unwrap_err(),
+                format!("Invalid WAVS_ENV_MAX_TOKENS {:?}: expected a positive integer", value)
             );
-            println!("Error: {}", error_msg);
-            return Err(error_msg);
         }
+    }
 
-        // Read response body
-        let mut body_buf = Vec::new();
-        res.body_mut()
-            .read_to_end(&mut body_buf)
-            .await
-            .map_err(|e| format!("Failed to read response body: {}", e))?;
+    #[test]
+    fn test_tools_request_body() {
+        let messages = vec![Message::new_user("What is 2+2?".to_string())];
+        let tools = vec![Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: "calculator".to_string(),
+                description: None,
+                parameters: Some(json!({ "type": "object" })),
+                output_schema: None,
+            },
+        }];
 
-        let body =
-            String::from_utf8(body_buf).map_err(|e| format!("Invalid UTF-8 in response: {}", e))?;
+        let client = LLMClient::new("llama3.2").unwrap();
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert!(body.get("tools").is_none());
+        assert_eq!(body["options"]["num_predict"], 100);
 
-        println!("Raw response: {}", body);
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "calculator");
+        assert_eq!(body["options"]["num_predict"], 1024);
 
-        // Parse response based on provider
-        let content = if self.api_key.is_some() {
-            // Parse OpenAI response format
-            #[derive(Deserialize)]
-            struct ChatResponse {
-                choices: Vec<Choice>,
-            }
+        let client = openai_test_client("gpt-4");
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert_eq!(body["tools"][0]["function"]["name"], "calculator");
+        assert_eq!(body["max_tokens"], 1024);
+    }
 
-            #[derive(Deserialize)]
-            struct Choice {
-                message: Message,
-            }
+    #[test]
+    fn test_tool_choice_request_body() {
+        let messages = vec![Message::new_user("Who wears hat 1?".to_string())];
+        let tool = |name: &str| Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: name.to_string(),
+                description: None,
+                parameters: Some(json!({ "type": "object" })),
+                output_schema: None,
+            },
+        };
+        let tools = vec![tool("hat_lookup"), tool("calculator")];
+        let forced = ToolChoice::Function("hat_lookup".to_string());
 
-            let resp: ChatResponse = serde_json::from_str(&body)
-                .map_err(|e| format!("Failed to parse OpenAI response: {}", e))?;
+        let mut client = openai_test_client("gpt-4");
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert!(body.get("tool_choice").is_none());
+        client.set_tool_choice(Some(forced.clone()));
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert_eq!(
+            body["tool_choice"],
+            json!({ "type": "function", "function": { "name": "hat_lookup" } })
+        );
+        assert_eq!(body["tools"].as_array().unwrap().len(), 2);
+        client.set_tool_choice(Some(ToolChoice::Auto));
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert_eq!(body["tool_choice"], "auto");
+        client.set_tool_choice(Some(ToolChoice::None));
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert_eq!(body["tool_choice"], "none");
+        // Without tools there is nothing to choose from
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert!(body.get("tool_choice").is_none());
 
-            resp.choices
-                .first()
-                .map(|choice| choice.message.content.clone())
-                .ok_or_else(|| "No response choices returned".to_string())?
-        } else {
-            // Parse Ollama chat response format
-            #[derive(Deserialize)]
-            struct OllamaResponse {
-                message: Message,
-            }
+        env::set_var("WAVS_ENV_ANTHROPIC_API_KEY", "test-key");
+        let mut client = LLMClient::new("claude-3-5-haiku-latest").unwrap();
+        client.set_tool_choice(Some(forced.clone()));
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert_eq!(body["tool_choice"], json!({ "type": "tool", "name": "hat_lookup" }));
+
+        // Ollama is offered only the chosen tool
+        let mut client = LLMClient::new("llama3.2").unwrap();
+        client.set_tool_choice(Some(forced));
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert!(body.get("tool_choice").is_none());
+        assert_eq!(body["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(body["tools"][0]["function"]["name"], "hat_lookup");
+        client.set_tool_choice(Some(ToolChoice::None));
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert!(body.get("tools").is_none());
+        assert_eq!(body["options"]["num_predict"], 100);
+
+        client.set_tool_choice(Some(ToolChoice::Function("unknown".to_string())));
+        let err = client.build_request_body(&messages, Some(&tools)).unwrap_err();
+        assert!(err.contains("not among the offered tools"));
+    }
 
-            let resp: OllamaResponse = serde_json::from_str(&body)
-                .map_err(|e| format!("Failed to parse Ollama response: {}", e))?;
+    #[test]
+    fn test_validate_tool_name() {
+        assert!(validate_tool_name("hat_lookup").is_ok());
+        assert!(validate_tool_name("get-balance2").is_ok());
+        assert!(validate_tool_name(&"a".repeat(64)).is_ok());
+
+        let err = validate_tool_name(&"a".repeat(65)).unwrap_err().to_string();
+        assert!(err.contains(&"a".repeat(65)));
+        assert!(validate_tool_name("hat lookup").unwrap_err().to_string().contains("'hat lookup'"));
+        assert!(validate_tool_name("").is_err());
 
-            resp.message.content
+        assert_eq!(sanitize_name("hat lookup!"), "hat_lookup_");
+        assert_eq!(sanitize_name(&"é".repeat(70)), "_".repeat(64));
+        assert_eq!(sanitize_name(""), "_");
+
+        // Invalid names are rejected before anything is sent
+        let tool = Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: "hat lookup".to_string(),
+                description: None,
+                parameters: None,
+                output_schema: None,
+            },
         };
+        let client = LLMClient::new("llama3.2").unwrap();
+        let messages = vec![Message::new_user("Look up hat 1".to_string())];
+        let err = client.build_request_body(&messages, Some(&[tool])).unwrap_err();
+        assert!(err.contains("Invalid tool name 'hat lookup'"));
+    }
+
+    #[test]
+    fn test_parse_tool_call_message() {
+        let body = r#"{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function",
+            "function":{"name":"calculator","arguments":"{\"operation\":\"add\",\"a\":2,\"b\":2}"}}]}"#;
+        let message: Message = serde_json::from_str(body).unwrap();
+        assert!(message.content.is_none());
+        let tool_calls = message.tool_calls.unwrap();
+        assert_eq!(tool_calls[0].id, "call_1");
+        assert_eq!(tool_calls[0].function.name, "calculator");
 
-        println!("Successfully received response of length: {}", content.len());
-        Ok(content)
+        let serialized =
+            serde_json::to_value(Message::new_tool_result("call_1".to_string(), "4".to_string()))
+                .unwrap();
+        assert_eq!(serialized, json!({ "role": "tool", "content": "4", "tool_call_id": "call_1" }));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use wstd::runtime::block_on;
+    #[test]
+    fn test_assistant_messages() {
+        let answer = serde_json::to_value(Message::new_assistant("4".to_string())).unwrap();
+        assert_eq!(answer, json!({ "role": "assistant", "content": "4" }));
 
-    fn setup_test_env() {
-        env::set_var("WAVS_ENV_OLLAMA_API_URL", "http://localhost:11434");
+        let tool_call = ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "calculator".to_string(),
+                arguments: "{}".to_string(),
+            },
+        };
+        let message = Message::new_assistant_with_tools(String::new(), vec![tool_call]);
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "calculator", "arguments": "{}" }
+                }]
+            })
+        );
     }
 
-    // Unit tests that don't require HTTP requests
     #[test]
-    fn test_llm_client_initialization() {
-        setup_test_env();
+    fn test_tool_call_message_serializes_content() {
+        let tool_call = ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "calculator".to_string(),
+                arguments: "{}".to_string(),
+            },
+        };
+        let message = Message {
+            content: None,
+            ..Message::new_assistant_with_tools(String::new(), vec![tool_call])
+        };
+        let serialized = serde_json::to_value(&message).unwrap();
+        assert_eq!(serialized["content"], "");
+        assert_eq!(serialized["tool_calls"][0]["id"], "call_1");
 
-        let client = LLMClient::new("llama3.2");
-        assert!(client.is_ok());
-        let client = client.unwrap();
-        assert_eq!(client.model, "llama3.2");
-        assert!(client.api_url.contains("localhost:11434"));
-        assert!(client.api_url.contains("/api/chat"));
+        // Without tool calls an unset content is still left out
+        let message = Message { content: None, ..Message::new_assistant(String::new()) };
+        assert_eq!(serde_json::to_value(&message).unwrap(), json!({ "role": "assistant" }));
     }
 
     #[test]
-    fn test_new_client_empty_model() {
-        let result = LLMClient::new("");
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Model name cannot be empty");
+    fn test_parse_tool_call_arguments_string_or_object() {
+        let encoded = r#"{"function":{"name":"calculator","arguments":"{\"a\":2,\"b\":2}"}}"#;
+        let object = r#"{"function":{"name":"calculator","arguments":{"a":2,"b":2}}}"#;
+        let encoded: ToolCall = serde_json::from_str(encoded).unwrap();
+        let object: ToolCall = serde_json::from_str(object).unwrap();
+        assert_eq!(encoded.function.arguments, r#"{"a":2,"b":2}"#);
+        assert_eq!(serde_json::to_value(&encoded).unwrap(), serde_json::to_value(&object).unwrap());
+    }
 
-        let result = LLMClient::new("   ");
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Model name cannot be empty");
+    #[test]
+    fn test_json_tool_result_message() {
+        let result = ToolResult::json(json!({ "hatId": "0x1", "active": true }));
+        let message = Message::new_tool_result("call_1".to_string(), result.clone());
+        assert_eq!(
+            serde_json::to_value(message).unwrap(),
+            json!({ "role": "tool", "content": r#"{"active":true,"hatId":"0x1"}"#, "tool_call_id": "call_1" })
+        );
+
+        let result = ToolResult { text: Some("Hat 0x1".to_string()), ..result };
+        assert_eq!(result.content(), "Hat 0x1\n{\"active\":true,\"hatId\":\"0x1\"}");
+
+        // Output schemas stay out of the definitions sent to providers
+        let function = Function {
+            name: "hat_lookup".to_string(),
+            description: None,
+            parameters: None,
+            output_schema: Some(json!({ "type": "object" })),
+        };
+        assert_eq!(serde_json::to_value(function).unwrap(), json!({ "name": "hat_lookup" }));
+    }
+
+    #[test]
+    fn test_embeddings_request_bodies() {
+        let input = vec!["first".to_string(), "second".to_string()];
+
+        let client = LLMClient::new("nomic-embed-text").unwrap();
+        assert!(client.embeddings_url.ends_with("/api/embeddings"));
+        let bodies = client.build_embeddings_bodies(&input);
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0], json!({ "model": "nomic-embed-text", "prompt": "first" }));
+        assert_eq!(bodies[1]["prompt"], "second");
+
+        let client = openai_test_client("text-embedding-3-small");
+        assert_eq!(client.embeddings_url, "https://api.openai.com/v1/embeddings");
+        let bodies = client.build_embeddings_bodies(&input);
+        assert_eq!(bodies, vec![json!({ "model": "text-embedding-3-small", "input": input })]);
+    }
+
+    #[test]
+    fn test_parse_embeddings_responses() {
+        let body = r#"{"object":"list","data":[
+            {"object":"embedding","index":1,"embedding":[0.3,0.4]},
+            {"object":"embedding","index":0,"embedding":[0.1,0.2]}
+        ]}"#;
+        assert_eq!(parse_openai_embeddings(body).unwrap(), vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
+
+        let body = r#"{"embedding":[0.5,-0.5,1.0]}"#;
+        assert_eq!(parse_ollama_embedding(body).unwrap(), vec![0.5, -0.5, 1.0]);
+
+        assert!(parse_ollama_embedding("{}").is_err());
+    }
+
+    #[test]
+    fn test_embeddings_empty_input() {
+        let client = LLMClient::new("nomic-embed-text").unwrap();
+        let result = block_on(async { client.embeddings(&[]).await });
+        assert!(result.unwrap_err().contains("Embedding input cannot be empty"));
     }
 
     #[test]
     fn test_chat_completion_empty_messages() {
         let client = LLMClient::new("llama3.2").unwrap();
-        let result = block_on(async { client.chat_completion(&[]).await });
+        let result = block_on(async { client.chat_completion(&[], None).await });
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Messages cannot be empty"));
     }
 
+    #[test]
+    fn test_provider_for_model() {
+        assert_eq!(Provider::for_model("gpt-4"), Provider::OpenAI);
+        assert_eq!(Provider::for_model("gpt-3.5-turbo"), Provider::OpenAI);
+        assert_eq!(Provider::for_model("text-embedding-3-small"), Provider::OpenAI);
+        assert_eq!(Provider::for_model("claude-3-5-sonnet-latest"), Provider::Anthropic);
+        assert_eq!(Provider::for_model("llama3.2"), Provider::Ollama);
+        assert_eq!(Provider::for_model("nomic-embed-text"), Provider::Ollama);
+        assert_eq!(Provider::for_model(MOCK_MODEL), Provider::Mock);
+
+        // An OpenAI compatible server takes the models that default to Ollama
+        assert_eq!(Provider::infer("mistral-7b-instruct", true), Provider::OpenAICompatible);
+        assert_eq!(Provider::infer("gpt-4", true), Provider::OpenAI);
+        assert_eq!(Provider::infer("claude-3-5-haiku-latest", true), Provider::Anthropic);
+        assert_eq!(Provider::infer(MOCK_MODEL, true), Provider::Mock);
+
+        // Explicit selection overrides inference
+        let client = LLMClient::with_provider(Provider::Ollama, "gpt-4").unwrap();
+        assert_eq!(client.provider(), Provider::Ollama);
+        assert!(client.api_url.ends_with("/api/chat"));
+    }
+
+    #[test]
+    fn test_anthropic_request_body() {
+        env::set_var("WAVS_ENV_ANTHROPIC_API_KEY", "test-key");
+        let client = LLMClient::new("claude-3-5-haiku-latest").unwrap();
+        assert_eq!(client.api_url, "https://api.anthropic.com/v1/messages");
+
+        let tool_call = ToolCall {
+            id: "toolu_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "calculator".to_string(),
+                arguments: r#"{"operation":"add","a":2,"b":2}"#.to_string(),
+            },
+        };
+        let messages = vec![
+            Message::new_system("You are a calculator".to_string()),
+            Message::new_user("What is 2+2?".to_string()),
+            Message::new_assistant_with_tools(String::new(), vec![tool_call]),
+            Message::new_tool_result("toolu_1".to_string(), "4".to_string()),
+        ];
+        let tools = vec![Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: "calculator".to_string(),
+                description: Some("Add numbers".to_string()),
+                parameters: None,
+                output_schema: None,
+            },
+        }];
+
+        let body = client.build_request_body(&messages, Some(&tools)).unwrap();
+        assert_eq!(body["system"], "You are a calculator");
+        assert_eq!(body["max_tokens"], 1024);
+        assert_eq!(body["messages"][0], json!({ "role": "user", "content": "What is 2+2?" }));
+        assert_eq!(
+            body["messages"][1]["content"],
+            json!([{ "type": "tool_use", "id": "toolu_1", "name": "calculator",
+                "input": { "operation": "add", "a": 2, "b": 2 } }])
+        );
+        assert_eq!(
+            body["messages"][2],
+            json!({ "role": "user", "content": [
+                { "type": "tool_result", "tool_use_id": "toolu_1", "content": "4" }
+            ] })
+        );
+        assert_eq!(body["tools"][0]["input_schema"], json!({ "type": "object" }));
+    }
+
+    #[test]
+    fn test_parse_anthropic_response() {
+        let body = r#"{"id":"msg_1","type":"message","role":"assistant","content":[
+            {"type":"text","text":"Let me add those."},
+            {"type":"tool_use","id":"toolu_1","name":"calculator","input":{"a":2,"b":2}}
+        ],"stop_reason":"tool_use"}"#;
+        let message = parse_anthropic_response(body).unwrap();
+        assert_eq!(message.content.as_deref(), Some("Let me add those."));
+        let tool_calls = message.tool_calls.unwrap();
+        assert_eq!(tool_calls[0].id, "toolu_1");
+        assert_eq!(tool_calls[0].function.arguments, r#"{"a":2,"b":2}"#);
+
+        let body = r#"{"content":[{"type":"text","text":"4"}],"stop_reason":"end_turn"}"#;
+        let message = parse_anthropic_response(body).unwrap();
+        assert_eq!(message.content.as_deref(), Some("4"));
+        assert!(message.tool_calls.is_none());
+    }
+
+    /// Feed `stream` to a parser in `chunk_size` pieces, returning the tokens and text
+    fn parse_stream(provider: Provider, stream: &str, chunk_size: usize) -> (Vec<String>, String) {
+        let mut tokens = Vec::new();
+        let mut on_token = |token: &str| tokens.push(token.to_string());
+        let mut parser = StreamParser::new(provider);
+        for chunk in stream.as_bytes().chunks(chunk_size) {
+            parser.feed(chunk, &mut on_token).unwrap();
+        }
+        let text = parser.finish(&mut on_token).unwrap();
+        (tokens, text)
+    }
+
+    #[test]
+    fn test_parse_openai_stream() {
+        let stream = concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hats \"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"are güd\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"ignored\"}}]}\n\n",
+        );
+
+        // Chunk boundaries split lines and the multi-byte character
+        for chunk_size in [1, 7, stream.len()] {
+            let (tokens, text) = parse_stream(Provider::OpenAI, stream, chunk_size);
+            assert_eq!(tokens, ["Hats ", "are güd"]);
+            assert_eq!(text, "Hats are güd");
+        }
+    }
+
+    #[test]
+    fn test_parse_ollama_and_anthropic_streams() {
+        let stream = concat!(
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"4\"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"2\"},\"done\":false}\n",
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true}",
+        );
+        assert_eq!(parse_stream(Provider::Ollama, stream, 5).1, "42");
+
+        let stream = concat!(
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        );
+        assert_eq!(parse_stream(Provider::Anthropic, stream, 3).1, "Hi");
+
+        let mut parser = StreamParser::new(Provider::Ollama);
+        let err = parser.feed(b"{\"error\":\"model not found\"}\n", &mut |_| {}).unwrap_err();
+        assert_eq!(err, "Request failed: model not found");
+    }
+
+    #[test]
+    fn test_parse_ollama_tags() {
+        let body = br#"{"models":[
+            {"name":"llama3.2:latest","model":"llama3.2:latest","size":2019393189},
+            {"name":"qwen2.5:7b","model":"qwen2.5:7b","size":4683087332}
+        ]}"#;
+        let models = parse_ollama_tags(body).unwrap();
+        assert_eq!(models, vec!["llama3.2:latest", "qwen2.5:7b"]);
+
+        let available = |model| models.iter().any(|name| is_same_model(name, model));
+        assert!(available("llama3.2"));
+        assert!(available("llama3.2:latest"));
+        assert!(available("qwen2.5:7b"));
+        assert!(!available("qwen2.5"));
+        assert!(!available("llama3.1"));
+
+        assert!(parse_ollama_tags(b"{}").is_err());
+    }
+
+    #[test]
+    fn test_parse_ollama_chat_response() {
+        let body = r#"{"model":"llama3.2","created_at":"2025-01-01T00:00:00Z",
+            "message":{"role":"assistant","content":"4"},"done":true}"#;
+        match serde_json::from_str(body).unwrap() {
+            OllamaChatResponse::Success { message } => {
+                assert_eq!(message.content.as_deref(), Some("4"))
+            }
+            other => panic!("Expected a message, got {:?}", other),
+        }
+
+        let body = r#"{"error":"model \"llama9\" not found, try pulling it first"}"#;
+        match serde_json::from_str(body).unwrap() {
+            OllamaChatResponse::Error { error } => assert!(error.contains("not found")),
+            other => panic!("Expected an error, got {:?}", other),
+        }
+
+        assert!(serde_json::from_str::<OllamaChatResponse>(r#"{"done":true}"#).is_err());
+    }
+
+    #[test]
+    fn test_ollama_error_body_is_request_failed() {
+        let body = r#"{"error":"model \"llama9\" not found, try pulling it first"}"#;
+        let expected = "model \"llama9\" not found, try pulling it first".to_string();
+        let err = parse_ollama_response(body).unwrap_err();
+        assert_eq!(err, Error::RequestFailed(expected.clone()));
+        assert_eq!(err.kind(), ErrorKind::RequestFailed);
+
+        // Error statuses carry the same body
+        assert_eq!(ollama_error(body.as_bytes()), Some(expected));
+        assert_eq!(ollama_error(b"Bad Gateway"), None);
+
+        let err = parse_ollama_response("not json").unwrap_err();
+        assert!(err.to_string().contains("Failed to parse Ollama response"));
+    }
+
+    #[test]
+    fn test_parse_mock_responses() {
+        let responses = parse_mock_responses("The answer is 4").unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].role, "assistant");
+        assert_eq!(responses[0].content.as_deref(), Some("The answer is 4"));
+
+        let responses = parse_mock_responses(
+            r#"[{"tool_calls":[{"id":"call_1","function":{"name":"calculator",
+                "arguments":"{\"operation\":\"add\",\"a\":2,\"b\":2}"}}]}, "Done"]"#,
+        )
+        .unwrap();
+        assert_eq!(responses.len(), 2);
+        let tool_calls = responses[0].tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls[0].id, "call_1");
+        assert_eq!(tool_calls[0].tool_type, "function");
+        assert_eq!(tool_calls[0].function.name, "calculator");
+        assert_eq!(responses[1].content.as_deref(), Some("Done"));
+
+        assert!(parse_mock_responses("[1]").unwrap_err().contains("Invalid mock LLM response"));
+    }
+
+    #[test]
+    fn test_mock_client_replays_responses() {
+        env::set_var("WAVS_ENV_MOCK_LLM_RESPONSE", r#"["first", "second"]"#);
+        let client = LLMClient::new(MOCK_MODEL).unwrap();
+        let messages = vec![Message::new_user("Hello".to_string())];
+
+        let replies: Vec<_> =
+            (0..3).map(|_| block_on(client.chat_completion_text(&messages)).unwrap()).collect();
+        assert_eq!(replies, ["first", "second", "second"]);
+
+        let client = LLMClient::mock(Vec::new());
+        let result = block_on(client.chat_completion(&messages, None));
+        assert_eq!(result.unwrap_err(), "Mock LLM has no responses configured");
+    }
+
+    #[test]
+    fn test_chat_completion_json() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Decision {
+            approve: bool,
+            reason: String,
+        }
+
+        let messages = vec![Message::new_user("Decide, answering in JSON".to_string())];
+        let mut client = LLMClient::mock(vec![Message::new_assistant(
+            r#" {"approve": true, "reason": "holds the token"} "#.to_string(),
+        )]);
+        let decision: Decision = block_on(client.chat_completion_json(&messages)).unwrap();
+        assert_eq!(decision, Decision { approve: true, reason: "holds the token".to_string() });
+        // JSON mode was only on for the request
+        assert!(!client.json_mode);
+
+        let mut client =
+            LLMClient::mock(vec![Message::new_assistant(r#"{"approve": "yes"}"#.to_string())]);
+        let err = block_on(client.chat_completion_json::<Decision>(&messages)).unwrap_err();
+        assert!(err.starts_with("Failed to deserialize response into"), "{}", err);
+        assert!(
+            err.contains("Decision: invalid type: string \"yes\", expected a boolean"),
+            "{}",
+            err
+        );
+        assert!(err.ends_with(r#"content: {"approve": "yes"}"#), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_multiple_openai_choices() {
+        let body = json!({
+            "choices": [
+                { "index": 0, "message": { "role": "assistant", "content": "{\"approve\": true}" } },
+                { "index": 1, "message": { "role": "assistant", "content": "{}" } },
+                {
+                    "index": 2,
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "function": { "name": "get_hat", "arguments": "{}" }
+                        }]
+                    }
+                }
+            ]
+        });
+        let candidates = parse_openai_choices(&body.to_string()).unwrap();
+        assert_eq!(candidates.len(), 3);
+        assert_eq!(candidates[0].content.as_deref(), Some(r#"{"approve": true}"#));
+        assert_eq!(candidates[1].content.as_deref(), Some("{}"));
+        assert_eq!(candidates[2].tool_calls.as_ref().unwrap()[0].function.name, "get_hat");
+
+        assert_eq!(
+            parse_openai_choices(r#"{"choices": []}"#).unwrap_err(),
+            "No response choices returned"
+        );
+    }
+
+    #[test]
+    fn test_chat_completion_best_of() {
+        let messages = vec![Message::new_user("Answer in JSON".to_string())];
+        let client = LLMClient::mock(
+            ["{\"approve\": true, \"reason\": \"ok\"}", "not json", "{\"approve\": true}"]
+                .map(|content| Message::new_assistant(content.to_string()))
+                .to_vec(),
+        );
+        let shortest_json = |candidates: &[Message]| {
+            let contents: Vec<_> =
+                candidates.iter().map(|m| m.content.as_deref().unwrap_or_default()).collect();
+            (0..contents.len())
+                .filter(|&i| serde_json::from_str::<Value>(contents[i]).is_ok())
+                .min_by_key(|&i| contents[i].len())
+                .unwrap_or(0)
+        };
+        let best = block_on(client.chat_completion_best_of(&messages, 3, shortest_json)).unwrap();
+        assert_eq!(best.content.as_deref(), Some(r#"{"approve": true}"#));
+
+        let err = block_on(client.chat_completion_best_of(&messages, 2, |_| 2)).unwrap_err();
+        assert_eq!(err, "Picked candidate 2 of only 2");
+        assert!(block_on(client.chat_completion_candidates(&messages, None, 0)).is_err());
+    }
+
     // Integration tests that require HTTP - only run in WASI environment
     #[cfg(all(test, target_arch = "wasm32"))]
     mod integration {
@@ -284,16 +2830,13 @@ mod tests {
                 println!("Client initialized successfully");
 
                 let messages = vec![
-                    Message {
-                        role: "system".to_string(),
-                        content: "You are a helpful math assistant".to_string(),
-                    },
-                    Message { role: "user".to_string(), content: "What is 2+2?".to_string() },
+                    Message::new_system("You are a helpful math assistant".to_string()),
+                    Message::new_user("What is 2+2?".to_string()),
                 ];
                 println!("Sending test message: {:?}", messages);
 
                 let result = block_on(async {
-                    match client.chat_completion(&messages).await {
+                    match client.chat_completion_text(&messages).await {
                         Ok(response) => {
                             println!("Received successful response");
                             Ok(response)
@@ -346,16 +2889,13 @@ mod tests {
                 println!("Client initialized successfully");
 
                 let messages = vec![
-                    Message {
-                        role: "system".to_string(),
-                        content: "You are a helpful math assistant".to_string(),
-                    },
-                    Message { role: "user".to_string(), content: "What is 2+2?".to_string() },
+                    Message::new_system("You are a helpful math assistant".to_string()),
+                    Message::new_user("What is 2+2?".to_string()),
                 ];
                 println!("Sending test message: {:?}", messages);
 
                 let result = block_on(async {
-                    match client.chat_completion(&messages).await {
+                    match client.chat_completion_text(&messages).await {
                         Ok(response) => {
                             println!("Received successful response");
                             Ok(response)
@@ -381,6 +2921,108 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_request_log_redacts_credentials_and_prompts() {
+        let client = openai_test_client("gpt-4");
+        let messages = vec![
+            Message::new_system("You are a secret agent".to_string()),
+            Message::new_user("What is hat 0x1?".to_string()),
+        ];
+        let body = client.build_request_body(&messages, None).unwrap();
+        let req = client.build_request(&client.api_url, &body).unwrap();
+
+        let line = describe_request(&req, &body, false);
+        assert!(!line.contains("test-key"), "{}", line);
+        assert!(!line.contains("Bearer"), "{}", line);
+        assert!(line.contains("authorization: [redacted]"), "{}", line);
+        assert!(!line.contains("secret agent"), "{}", line);
+        assert!(!line.contains("hat 0x1"), "{}", line);
+        assert!(line.contains("\"model\":\"gpt-4\""), "{}", line);
+
+        // Opting into prompts still keeps the token out
+        let line = describe_request(&req, &body, true);
+        assert!(!line.contains("test-key"), "{}", line);
+        assert!(line.contains("secret agent"), "{}", line);
+    }
+
+    #[test]
+    fn test_openai_compatible_request() {
+        let (api_url, embeddings_url) = openai_compatible_urls("http://vllm:8000/v1/");
+        assert_eq!(api_url, "http://vllm:8000/v1/chat/completions");
+        assert_eq!(embeddings_url, "http://vllm:8000/v1/embeddings");
+
+        let mut client = LLMClient {
+            provider: Provider::OpenAICompatible,
+            model: "mistral-7b-instruct".to_string(),
+            api_url,
+            embeddings_url,
+            mock: None,
+            ..LLMClient::mock(Vec::new())
+        };
+        let messages = vec![Message::new_user("What is 2+2?".to_string())];
+        let body = client.build_request_body(&messages, None).unwrap();
+        assert_eq!(body["model"], "mistral-7b-instruct");
+        assert_eq!(body["messages"][0]["content"], "What is 2+2?");
+        assert_eq!(body["seed"], 42);
+        assert!(body.get("options").is_none());
+
+        // The key is optional
+        let req = client.build_request(&client.api_url, &body).unwrap();
+        assert_eq!(req.uri(), "http://vllm:8000/v1/chat/completions");
+        assert!(req.headers().get("authorization").is_none());
+        client.api_key = Some("local-key".to_string());
+        let req = client.build_request(&client.api_url, &body).unwrap();
+        assert_eq!(req.headers()["authorization"], "Bearer local-key");
+    }
+
+    #[test]
+    fn test_extra_headers_are_sent() {
+        let mut client = openai_test_client("gpt-4");
+        client.add_header("OpenAI-Organization", "org-hats").unwrap();
+        client.add_header("X-Route", "eu").unwrap();
+        let body = client.build_request_body(&[Message::new_user("Hi".to_string())], None).unwrap();
+        let req = client.build_request(&client.api_url, &body).unwrap();
+
+        let headers = req.headers();
+        assert_eq!(headers["openai-organization"], "org-hats");
+        assert_eq!(headers["x-route"], "eu");
+        assert_eq!(headers["content-type"], "application/json");
+        assert_eq!(headers["authorization"], "Bearer test-key");
+
+        assert!(client
+            .add_header("bad header", "x")
+            .unwrap_err()
+            .starts_with("Invalid header name"));
+        assert!(client.add_header("X-Route", "eu\nX-Evil: 1").is_err());
+    }
+
+    #[test]
+    fn test_openai_org_and_project_headers() {
+        let headers = provider_headers(
+            Provider::OpenAI,
+            Some("org-hats".to_string()),
+            Some("proj-agent".to_string()),
+        );
+        assert_eq!(
+            headers,
+            vec![
+                ("openai-organization", "org-hats".to_string()),
+                ("openai-project", "proj-agent".to_string())
+            ]
+        );
+        assert_eq!(
+            provider_headers(Provider::OpenAI, None, Some(" ".to_string())),
+            Vec::<(&str, String)>::new()
+        );
+
+        // The OpenAI account is not sent to other providers
+        for provider in [Provider::Ollama, Provider::Anthropic, Provider::Mock] {
+            let headers =
+                provider_headers(provider, Some("org-hats".to_string()), Some("proj".to_string()));
+            assert!(headers.is_empty(), "{:?}", provider);
+        }
+    }
+
     // Add a note about integration tests when running natively
     #[cfg(not(target_arch = "wasm32"))]
     #[test]
@@ -0,0 +1,120 @@
+//! Agent answers submitted as minting decisions, for "agent decides, minter
+//! acts" services
+//!
+//! With `WAVS_ENV_AGENT_MINTING_OUTPUT` set, the agent is asked to answer with
+//! a JSON decision, which is encoded as the `HatMintingData` the minter
+//! contract's `handleSignedData` consumes, in place of an `AgentResult`.
+
+use crate::IHatsAvsTypes::HatMintingData;
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::SolValue;
+use serde::Deserialize;
+
+/// Told to the model after the system prompt when minting output is enabled
+pub const INSTRUCTIONS: &str = "Decide whether the hat should be minted. Answer only with a JSON \
+    object of the form {\"hatId\": \"0x...\", \"wearer\": \"0x...\", \"mint\": true, \
+    \"reason\": \"...\"}, with mint false and a reason when it should not be minted.";
+
+/// Whether answers are submitted as minting decisions, set with
+/// `WAVS_ENV_AGENT_MINTING_OUTPUT`
+pub fn enabled() -> bool {
+    wavs_hats_common::env::flag("WAVS_ENV_AGENT_MINTING_OUTPUT", false)
+}
+
+/// Minting decision of the model
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MintingDecision {
+    pub hat_id: U256,
+    pub wearer: Address,
+    pub mint: bool,
+    #[serde(default)]
+    pub reason: String,
+}
+
+impl MintingDecision {
+    /// Parse the decision from the model's answer, ignoring any text around
+    /// the JSON object
+    pub fn from_answer(answer: &str) -> Result<Self, String> {
+        let json = match (answer.find('{'), answer.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &answer[start..=end],
+            _ => answer,
+        };
+        let decision: Self = serde_json::from_str(json)
+            .map_err(|e| format!("Agent answer is not a minting decision: {}", e))?;
+
+        // The minter contract reverts on either, so nothing is submitted
+        if decision.hat_id.is_zero() {
+            return Err("Agent minting decision has no hat ID".to_string());
+        }
+        if decision.wearer == Address::ZERO {
+            return Err("Agent minting decision has no wearer".to_string());
+        }
+        Ok(decision)
+    }
+
+    /// ABI encode the decision as `HatMintingData`
+    ///
+    /// The agent has no requestor of its own, so the requestor is zero. A
+    /// rejection without a reason is given one, as the minter does.
+    pub fn abi_encode(self) -> Vec<u8> {
+        let reason = match (self.mint, self.reason.trim().is_empty()) {
+            (true, _) => String::new(),
+            (false, true) => "agent declined the mint".to_string(),
+            (false, false) => self.reason,
+        };
+        HatMintingData {
+            hatId: self.hat_id,
+            wearer: self.wearer,
+            requestor: Address::ZERO,
+            success: self.mint,
+            reason,
+        }
+        .abi_encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minting_output_encoding() {
+        let wearer = Address::repeat_byte(0x42);
+        let answer = format!(
+            "The wearer holds the required stake.\n{{\"hatId\": \"0x{:x}\", \"wearer\": \"{}\", \
+             \"mint\": true, \"reason\": \"staked\"}}",
+            U256::from(1) << 224,
+            wearer
+        );
+        let decision = MintingDecision::from_answer(&answer).unwrap();
+        let data = HatMintingData::abi_decode(&decision.abi_encode(), true).unwrap();
+        assert_eq!(data.hatId, U256::from(1) << 224);
+        assert_eq!(data.wearer, wearer);
+        assert_eq!(data.requestor, Address::ZERO);
+        assert!(data.success);
+        assert_eq!(data.reason, "");
+
+        let answer = format!(r#"{{"hatId": "0x1", "wearer": "{}", "mint": false}}"#, wearer);
+        let decision = MintingDecision::from_answer(&answer).unwrap();
+        let data = HatMintingData::abi_decode(&decision.abi_encode(), true).unwrap();
+        assert!(!data.success);
+        assert_eq!(data.reason, "agent declined the mint");
+    }
+
+    #[test]
+    fn test_invalid_minting_decision() {
+        let err = MintingDecision::from_answer("Mint it").unwrap_err();
+        assert!(err.starts_with("Agent answer is not a minting decision"));
+        let answer = format!(r#"{{"hatId": "0x0", "wearer": "{}", "mint": true}}"#, Address::ZERO);
+        assert_eq!(
+            MintingDecision::from_answer(&answer).unwrap_err(),
+            "Agent minting decision has no hat ID"
+        );
+        let answer = format!(r#"{{"hatId": "0x1", "wearer": "{}", "mint": true}}"#, Address::ZERO);
+        assert_eq!(
+            MintingDecision::from_answer(&answer).unwrap_err(),
+            "Agent minting decision has no wearer"
+        );
+    }
+}
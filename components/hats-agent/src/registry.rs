@@ -0,0 +1,227 @@
+//! Registry mapping tool names to their definitions and handlers
+
+use crate::llm::{Tool, ToolCall, ToolResult};
+use crate::tools::{builders, handlers, ToolContext};
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use wavs_hats_common::info;
+
+/// Future returned by a tool handler
+pub type ToolFuture<'a> = Pin<Box<dyn Future<Output = Result<ToolResult, String>> + 'a>>;
+
+/// Handler receiving the validated arguments of a call
+type Handler = Box<dyn for<'a> Fn(&'a Value, &'a ToolContext) -> ToolFuture<'a>>;
+
+/// Tools offered to the model, in registration order
+#[derive(Default)]
+pub struct ToolRegistry {
+    entries: Vec<(Tool, Handler)>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry with the built-in tools of [`builders::default_toolset`]
+    pub fn builtin() -> Self {
+        Self::with_tools(builders::default_toolset()).expect("built-in tools have unique names")
+    }
+
+    /// Registry with the built-in tools of [`builders::default_toolset`] that
+    /// are in `allowed`, such as the tools listed by a configuration hat
+    ///
+    /// Naming a tool does not enable one the deployment disabled.
+    pub fn builtin_allowing(allowed: &[String]) -> Self {
+        let tools = builders::default_toolset()
+            .into_iter()
+            .filter(|tool| allowed.contains(&tool.function.name))
+            .collect();
+        Self::with_tools(tools).expect("built-in tools have unique names")
+    }
+
+    /// Registry with the handlers of the built-in `tools`
+    fn with_tools(tools: Vec<Tool>) -> Result<Self, String> {
+        let mut registry = Self::new();
+        for tool in tools {
+            let name = tool.function.name.clone();
+            match name.as_str() {
+                "calculator" => registry.register(&name, tool, |args, _| {
+                    Box::pin(std::future::ready(handlers::execute_calculator(args).map(Into::into)))
+                })?,
+                "evaluate" => registry.register(&name, tool, |args, _| {
+                    Box::pin(std::future::ready(handlers::execute_evaluate(args).map(Into::into)))
+                })?,
+                "hat_lookup" => registry.register(&name, tool, |args, ctx| {
+                    Box::pin(handlers::execute_hat_lookup(args, ctx))
+                })?,
+                "hat_id" => registry.register(&name, tool, |args, _| {
+                    Box::pin(std::future::ready(handlers::execute_hat_id(args).map(Into::into)))
+                })?,
+                "http_get" => registry.register(&name, tool, |args, _| {
+                    Box::pin(async { handlers::execute_http_get(args).await.map(Into::into) })
+                })?,
+                _ => return Err(format!("Tool {} has no built-in handler", name)),
+            };
+        }
+        Ok(registry)
+    }
+
+    /// Register `tool` under `name` with the handler executing its calls
+    ///
+    /// `name` must match the tool definition and not already be registered.
+    pub fn register(
+        &mut self,
+        name: &str,
+        tool: Tool,
+        handler: impl for<'a> Fn(&'a Value, &'a ToolContext) -> ToolFuture<'a> + 'static,
+    ) -> Result<&mut Self, String> {
+        if tool.function.name != name {
+            return Err(format!(
+                "Tool registered as {} is defined as {}",
+                name, tool.function.name
+            ));
+        }
+        if self.get(name).is_some() {
+            return Err(format!("Tool {} is already registered", name));
+        }
+
+        self.entries.push((tool, Box::new(handler)));
+        Ok(self)
+    }
+
+    /// Definitions of the registered tools, to send to the model
+    pub fn tools(&self) -> Vec<Tool> {
+        self.entries.iter().map(|(tool, _)| tool.clone()).collect()
+    }
+
+    fn get(&self, name: &str) -> Option<&(Tool, Handler)> {
+        self.entries.iter().find(|(tool, _)| tool.function.name == name)
+    }
+
+    /// Validate the arguments of `tool_call` and run its handler
+    pub async fn dispatch(
+        &self,
+        tool_call: &ToolCall,
+        ctx: &ToolContext,
+    ) -> Result<ToolResult, String> {
+        let name = &tool_call.function.name;
+        info!("Executing tool call: {}", name);
+
+        let (tool, handler) = self.get(name).ok_or_else(|| format!("Unknown tool: {}", name))?;
+        let args: Value = serde_json::from_str(&tool_call.function.arguments)
+            .map_err(|e| format!("Failed to parse arguments for {}: {}", name, e))?;
+        handlers::validate_arguments(tool, &args)?;
+
+        handler(&args, ctx).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{Function, ToolCallFunction};
+    use wstd::runtime::block_on;
+
+    fn echo_tool() -> Tool {
+        Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: "echo".to_string(),
+                description: None,
+                parameters: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": { "text": { "type": "string" } },
+                    "required": ["text"]
+                })),
+                output_schema: None,
+            },
+        }
+    }
+
+    fn echo_call(arguments: &str) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "echo".to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_register_and_dispatch_custom_tool() {
+        let mut registry = ToolRegistry::new();
+        registry
+            .register("echo", echo_tool(), |args, _| {
+                let text = args["text"].as_str().unwrap_or_default().to_uppercase();
+                Box::pin(async move { Ok(text.into()) })
+            })
+            .unwrap();
+
+        assert_eq!(registry.tools().len(), 1);
+        assert_eq!(registry.tools()[0].function.name, "echo");
+
+        let result =
+            block_on(registry.dispatch(&echo_call(r#"{"text":"hi"}"#), &Default::default()));
+        assert_eq!(result.unwrap().content(), "HI");
+
+        // Arguments are validated against the registered definition
+        let result = block_on(registry.dispatch(&echo_call("{}"), &Default::default()));
+        assert_eq!(result.unwrap_err(), "Missing required argument 'text' for echo");
+    }
+
+    fn noop<'a>(_: &'a Value, _: &'a ToolContext) -> ToolFuture<'a> {
+        Box::pin(async { Ok(ToolResult::default()) })
+    }
+
+    #[test]
+    fn test_register_misconfiguration() {
+        let mut registry = ToolRegistry::new();
+        let handler = noop;
+
+        let err = registry.register("shout", echo_tool(), handler).err().unwrap();
+        assert_eq!(err, "Tool registered as shout is defined as echo");
+
+        registry.register("echo", echo_tool(), handler).unwrap();
+        let err = registry.register("echo", echo_tool(), handler).err().unwrap();
+        assert_eq!(err, "Tool echo is already registered");
+    }
+
+    #[test]
+    fn test_builtin_tools() {
+        let names: Vec<_> =
+            ToolRegistry::builtin().tools().into_iter().map(|t| t.function.name).collect();
+        assert_eq!(names, ["calculator", "evaluate", "hat_lookup", "hat_id", "http_get"]);
+
+        let allowed = ["hat_id".to_string(), "hat_lookup".to_string(), "unknown".to_string()];
+        let registry = ToolRegistry::builtin_allowing(&allowed);
+        let names: Vec<_> = registry.tools().into_iter().map(|t| t.function.name).collect();
+        assert_eq!(names, ["hat_lookup", "hat_id"]);
+    }
+
+    #[test]
+    fn test_disabled_tools() {
+        let tools = builders::toolset(" http_get,evaluate, unknown");
+        let names: Vec<_> = tools.iter().map(|t| t.function.name.as_str()).collect();
+        assert_eq!(names, ["calculator", "hat_lookup", "hat_id"]);
+
+        // A disabled tool is neither offered nor executed
+        let registry = ToolRegistry::with_tools(tools).unwrap();
+        assert_eq!(registry.tools().len(), 3);
+        let call = ToolCall {
+            function: ToolCallFunction {
+                name: "http_get".to_string(),
+                arguments: r#"{"url":"https://example.com"}"#.to_string(),
+            },
+            ..echo_call("{}")
+        };
+        let result = block_on(registry.dispatch(&call, &Default::default()));
+        assert_eq!(result.unwrap_err(), "Unknown tool: http_get");
+
+        let err = ToolRegistry::with_tools(vec![echo_tool()]).err().unwrap();
+        assert_eq!(err, "Tool echo has no built-in handler");
+    }
+}
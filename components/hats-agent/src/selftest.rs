@@ -0,0 +1,75 @@
+//! Liveness probe of the LLM endpoint through the normal trigger path
+
+use crate::llm::{LLMClient, Message};
+use serde::Serialize;
+use std::time::Instant;
+
+/// Prompt that runs the self-test instead of the agent
+pub const PROMPT: &str = "__selftest__";
+
+/// Whether a trigger's prompt asks for the self-test
+pub fn requested(prompt: &str) -> bool {
+    prompt.trim() == PROMPT
+}
+
+/// Outcome of the self-test, submitted as JSON in place of an answer
+///
+/// The latency and error differ between operators.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTest {
+    pub provider: String,
+    pub model: String,
+    pub latency_ms: u64,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SelfTest {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Send a minimal completion without tools and report whether it answered
+pub async fn run(client: &LLMClient) -> SelfTest {
+    let messages = [Message::new_user("Reply with OK".to_string())];
+    let start = Instant::now();
+    let result = client.chat_completion(&messages, None).await;
+    SelfTest {
+        provider: format!("{:?}", client.provider()),
+        model: client.model().to_string(),
+        latency_ms: start.elapsed().as_millis().try_into().unwrap_or(u64::MAX),
+        ok: result.is_ok(),
+        error: result.err(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::parse_mock_responses;
+    use wstd::runtime::block_on;
+
+    #[test]
+    fn test_selftest_with_mock_provider() {
+        assert!(requested(" __selftest__\n"));
+        assert!(!requested("What hats exist?"));
+
+        let client = LLMClient::mock(parse_mock_responses("OK").unwrap());
+        let status = block_on(run(&client));
+        assert!(status.ok);
+        assert_eq!(status.error, None);
+        assert_eq!((status.provider.as_str(), status.model.as_str()), ("Mock", "mock"));
+        let json: serde_json::Value = serde_json::from_str(&status.to_json()).unwrap();
+        assert_eq!(json["ok"], true);
+        assert!(json["latencyMs"].is_u64());
+
+        // A mock without responses fails like an unreachable endpoint
+        let status = block_on(run(&LLMClient::mock(Vec::new())));
+        assert!(!status.ok);
+        assert!(status.error.is_some());
+        assert!(status.to_json().contains("\"error\""));
+    }
+}
@@ -0,0 +1,241 @@
+//! Agent answers checked against an output schema, for workflows that
+//! consume the answer as data
+//!
+//! A configuration hat's `outputSchema` turns on JSON mode and is checked
+//! against the model's final answer. An answer that doesn't match is sent
+//! back to the model once with the mismatch, and a second mismatch fails the
+//! run rather than submitting it.
+
+use crate::context;
+use crate::llm::{ChatClient, Message};
+use serde_json::Value;
+use wavs_hats_common::warn;
+
+/// Told to the model after the system prompt when an output schema is set
+pub fn instructions(schema: &Value) -> String {
+    format!("Answer only with a JSON value matching this JSON schema: {}", schema)
+}
+
+/// Check `answer` against `schema`, asking the model once to correct an
+/// answer that doesn't match
+///
+/// `messages` is the conversation that led to `answer`. The correction is
+/// requested without tools, with the messages trimmed to `context_tokens`.
+pub async fn checked_answer<C: ChatClient>(
+    client: &C,
+    messages: &mut Vec<Message>,
+    answer: String,
+    schema: &Value,
+    context_tokens: usize,
+) -> Result<String, String> {
+    let Err(e) = check(&answer, schema) else {
+        return Ok(answer);
+    };
+    warn!("Answer does not match the output schema, asking for a correction: {}", e);
+
+    // A system note rather than a user message, so the user's prompt stays last
+    messages.push(Message::new_assistant(answer));
+    messages.push(Message::new_system(format!(
+        "Your answer does not match the output schema: {}. Answer again with only JSON \
+         matching the schema.",
+        e
+    )));
+    let window = context::trim_messages(client.model(), messages, context_tokens);
+    let answer = client.chat_completion(&window, None).await?.content.unwrap_or_default();
+    check(&answer, schema)
+        .map_err(|e| format!("Agent answer does not match the output schema: {}", e))?;
+    Ok(answer)
+}
+
+/// Parse `answer` as JSON and validate it against `schema`
+pub fn check(answer: &str, schema: &Value) -> Result<(), String> {
+    let value: Value =
+        serde_json::from_str(answer.trim()).map_err(|e| format!("answer is not JSON: {}", e))?;
+    validate(schema, &value, "$")
+}
+
+/// Validate `value` at `path` against `schema`
+///
+/// Supports the subset of JSON schema that describes plain data: `type`,
+/// possibly a list of types, `enum`, `required`, `properties`,
+/// `additionalProperties: false` and `items`. Other keywords are ignored.
+pub fn validate(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    if let Some(types) = types(schema) {
+        if !types.iter().any(|expected| has_type(value, expected)) {
+            return Err(format!("{} must be of type {}, got {}", path, types.join(" or "), value));
+        }
+    }
+
+    if let Some(allowed) = schema["enum"].as_array() {
+        if !allowed.contains(value) {
+            return Err(format!(
+                "{} must be one of {}, got {}",
+                path,
+                Value::Array(allowed.clone()),
+                value
+            ));
+        }
+    }
+
+    if let Some(object) = value.as_object() {
+        for field in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+            if !object.contains_key(field) {
+                return Err(format!("{} is missing required field '{}'", path, field));
+            }
+        }
+        let properties = schema["properties"].as_object();
+        for (field, value) in object {
+            match properties.and_then(|properties| properties.get(field)) {
+                Some(property) => validate(property, value, &format!("{}.{}", path, field))?,
+                None if schema["additionalProperties"] == Value::Bool(false) => {
+                    return Err(format!("{} has unexpected field '{}'", path, field));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            validate(items, item, &format!("{}[{}]", path, i))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Types allowed by `schema`, given as a single type or a list of them
+fn types(schema: &Value) -> Option<Vec<&str>> {
+    match &schema["type"] {
+        Value::String(expected) => Some(vec![expected.as_str()]),
+        Value::Array(types) => Some(types.iter().filter_map(Value::as_str).collect()),
+        _ => None,
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::DEFAULT_CONTEXT_TOKENS;
+    use crate::llm::LLMClient;
+    use serde_json::json;
+    use wstd::runtime::block_on;
+
+    fn decision_schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "approve": { "type": "boolean" },
+                "reason": { "type": "string" },
+                "risk": { "enum": ["low", "high"] }
+            },
+            "required": ["approve", "reason"],
+            "additionalProperties": false
+        })
+    }
+
+    fn conversation() -> Vec<Message> {
+        vec![Message::new_user("Should hat 1 be minted to Alice?".to_string())]
+    }
+
+    #[test]
+    fn test_valid_answer() {
+        let client = LLMClient::mock(Vec::new());
+        let mut messages = conversation();
+        let answer = r#"{"approve": true, "reason": "Alice holds the token", "risk": "low"}"#;
+
+        let checked = block_on(checked_answer(
+            &client,
+            &mut messages,
+            answer.to_string(),
+            &decision_schema(),
+            DEFAULT_CONTEXT_TOKENS,
+        ));
+        assert_eq!(checked.unwrap(), answer);
+        // Nothing was sent back to the model
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_then_corrected_answer() {
+        let corrected = r#"{"approve": false, "reason": "Alice holds no token"}"#;
+        let client = LLMClient::mock(vec![Message::new_assistant(corrected.to_string())]);
+        let mut messages = conversation();
+
+        let checked = block_on(checked_answer(
+            &client,
+            &mut messages,
+            r#"{"approve": "no", "reason": ""}"#.to_string(),
+            &decision_schema(),
+            DEFAULT_CONTEXT_TOKENS,
+        ));
+        assert_eq!(checked.unwrap(), corrected);
+        assert_eq!(messages[1].content.as_deref(), Some(r#"{"approve": "no", "reason": ""}"#));
+        assert_eq!(messages[2].role, "system");
+        assert!(messages[2]
+            .content
+            .as_deref()
+            .unwrap()
+            .contains(r#"$.approve must be of type boolean, got "no""#));
+    }
+
+    #[test]
+    fn test_persistent_mismatch_fails() {
+        let client =
+            LLMClient::mock(vec![Message::new_assistant("The mint is approved".to_string())]);
+        let mut messages = conversation();
+
+        let err = block_on(checked_answer(
+            &client,
+            &mut messages,
+            r#"{"approve": true, "reason": "ok", "risk": "none"}"#.to_string(),
+            &decision_schema(),
+            DEFAULT_CONTEXT_TOKENS,
+        ))
+        .unwrap_err();
+        assert!(
+            err.starts_with("Agent answer does not match the output schema: answer is not JSON")
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = decision_schema();
+        let check = |value: Value| validate(&schema, &value, "$");
+
+        assert_eq!(check(json!({ "approve": true, "reason": "" })), Ok(()));
+        assert_eq!(
+            check(json!({ "approve": true })),
+            Err("$ is missing required field 'reason'".to_string())
+        );
+        assert_eq!(
+            check(json!({ "approve": true, "reason": "", "risk": "none" })),
+            Err(r#"$.risk must be one of ["low","high"], got "none""#.to_string())
+        );
+        assert_eq!(
+            check(json!({ "approve": true, "reason": "", "extra": 1 })),
+            Err("$ has unexpected field 'extra'".to_string())
+        );
+        assert_eq!(check(json!([])), Err("$ must be of type object, got []".to_string()));
+
+        let schema = json!({ "type": "array", "items": { "type": ["integer", "null"] } });
+        assert_eq!(validate(&schema, &json!([1, null]), "$"), Ok(()));
+        assert_eq!(
+            validate(&schema, &json!([1, 1.5]), "$"),
+            Err("$[1] must be of type integer or null, got 1.5".to_string())
+        );
+    }
+}
@@ -0,0 +1,652 @@
+//! Tools the agent can offer to the model, with their definitions and handlers
+
+use alloy_network::Ethereum;
+use alloy_provider::RootProvider;
+use alloy_rpc_types::BlockId;
+use wavs_hats_common::metadata::MetadataCache;
+
+/// Per-run state shared with tool handlers
+#[derive(Debug, Clone)]
+pub struct ToolContext {
+    /// Hat reads of the run, pinned to its block, when a provider is configured
+    pub cache: Option<MetadataCache>,
+}
+
+impl ToolContext {
+    pub fn new(block: BlockId, provider: Option<RootProvider<Ethereum>>) -> Self {
+        Self { cache: provider.map(|provider| MetadataCache::new(provider, block)) }
+    }
+
+    /// Cached hat reads for on-chain tools, or an error to report to the model
+    pub fn cache(&self) -> Result<&MetadataCache, String> {
+        self.cache.as_ref().ok_or_else(|| "No Ethereum provider available".to_string())
+    }
+}
+
+impl Default for ToolContext {
+    fn default() -> Self {
+        Self::new(BlockId::latest(), None)
+    }
+}
+
+/// Tool definitions sent to the model
+pub mod builders {
+    use crate::llm::{Function, Tool};
+    use serde_json::json;
+    use wavs_hats_common::warn;
+
+    /// Tools a deployment exposes, without those in `WAVS_ENV_DISABLED_TOOLS`
+    pub fn default_toolset() -> Vec<Tool> {
+        toolset(&std::env::var("WAVS_ENV_DISABLED_TOOLS").unwrap_or_default())
+    }
+
+    /// Every built-in tool but those named in the comma separated `disabled`
+    pub fn toolset(disabled: &str) -> Vec<Tool> {
+        let tools = vec![calculator(), evaluate(), hat_lookup(), hat_id_tools(), http_get()];
+        let disabled: Vec<&str> =
+            disabled.split(',').map(str::trim).filter(|name| !name.is_empty()).collect();
+        for name in &disabled {
+            if !tools.iter().any(|tool| tool.function.name == *name) {
+                warn!("Cannot disable unknown tool {}", name);
+            }
+        }
+        tools.into_iter().filter(|tool| !disabled.contains(&tool.function.name.as_str())).collect()
+    }
+
+    /// Calculator for basic arithmetic on two numbers
+    pub fn calculator() -> Tool {
+        Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: "calculator".to_string(),
+                description: Some("Perform an arithmetic operation on two numbers".to_string()),
+                parameters: Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "operation": {
+                            "type": "string",
+                            "enum": ["add", "subtract", "multiply", "divide", "power", "modulo"],
+                            "description": "The operation to perform"
+                        },
+                        "a": {
+                            "type": "number",
+                            "description": "The first operand"
+                        },
+                        "b": {
+                            "type": "number",
+                            "description": "The second operand"
+                        }
+                    },
+                    "required": ["operation", "a", "b"]
+                })),
+                output_schema: None,
+            },
+        }
+    }
+
+    /// Evaluate a full arithmetic expression
+    pub fn evaluate() -> Tool {
+        Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: "evaluate".to_string(),
+                description: Some(
+                    "Evaluate an arithmetic expression with + - * / and parentheses".to_string(),
+                ),
+                parameters: Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "expression": {
+                            "type": "string",
+                            "description": "The expression to evaluate, e.g. (3 + 4) * 2"
+                        }
+                    },
+                    "required": ["expression"]
+                })),
+                output_schema: None,
+            },
+        }
+    }
+
+    /// Look up a hat's details, supply, and status on chain
+    pub fn hat_lookup() -> Tool {
+        Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: "hat_lookup".to_string(),
+                description: Some(
+                    "Look up a Hats Protocol hat by ID and return its details, supply, and status"
+                        .to_string(),
+                ),
+                parameters: Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "hatId": {
+                            "type": "string",
+                            "description": "The hat ID as a decimal or 0x-prefixed hex string"
+                        }
+                    },
+                    "required": ["hatId"]
+                })),
+                output_schema: Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "hatId": { "type": "string" },
+                        "details": { "type": "string" },
+                        "maxSupply": { "type": "integer" },
+                        "supply": { "type": "integer" },
+                        "active": { "type": "boolean" },
+                        "mutable": { "type": "boolean" },
+                        "eligibility": { "type": "string" },
+                        "toggle": { "type": "string" },
+                        "imageURI": { "type": "string" }
+                    }
+                })),
+            },
+        }
+    }
+
+    /// Position of a hat in its tree, computed from the bits of its ID
+    pub fn hat_id_tools() -> Tool {
+        Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: "hat_id".to_string(),
+                description: Some(
+                    "Decode a Hats Protocol hat ID into its top hat domain, its level in the \
+                     tree, and the ID of its admin hat one level up. Does not read the chain."
+                        .to_string(),
+                ),
+                parameters: Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "hatId": {
+                            "type": "string",
+                            "description": "The hat ID as a decimal or 0x-prefixed hex string"
+                        }
+                    },
+                    "required": ["hatId"]
+                })),
+                output_schema: None,
+            },
+        }
+    }
+
+    /// Fetch text or JSON from an allowlisted URL
+    pub fn http_get() -> Tool {
+        Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: "http_get".to_string(),
+                description: Some(
+                    "Fetch the body of an HTTP(S) URL, such as a JSON API response. \
+                     Only allowlisted domains can be fetched."
+                        .to_string(),
+                ),
+                parameters: Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "url": {
+                            "type": "string",
+                            "description": "The http:// or https:// URL to fetch"
+                        }
+                    },
+                    "required": ["url"]
+                })),
+                output_schema: None,
+            },
+        }
+    }
+}
+
+/// Execution of tool calls requested by the model
+pub mod handlers {
+    use super::ToolContext;
+    use crate::expression;
+    use crate::llm::{Tool, ToolResult};
+    use alloy_primitives::U256;
+    use serde_json::{json, Value};
+    use url::Url;
+    use wavs_hats_common::evm::HatView;
+    use wavs_hats_common::{hats, http};
+
+    /// Check tool call arguments against the tool's `parameters` JSON schema
+    ///
+    /// Supports the subset of JSON schema used by our tools: an object with
+    /// `required` fields and per-property `type` and `enum` constraints.
+    pub fn validate_arguments(tool: &Tool, args: &Value) -> Result<(), String> {
+        let name = &tool.function.name;
+        let Some(schema) = &tool.function.parameters else {
+            return Ok(());
+        };
+
+        let args = args
+            .as_object()
+            .ok_or_else(|| format!("Arguments for {} must be a JSON object", name))?;
+
+        if let Some(required) = schema["required"].as_array() {
+            for field in required.iter().filter_map(Value::as_str) {
+                if args.get(field).map_or(true, Value::is_null) {
+                    return Err(format!("Missing required argument '{}' for {}", field, name));
+                }
+            }
+        }
+
+        let Some(properties) = schema["properties"].as_object() else {
+            return Ok(());
+        };
+
+        for (field, value) in args {
+            let Some(property) = properties.get(field) else {
+                continue;
+            };
+
+            if let Some(expected) = property["type"].as_str() {
+                let matches = match expected {
+                    "string" => value.is_string(),
+                    "number" => value.is_number(),
+                    "integer" => value.is_i64() || value.is_u64(),
+                    "boolean" => value.is_boolean(),
+                    "object" => value.is_object(),
+                    "array" => value.is_array(),
+                    _ => true,
+                };
+                if !matches {
+                    return Err(format!(
+                        "Argument '{}' for {} must be of type {}, got {}",
+                        field, name, expected, value
+                    ));
+                }
+            }
+
+            if let Some(allowed) = property["enum"].as_array() {
+                if !allowed.contains(value) {
+                    return Err(format!(
+                        "Argument '{}' for {} must be one of {}, got {}",
+                        field,
+                        name,
+                        Value::Array(allowed.clone()),
+                        value
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Arguments have been validated against `builders::calculator`
+    pub fn execute_calculator(args: &Value) -> Result<String, String> {
+        let operation = args["operation"].as_str().unwrap_or_default();
+        let a = args["a"].as_f64().unwrap_or_default();
+        let b = args["b"].as_f64().unwrap_or_default();
+
+        let (symbol, result) = match operation {
+            "add" => ("+", a + b),
+            "subtract" => ("-", a - b),
+            "multiply" => ("*", a * b),
+            "divide" => {
+                if b == 0.0 {
+                    return Err("Cannot divide by zero".to_string());
+                }
+                ("/", a / b)
+            }
+            "power" => ("^", a.powf(b)),
+            "modulo" => {
+                if b == 0.0 {
+                    return Err("Cannot take modulo by zero".to_string());
+                }
+                ("%", a % b)
+            }
+            _ => return Err(format!("Unknown operation: {}", operation)),
+        };
+
+        Ok(format!("The result of {} {} {} is {}", a, symbol, b, result))
+    }
+
+    /// Arguments have been validated against `builders::evaluate`
+    pub fn execute_evaluate(args: &Value) -> Result<String, String> {
+        let expression = args["expression"].as_str().unwrap_or_default();
+        let result = expression::evaluate(expression)?;
+        Ok(format!("The result of {} is {}", expression, result))
+    }
+
+    /// Arguments have been validated against `builders::hat_lookup`
+    ///
+    /// Returns a summary of the hat along with its fields as JSON.
+    pub async fn execute_hat_lookup(args: &Value, ctx: &ToolContext) -> Result<ToolResult, String> {
+        let hat_id = parse_hat_id(args)?;
+
+        let hat = ctx.cache()?.get_or_fetch(hat_id).await?;
+        Ok(ToolResult {
+            text: Some(format_hat_summary(hat_id, &hat)),
+            json: Some(hat_json(hat_id, &hat)),
+        })
+    }
+
+    /// Arguments have been validated against `builders::hat_id_tools`
+    ///
+    /// Follows the Hats ID layout, see [`wavs_hats_common::hats`]: a 32 bit
+    /// domain followed by 14 levels of 16 bits.
+    pub fn execute_hat_id(args: &Value) -> Result<String, String> {
+        let hat_id = parse_hat_id(args)?;
+        if hats::domain(hat_id) == 0 {
+            return Err(format!("Invalid hat ID {:#x}: its top hat domain is 0", hat_id));
+        }
+
+        let admin = if hats::is_top_hat(hat_id) {
+            "none, it is a top hat".to_string()
+        } else {
+            format!("{:#x}", hats::admin(hat_id))
+        };
+        Ok(format!(
+            "Hat {:#x}: domain: {}, level: {} of {}, admin: {}",
+            hat_id,
+            hats::domain(hat_id),
+            hats::level(hat_id),
+            hats::MAX_LEVELS,
+            admin
+        ))
+    }
+
+    /// The `hatId` argument, decimal or 0x-prefixed hex
+    fn parse_hat_id(args: &Value) -> Result<U256, String> {
+        let raw_id = args["hatId"].as_str().unwrap_or_default();
+        raw_id.trim().parse().map_err(|e| format!("Invalid hat ID {}: {}", raw_id, e))
+    }
+
+    /// Largest response body returned to the model by `http_get`, in bytes
+    pub const MAX_HTTP_TOOL_BYTES: usize = 16 * 1024;
+
+    /// Arguments have been validated against `builders::http_get`
+    pub async fn execute_http_get(args: &Value) -> Result<String, String> {
+        let url = check_allowlisted(args["url"].as_str().unwrap_or_default(), &http_allowlist())?;
+        // One byte past the limit tells a body that was cut from one that fits
+        let body = http::get_prefix(url.as_str(), MAX_HTTP_TOOL_BYTES + 1).await?;
+        Ok(truncate_body(&body, MAX_HTTP_TOOL_BYTES))
+    }
+
+    /// Domains `http_get` may fetch, from the comma separated `WAVS_ENV_HTTP_TOOL_ALLOWLIST`
+    fn http_allowlist() -> Vec<String> {
+        std::env::var("WAVS_ENV_HTTP_TOOL_ALLOWLIST")
+            .unwrap_or_default()
+            .split(',')
+            .map(|domain| domain.trim().trim_start_matches("*.").to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect()
+    }
+
+    /// Parse `raw` and check its host is an allowlisted domain or a subdomain of one
+    fn check_allowlisted(raw: &str, allowlist: &[String]) -> Result<Url, String> {
+        let url = Url::parse(raw).map_err(|e| format!("Invalid URL {}: {}", raw, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("Only http and https URLs can be fetched, got {}", raw));
+        }
+
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        let allowed = allowlist
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)));
+        if !allowed {
+            return Err(format!(
+                "Domain {} is not allowed. Allowed domains: {}",
+                host,
+                if allowlist.is_empty() { "none".to_string() } else { allowlist.join(", ") }
+            ));
+        }
+
+        Ok(url)
+    }
+
+    /// Decode `body` as text, cutting it to at most `max_bytes` with a marker
+    ///
+    /// `body` may itself be the start of a longer response, so the marker
+    /// gives the length kept rather than the full length.
+    fn truncate_body(body: &[u8], max_bytes: usize) -> String {
+        if body.len() <= max_bytes {
+            return String::from_utf8_lossy(body).into_owned();
+        }
+
+        let text = String::from_utf8_lossy(&body[..max_bytes]);
+        // Drop a multi-byte character split by the cut
+        let text = text.trim_end_matches(char::REPLACEMENT_CHARACTER);
+        format!("{}\n[truncated after {} bytes]", text, max_bytes)
+    }
+
+    /// Fields of a hat, as described by the output schema of `builders::hat_lookup`
+    fn hat_json(hat_id: U256, hat: &HatView) -> Value {
+        json!({
+            "hatId": format!("{:#x}", hat_id),
+            "details": hat.details,
+            "maxSupply": hat.max_supply,
+            "supply": hat.supply,
+            "active": hat.active,
+            "mutable": hat.mutable,
+            "eligibility": hat.eligibility.to_string(),
+            "toggle": hat.toggle.to_string(),
+            "imageURI": hat.image_uri,
+        })
+    }
+
+    fn format_hat_summary(hat_id: U256, hat: &HatView) -> String {
+        format!(
+            "Hat {:#x}: details: \"{}\", max supply: {}, current supply: {}, active: {}, \
+             mutable: {}, eligibility module: {}, toggle module: {}, image URI: \"{}\"",
+            hat_id,
+            hat.details,
+            hat.max_supply,
+            hat.supply,
+            hat.active,
+            hat.mutable,
+            hat.eligibility,
+            hat.toggle,
+            hat.image_uri
+        )
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::llm::{ToolCall, ToolCallFunction};
+        use crate::registry::ToolRegistry;
+        use crate::tools::builders;
+        use alloy_primitives::Address;
+        use alloy_sol_types::SolCall;
+        use wstd::runtime::block_on;
+
+        async fn execute_tool_call(
+            tool_call: &ToolCall,
+            ctx: &ToolContext,
+        ) -> Result<String, String> {
+            ToolRegistry::builtin().dispatch(tool_call, ctx).await.map(|result| result.content())
+        }
+
+        fn calculator_call(arguments: &str) -> ToolCall {
+            ToolCall {
+                id: "call_1".to_string(),
+                tool_type: "function".to_string(),
+                function: ToolCallFunction {
+                    name: "calculator".to_string(),
+                    arguments: arguments.to_string(),
+                },
+            }
+        }
+
+        #[test]
+        fn test_execute_calculator() {
+            let call = calculator_call(r#"{"operation":"multiply","a":6,"b":7}"#);
+            assert_eq!(
+                block_on(execute_tool_call(&call, &ToolContext::default())).unwrap(),
+                "The result of 6 * 7 is 42"
+            );
+
+            let call = calculator_call(r#"{"operation":"divide","a":1,"b":0}"#);
+            assert_eq!(
+                block_on(execute_tool_call(&call, &ToolContext::default())).unwrap_err(),
+                "Cannot divide by zero"
+            );
+        }
+
+        #[test]
+        fn test_calculator_power_and_modulo() {
+            let call = calculator_call(r#"{"operation":"power","a":9,"b":0.5}"#);
+            assert_eq!(
+                block_on(execute_tool_call(&call, &ToolContext::default())).unwrap(),
+                "The result of 9 ^ 0.5 is 3"
+            );
+
+            let call = calculator_call(r#"{"operation":"modulo","a":7,"b":3}"#);
+            assert_eq!(
+                block_on(execute_tool_call(&call, &ToolContext::default())).unwrap(),
+                "The result of 7 % 3 is 1"
+            );
+
+            let call = calculator_call(r#"{"operation":"modulo","a":7,"b":0}"#);
+            assert_eq!(
+                block_on(execute_tool_call(&call, &ToolContext::default())).unwrap_err(),
+                "Cannot take modulo by zero"
+            );
+        }
+
+        #[test]
+        fn test_execute_evaluate() {
+            let mut call = calculator_call(r#"{"expression":"(3 + 4) * 2"}"#);
+            call.function.name = "evaluate".to_string();
+            assert_eq!(
+                block_on(execute_tool_call(&call, &ToolContext::default())).unwrap(),
+                "The result of (3 + 4) * 2 is 14"
+            );
+
+            call.function.arguments = r#"{"expression":"3 +* 4"}"#.to_string();
+            assert!(block_on(execute_tool_call(&call, &ToolContext::default()))
+                .unwrap_err()
+                .starts_with("Unexpected '*'"));
+        }
+
+        #[test]
+        fn test_execute_hat_id() {
+            let mut call = calculator_call(
+                r#"{"hatId":"0x0000000100010002000000000000000000000000000000000000000000000000"}"#,
+            );
+            call.function.name = "hat_id".to_string();
+            assert_eq!(
+                block_on(execute_tool_call(&call, &ToolContext::default())).unwrap(),
+                "Hat 0x100010002000000000000000000000000000000000000000000000000: domain: 1, \
+                 level: 2 of 14, admin: 0x100010000000000000000000000000000000000000000000000000000"
+            );
+
+            // Top hat 1, as a decimal ID
+            call.function.arguments = format!(r#"{{"hatId":"{}"}}"#, U256::from(1) << 224);
+            assert_eq!(
+                block_on(execute_tool_call(&call, &ToolContext::default())).unwrap(),
+                "Hat 0x100000000000000000000000000000000000000000000000000000000: domain: 1, \
+                 level: 0 of 14, admin: none, it is a top hat"
+            );
+
+            call.function.arguments = r#"{"hatId":"1"}"#.to_string();
+            assert!(block_on(execute_tool_call(&call, &ToolContext::default()))
+                .unwrap_err()
+                .contains("top hat domain is 0"));
+        }
+
+        #[test]
+        fn test_validate_missing_required_field() {
+            let args = serde_json::json!({ "operation": "add", "a": 1 });
+            let err = validate_arguments(&builders::calculator(), &args).unwrap_err();
+            assert_eq!(err, "Missing required argument 'b' for calculator");
+
+            let call = calculator_call(r#"{"operation":"add","a":1}"#);
+            assert!(block_on(execute_tool_call(&call, &ToolContext::default()))
+                .unwrap_err()
+                .contains("'b'"));
+        }
+
+        #[test]
+        fn test_validate_wrong_type_and_enum() {
+            let args = serde_json::json!({ "operation": "add", "a": "one", "b": 2 });
+            let err = validate_arguments(&builders::calculator(), &args).unwrap_err();
+            assert!(err.starts_with("Argument 'a' for calculator must be of type number"));
+
+            let args = serde_json::json!({ "operation": "sqrt", "a": 1, "b": 2 });
+            let err = validate_arguments(&builders::calculator(), &args).unwrap_err();
+            assert!(err.starts_with("Argument 'operation' for calculator must be one of"));
+
+            let err = validate_arguments(&builders::calculator(), &serde_json::json!([1, 2]));
+            assert!(err.unwrap_err().contains("must be a JSON object"));
+        }
+
+        #[test]
+        fn test_format_hat_summary() {
+            // Encoded the way the Hats contract returns it, standing in for the RPC call
+            let encoded = wavs_hats_common::evm::IHats::viewHatCall::abi_encode_returns(&(
+                "Treasury Signer".to_string(),
+                5u32,
+                2u32,
+                Address::repeat_byte(0x11),
+                Address::repeat_byte(0x22),
+                "ipfs://bafy".to_string(),
+                0u16,
+                true,
+                true,
+            ));
+            let hat = wavs_hats_common::evm::decode_view_hat(&encoded).unwrap();
+
+            let summary = format_hat_summary(U256::from(1) << 224, &hat);
+            assert!(summary.starts_with(
+                "Hat 0x100000000000000000000000000000000000000000000000000000000: \
+                 details: \"Treasury Signer\", max supply: 5, current supply: 2, active: true"
+            ));
+            assert!(summary.contains("image URI: \"ipfs://bafy\""));
+        }
+
+        #[test]
+        fn test_hat_lookup_invalid_id() {
+            let result = block_on(execute_hat_lookup(
+                &serde_json::json!({ "hatId": "hat-one" }),
+                &ToolContext::default(),
+            ));
+            assert!(result.unwrap_err().starts_with("Invalid hat ID hat-one"));
+        }
+
+        #[test]
+        fn test_http_allowlist() {
+            let allowlist = vec!["snapshot.org".to_string(), "api.tally.xyz".to_string()];
+
+            assert!(check_allowlisted("https://snapshot.org/api", &allowlist).is_ok());
+            assert!(check_allowlisted("https://hub.snapshot.org/graphql", &allowlist).is_ok());
+            assert!(check_allowlisted("https://API.tally.xyz/query", &allowlist).is_ok());
+
+            let err = check_allowlisted("https://evilsnapshot.org/api", &allowlist).unwrap_err();
+            assert_eq!(
+                err,
+                "Domain evilsnapshot.org is not allowed. Allowed domains: snapshot.org, api.tally.xyz"
+            );
+            assert!(check_allowlisted("https://tally.xyz", &allowlist).is_err());
+            assert!(check_allowlisted("file:///etc/passwd", &allowlist)
+                .unwrap_err()
+                .starts_with("Only http and https"));
+            assert!(check_allowlisted("not a url", &allowlist)
+                .unwrap_err()
+                .starts_with("Invalid URL"));
+            assert!(check_allowlisted("https://snapshot.org", &[]).unwrap_err().ends_with("none"));
+        }
+
+        #[test]
+        fn test_truncate_body() {
+            assert_eq!(truncate_body(b"{\"ok\":true}", 64), "{\"ok\":true}");
+            assert_eq!(truncate_body(b"abcdef", 4), "abcd\n[truncated after 4 bytes]");
+            // The cut falls inside the two-byte "\u{e9}"
+            assert_eq!(truncate_body("ab\u{e9}".as_bytes(), 3), "ab\n[truncated after 3 bytes]");
+        }
+
+        #[test]
+        fn test_unknown_tool() {
+            let mut call = calculator_call("{}");
+            call.function.name = "teleport".to_string();
+            assert_eq!(
+                block_on(execute_tool_call(&call, &ToolContext::default())).unwrap_err(),
+                "Unknown tool: teleport"
+            );
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Generated images for hats created without an image URI
+
+use wavs_hats_common::metadata::HatMetadata;
+use wavs_hats_common::warn;
+use wavs_hats_common::{env, image, ipfs};
+
+/// Size of generated hat images
+const IMAGE_SIZE: &str = "1024x1024";
+
+/// Largest generated image that is pinned
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+
+/// Image generation and storage, implemented by [`OpenAIPinata`] and by fakes in tests
+pub trait ImageService {
+    async fn generate(&self, prompt: &str) -> Result<Vec<u8>, String>;
+    async fn pin(&self, bytes: &[u8], name: &str) -> Result<String, String>;
+}
+
+/// Generates images with DALL-E and pins them with Pinata
+pub struct OpenAIPinata;
+
+impl ImageService for OpenAIPinata {
+    async fn generate(&self, prompt: &str) -> Result<Vec<u8>, String> {
+        image::generate(prompt, IMAGE_SIZE).await
+    }
+
+    async fn pin(&self, bytes: &[u8], name: &str) -> Result<String, String> {
+        ipfs::pin_bytes(bytes, name).await
+    }
+}
+
+/// Whether `WAVS_ENV_AUTO_IMAGE` allows generating images, disabled by default
+///
+/// DALL-E takes no seed, so every operator generates a different image, and
+/// one whose generation fails leaves the URI empty. Operators then sign
+/// different hat creations for the same trigger, so only enable this for a
+/// single operator.
+pub fn enabled() -> bool {
+    env::flag("WAVS_ENV_AUTO_IMAGE", false)
+}
+
+/// Image URI to create the hat with
+///
+/// A provided URI is kept. Otherwise, if `enabled`, an image is generated
+/// from `details` and pinned, see [`describe`]. Failures leave the URI empty so the hat is
+/// still created.
+pub async fn image_uri<S: ImageService>(
+    service: &S,
+    enabled: bool,
+    details: &str,
+    image_uri: String,
+) -> String {
+    if !image_uri.is_empty() || !enabled {
+        return image_uri;
+    }
+
+    let prompt = format!("An emblem for a role or badge described as: {}", describe(details));
+    let result = async {
+        let bytes = service.generate(&prompt).await?;
+        let info = image::validate(&bytes, MAX_IMAGE_BYTES)?;
+        let name = match info.format {
+            image::ImageFormat::Png => "hat.png",
+            image::ImageFormat::Jpeg => "hat.jpg",
+        };
+        service.pin(&bytes, name).await
+    }
+    .await;
+
+    match result {
+        Ok(cid) => ipfs::get_ipfs_url(&cid, None),
+        Err(e) => {
+            warn!("Failed to generate hat image, leaving imageURI empty: {}", e);
+            String::new()
+        }
+    }
+}
+
+/// Description of a hat for its image prompt
+///
+/// Details holding metadata JSON are described by their name and
+/// description, and other details are used as they are.
+fn describe(details: &str) -> String {
+    let Ok(metadata) = HatMetadata::from_json(details.as_bytes()) else {
+        return details.to_string();
+    };
+    let parts: Vec<&str> = [metadata.name.trim(), metadata.description.trim()]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect();
+    if parts.is_empty() {
+        details.to_string()
+    } else {
+        parts.join(": ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use wstd::runtime::block_on;
+
+    /// Signature and IHDR chunk of a 1x1 PNG
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x01\0\0\0\x01";
+
+    /// Records prompts and pins, optionally failing generation
+    #[derive(Default)]
+    struct FakeImageService {
+        fail: bool,
+        image: Option<Vec<u8>>,
+        prompts: RefCell<Vec<String>>,
+        pinned: RefCell<Vec<(Vec<u8>, String)>>,
+    }
+
+    impl ImageService for FakeImageService {
+        async fn generate(&self, prompt: &str) -> Result<Vec<u8>, String> {
+            self.prompts.borrow_mut().push(prompt.to_string());
+            if self.fail {
+                return Err("API error: status 500".to_string());
+            }
+            Ok(self.image.clone().unwrap_or_else(|| PNG.to_vec()))
+        }
+
+        async fn pin(&self, bytes: &[u8], name: &str) -> Result<String, String> {
+            self.pinned.borrow_mut().push((bytes.to_vec(), name.to_string()));
+            Ok("bafkreihat".to_string())
+        }
+    }
+
+    #[test]
+    fn test_auto_generate() {
+        let service = FakeImageService::default();
+        let uri = block_on(image_uri(&service, true, "Treasury Signer", String::new()));
+        assert_eq!(uri, "ipfs://bafkreihat");
+        assert!(service.prompts.borrow()[0].contains("Treasury Signer"));
+        assert_eq!(*service.pinned.borrow(), vec![(PNG.to_vec(), "hat.png".to_string())]);
+    }
+
+    #[test]
+    fn test_describe_metadata_details() {
+        assert_eq!(describe("Treasury Signer"), "Treasury Signer");
+        assert_eq!(
+            describe(r#"{"name": "Treasury Signer", "description": "Signs payouts"}"#),
+            "Treasury Signer: Signs payouts"
+        );
+        assert_eq!(describe(r#"{"name": "Treasury Signer"}"#), "Treasury Signer");
+        assert_eq!(describe("{}"), "{}");
+    }
+
+    #[test]
+    fn test_opt_out_and_provided_uri() {
+        let service = FakeImageService::default();
+        assert_eq!(block_on(image_uri(&service, false, "Signer", String::new())), "");
+
+        let uri = block_on(image_uri(&service, true, "Signer", "ipfs://bafy".to_string()));
+        assert_eq!(uri, "ipfs://bafy");
+        assert!(service.prompts.borrow().is_empty());
+
+        // Inline images are kept without pinning
+        let data_uri = image::encode_data_uri(PNG, "image/png");
+        let uri = block_on(image_uri(&service, true, "Signer", data_uri.clone()));
+        assert_eq!(uri, data_uri);
+        assert!(service.pinned.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_generation_failure_leaves_uri_empty() {
+        let service = FakeImageService { fail: true, ..Default::default() };
+        assert_eq!(block_on(image_uri(&service, true, "Signer", String::new())), "");
+        assert!(service.pinned.borrow().is_empty());
+
+        // Invalid images are never pinned
+        let service = FakeImageService { image: Some(b"<html>".to_vec()), ..Default::default() };
+        assert_eq!(block_on(image_uri(&service, true, "Signer", String::new())), "");
+        assert!(service.pinned.borrow().is_empty());
+    }
+}
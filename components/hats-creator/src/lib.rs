@@ -1,64 +1,109 @@
+mod auto_image;
 #[allow(warnings)]
 mod bindings;
+mod validation;
+
 use alloy_sol_types::{sol, SolValue};
-use bindings::{
-    export,
-    wavs::worker::layer_types::{TriggerData, TriggerDataEthContractEvent},
-    Guest, TriggerAction,
-};
-use wavs_wasi_chain::{decode_event_log_data, ethereum::alloy_primitives::Uint};
+use bindings::{export, Guest, TriggerAction};
+use validation::CreationTarget;
+use wavs_hats_common::{debug, info};
+use wavs_hats_common::{dry_run, idempotency};
+use wavs_wasi_chain::ethereum::alloy_primitives::Uint;
+use wstd::runtime::block_on;
 
-sol!("../../src/interfaces/IHatsAvsTypes.sol");
+sol!(
+    #[sol(all_derives)]
+    "../../src/interfaces/IHatsAvsTypes.sol"
+);
 
 struct Component;
 
 impl Guest for Component {
     fn run(trigger_action: TriggerAction) -> std::result::Result<Option<Vec<u8>>, String> {
-        match trigger_action.data {
-            TriggerData::EthContractEvent(TriggerDataEthContractEvent { log, .. }) => {
-                // Decode the HatCreationTrigger event
-                let IHatsAvsTypes::HatCreationTrigger {
-                    triggerId,
-                    creator,
-                    admin,
-                    details,
-                    maxSupply,
-                    eligibility,
-                    toggle,
-                    mutable_,
-                    imageURI,
-                } = decode_event_log_data!(log)
-                    .map_err(|e| format!("Failed to decode event log data: {}", e))?;
+        // Decode the HatCreationTrigger event
+        let (event, _) = wavs_hats_common::decode_trigger!(
+            trigger_action.data,
+            IHatsAvsTypes::HatCreationTrigger
+        )?;
+        let dry_run = dry_run::enabled();
+        if dry_run {
+            dry_run::log("input", &event);
+        }
+        let IHatsAvsTypes::HatCreationTrigger {
+            triggerId,
+            creator,
+            admin,
+            details,
+            maxSupply,
+            eligibility,
+            toggle,
+            mutable_,
+            imageURI,
+        } = event;
 
-                eprintln!("Successfully decoded hat creation trigger");
-                eprintln!("Trigger ID: {}", u64::from(triggerId));
-                eprintln!("Creator: {}", creator);
-                eprintln!("Admin hat ID: {}", admin);
-                eprintln!("Details: {}", details);
-                eprintln!("Max supply: {}", maxSupply);
+        info!("Successfully decoded hat creation trigger");
+        info!("Trigger ID: {}", triggerId);
+        if let Some(output) = idempotency::cached(env!("CARGO_PKG_NAME"), triggerId) {
+            return Ok(Some(output));
+        }
+        info!("Creator: {}", creator);
+        info!("Admin hat ID: {}", admin);
+        debug!("Details: {}", details);
+        debug!("Max supply: {}", maxSupply);
 
-                // Create HatCreationData with the extracted data
-                let result = IHatsAvsTypes::HatCreationData {
-                    admin,
-                    details,
-                    maxSupply,
-                    eligibility,
-                    toggle,
-                    mutable_,
-                    imageURI,
-                    requestor: creator,
-                    hatId: Uint::from(0), // Filled in by the contract after creation
-                    success: true,
-                };
+        // Reject invalid parameters here rather than letting createHat revert
+        let target = CreationTarget::from_admin(admin);
+        if target == CreationTarget::TopHat {
+            info!("Request is for a new top hat");
+        }
+        let validation =
+            target.validate().and_then(|()| validation::validate(&details, maxSupply, &imageURI));
+        let (success, reason) = match validation {
+            Ok(()) => (true, String::new()),
+            Err(reason) => {
+                info!("Rejecting hat creation: {}", reason);
+                (false, reason)
+            }
+        };
 
-                // Log success message
-                eprintln!("Hat creation component successfully processed the trigger");
+        // Generate an image for valid requests without one
+        let image_uri = if success {
+            block_on(auto_image::image_uri(
+                &auto_image::OpenAIPinata,
+                auto_image::enabled(),
+                &details,
+                imageURI,
+            ))
+        } else {
+            imageURI
+        };
 
-                // Return the ABI-encoded result
-                Ok(Some(result.abi_encode()))
-            }
-            _ => Err("Unsupported trigger data".to_string()),
+        // Create HatCreationData with the extracted data
+        let mut result = IHatsAvsTypes::HatCreationData {
+            admin: target.admin(),
+            details,
+            maxSupply,
+            eligibility,
+            toggle,
+            mutable_,
+            imageURI: image_uri,
+            requestor: creator,
+            hatId: Uint::from(0), // Filled in by the contract after creation
+            success,
+            reason,
+        };
+        if dry_run {
+            dry_run::log("output", &result);
+            dry_run::simulate(&mut result.success, &mut result.reason);
         }
+
+        // Log success message
+        info!("Hat creation component successfully processed the trigger");
+
+        // Return the ABI-encoded result
+        let output = result.abi_encode();
+        idempotency::record(env!("CARGO_PKG_NAME"), triggerId, &output);
+        Ok(Some(output))
     }
 }
 
@@ -0,0 +1,142 @@
+//! Validation of hat creation requests before they reach the Hats contract
+
+use wavs_hats_common::{hats, image};
+use wavs_wasi_chain::ethereum::alloy_primitives::U256;
+
+/// Longest `details` or `imageURI` the Hats contract accepts, in bytes
+pub const MAX_STRING_LENGTH: usize = 7000;
+
+/// Check the parameters of a hat creation request, returning the reason it
+/// is rejected
+pub fn validate(details: &str, max_supply: u32, image_uri: &str) -> Result<(), String> {
+    if details.trim().is_empty() {
+        return Err("details must not be empty".to_string());
+    }
+    if details.len() > MAX_STRING_LENGTH {
+        return Err(format!("details must be at most {} bytes", MAX_STRING_LENGTH));
+    }
+    if max_supply == 0 {
+        return Err("maxSupply must be greater than 0".to_string());
+    }
+    if image_uri.len() > MAX_STRING_LENGTH {
+        return Err(format!("imageURI must be at most {} bytes", MAX_STRING_LENGTH));
+    }
+    // Inline images are stored as given, without pinning
+    if image_uri.starts_with("data:") {
+        return image::decode_data_uri(image_uri)
+            .map(|_| ())
+            .map_err(|e| format!("imageURI must be a valid data URI: {}", e));
+    }
+    if !image_uri.is_empty() && !is_valid_uri(image_uri) {
+        return Err(format!(
+            "imageURI must be an ipfs://, https:// or data: URI, got {}",
+            image_uri
+        ));
+    }
+    Ok(())
+}
+
+/// Hat a creation request asks for, decided by its admin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreationTarget {
+    /// A new top hat, requested with admin 0
+    TopHat,
+    /// A child of `admin`, with a bare top hat domain expanded like the minter does
+    Child { admin: U256 },
+}
+
+impl CreationTarget {
+    pub fn from_admin(admin: U256) -> Self {
+        if admin.is_zero() {
+            Self::TopHat
+        } else {
+            Self::Child { admin: hats::expand_top_hat_domain(admin) }
+        }
+    }
+
+    /// Admin hat ID submitted with the result, 0 for a top hat
+    pub fn admin(&self) -> U256 {
+        match self {
+            Self::TopHat => U256::ZERO,
+            Self::Child { admin } => *admin,
+        }
+    }
+
+    /// Check the request can be created, returning the reason it is rejected
+    ///
+    /// The hatter only creates child hats, so a top hat request is rejected
+    /// instead of reverting in `createHat`.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::TopHat => Err("top hats cannot be created by the hatter, mint them with \
+                 mintTopHat and request hats under them"
+                .to_string()),
+            Self::Child { admin } if hats::level(*admin) >= hats::MAX_LEVELS => {
+                Err(format!("admin hat {:#x} is at the deepest level", admin))
+            }
+            Self::Child { .. } => Ok(()),
+        }
+    }
+}
+
+fn is_valid_uri(uri: &str) -> bool {
+    let rest = uri.strip_prefix("ipfs://").or_else(|| uri.strip_prefix("https://"));
+    rest.is_some_and(|rest| !rest.is_empty() && !rest.chars().any(char::is_whitespace))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_request() {
+        assert!(validate("Treasury Signer", 5, "").is_ok());
+        assert!(validate("Treasury Signer", 1, "ipfs://bafybeigdyrzt").is_ok());
+        assert!(validate("Treasury Signer", 1, "https://example.com/hat.png").is_ok());
+        assert!(validate("Treasury Signer", 1, "data:image/png;base64,iVBORw0KGgo=").is_ok());
+    }
+
+    #[test]
+    fn test_empty_details() {
+        assert_eq!(validate("  ", 5, "").unwrap_err(), "details must not be empty");
+        assert!(validate(&"a".repeat(MAX_STRING_LENGTH + 1), 5, "").is_err());
+    }
+
+    #[test]
+    fn test_zero_max_supply() {
+        assert_eq!(validate("Signer", 0, "").unwrap_err(), "maxSupply must be greater than 0");
+    }
+
+    #[test]
+    fn test_top_hat_and_child_hat_targets() {
+        let top_hat = CreationTarget::from_admin(U256::ZERO);
+        assert_eq!(top_hat, CreationTarget::TopHat);
+        assert_eq!(top_hat.admin(), U256::ZERO);
+        assert!(top_hat.validate().unwrap_err().starts_with("top hats cannot be created"));
+
+        // A bare domain names its top hat, as for mints
+        let child = CreationTarget::from_admin(U256::from(1));
+        assert_eq!(child, CreationTarget::Child { admin: U256::from(1) << 224 });
+        assert_eq!(child.validate(), Ok(()));
+
+        let admin = hats::admin(U256::MAX);
+        assert_eq!(CreationTarget::from_admin(admin).admin(), admin);
+        assert_eq!(CreationTarget::from_admin(admin).validate(), Ok(()));
+        let err = CreationTarget::from_admin(U256::MAX).validate().unwrap_err();
+        assert!(err.ends_with("is at the deepest level"));
+    }
+
+    #[test]
+    fn test_malformed_image_uri() {
+        for uri in [
+            "http://example.com/hat.png",
+            "ipfs://",
+            "hat.png",
+            "https://example.com/a b",
+            "data:image/png;base64,not base64",
+            "data:image/png,raw",
+        ] {
+            assert!(validate("Signer", 1, uri).unwrap_err().starts_with("imageURI must be"));
+        }
+    }
+}
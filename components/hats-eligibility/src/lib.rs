@@ -1,47 +1,73 @@
 #[allow(warnings)]
 mod bindings;
-use alloy_sol_types::{sol, SolValue};
-use bindings::{
-    export,
-    wavs::worker::layer_types::{TriggerData, TriggerDataEthContractEvent},
-    Guest, TriggerAction,
-};
-use wavs_wasi_chain::decode_event_log_data;
 
-sol!("../../src/interfaces/IHatsAvsTypes.sol");
+use alloy_rpc_types::BlockId;
+use alloy_sol_types::sol;
+use bindings::{export, Guest, TriggerAction};
+use wavs_hats_common::eligibility::{self, Criteria};
+use wavs_hats_common::info;
+use wavs_hats_common::{dry_run, evm, idempotency};
+use wstd::runtime::block_on;
+
+sol!(
+    #[sol(all_derives)]
+    "../../src/interfaces/IHatsAvsTypes.sol"
+);
 
 struct Component;
 
 impl Guest for Component {
     fn run(trigger_action: TriggerAction) -> std::result::Result<Option<Vec<u8>>, String> {
-        match trigger_action.data {
-            TriggerData::EthContractEvent(TriggerDataEthContractEvent { log, .. }) => {
-                // Decode the EligibilityCheckTrigger event
-                let event: IHatsAvsTypes::EligibilityCheckTrigger = decode_event_log_data!(log)
-                    .map_err(|e| format!("Failed to decode event log data: {}", e))?;
-
-                // For this simplified implementation, we're just setting:
-                // eligible = true and standing = true
-                let eligible = true;
-                let standing = true;
-
-                // Create EligibilityResult with the proper triggerId from decoded data
-                let result = IHatsAvsTypes::EligibilityResult {
-                    triggerId: event.triggerId,
-                    eligible,
-                    standing,
-                    wearer: event.wearer,
-                    hatId: event.hatId,
-                };
-
-                // Log success message
-                eprintln!("Processed TriggerId: {}", event.triggerId);
-
-                // Return the ABI-encoded result
-                Ok(Some(result.abi_encode()))
-            }
-            _ => Err("Unsupported trigger data".to_string()),
+        // Decode the EligibilityCheckTrigger event
+        let (event, block_height) = wavs_hats_common::decode_trigger!(
+            trigger_action.data,
+            IHatsAvsTypes::EligibilityCheckTrigger
+        )?;
+        let dry_run = dry_run::enabled();
+        if dry_run {
+            dry_run::log("input", &event);
+        }
+        if let Some(output) = idempotency::cached(env!("CARGO_PKG_NAME"), event.triggerId) {
+            return Ok(Some(output));
+        }
+
+        // Evaluate the wearer against the configured criteria, pinned to the trigger block
+        let criteria = Criteria::from_env()?;
+        let block = BlockId::number(block_height);
+        let provider = if criteria.needs_provider() {
+            Some(wavs_hats_common::eth_provider!(&evm::hats_chain())?)
+        } else {
+            None
+        };
+        let decision = block_on(eligibility::evaluate(
+            &criteria,
+            provider.as_ref(),
+            event.wearer,
+            event.hatId,
+            block,
+        ))?;
+        if let Some(reason) = &decision.reason {
+            info!("Wearer {} is ineligible: {}", event.wearer, reason);
+        }
+
+        // Create EligibilityResult with the proper triggerId from decoded data
+        let result = IHatsAvsTypes::EligibilityResult {
+            triggerId: event.triggerId,
+            eligible: decision.eligible,
+            standing: decision.standing,
+            wearer: event.wearer,
+            hatId: event.hatId,
+        };
+
+        // Log success message
+        info!("Processed TriggerId: {}", event.triggerId);
+
+        // Return the ABI-encoded result
+        let output = dry_run::output(&result, dry_run);
+        if let Some(output) = &output {
+            idempotency::record(env!("CARGO_PKG_NAME"), event.triggerId, output);
         }
+        Ok(output)
     }
 }
 
@@ -0,0 +1,235 @@
+//! Checks a mint request must pass before the minter approves it
+
+use alloy_network::Ethereum;
+use alloy_primitives::{Address, U256};
+use alloy_provider::RootProvider;
+use alloy_rpc_types::BlockId;
+use alloy_sol_types::SolCall;
+use wavs_hats_common::eligibility::{self, Criteria};
+use wavs_hats_common::evm::{self, HatView, IHats};
+
+/// Check whether `hat_id` can be minted to `wearer` as of `block`
+///
+/// Reads the hat, the wearer's current status and their eligibility in a
+/// single multicall, then evaluates the wearer against `criteria`. Returns
+/// the reason the mint is rejected, if any.
+pub async fn rejection_reason(
+    provider: &RootProvider<Ethereum>,
+    criteria: &Criteria,
+    wearer: Address,
+    hat_id: U256,
+    block: BlockId,
+) -> Result<Option<String>, String> {
+    let mut reasons = rejection_reasons(provider, criteria, &[wearer], hat_id, block).await?;
+    Ok(reasons.pop().flatten())
+}
+
+/// Check whether `hat_id` can be minted to each of `wearers` as of `block`
+///
+/// Reads the hat once and the status and eligibility of every wearer in a
+/// single multicall. Wearers that pass those checks are then evaluated
+/// against `criteria`, the same criteria the eligibility component applies.
+/// Approved wearers count towards the hat's supply in order, so the wearers
+/// past its max supply are rejected, as are repeated wearers. Returns the
+/// rejection reason of each wearer, in order.
+pub async fn rejection_reasons(
+    provider: &RootProvider<Ethereum>,
+    criteria: &Criteria,
+    wearers: &[Address],
+    hat_id: U256,
+    block: BlockId,
+) -> Result<Vec<Option<String>>, String> {
+    let hats = evm::hats_address()?;
+    let mut calls = vec![(hats, IHats::viewHatCall { _hatId: hat_id }.abi_encode())];
+    for &wearer in wearers {
+        calls.push((hats, IHats::isWearerOfHatCall { _user: wearer, _hatId: hat_id }.abi_encode()));
+        calls.push((hats, IHats::isEligibleCall { _wearer: wearer, _hatId: hat_id }.abi_encode()));
+    }
+
+    let results = evm::multicall(provider, &calls, block).await?;
+    if results.len() != calls.len() {
+        return Err(format!("Expected {} multicall results, got {}", calls.len(), results.len()));
+    }
+
+    let hat = evm::decode_view_hat(&results[0])?;
+    let mut supply = hat.supply;
+    let mut reasons = Vec::with_capacity(wearers.len());
+    for (i, (wearer, status)) in wearers.iter().zip(results[1..].chunks(2)).enumerate() {
+        let is_wearer = IHats::isWearerOfHatCall::abi_decode_returns(&status[0], false)
+            .map_err(|e| format!("Failed to decode isWearerOfHat result: {}", e))?
+            .isWearer;
+        let eligible = IHats::isEligibleCall::abi_decode_returns(&status[1], false)
+            .map_err(|e| format!("Failed to decode isEligible result: {}", e))?
+            .eligible;
+
+        let reason = if wearers[..i].contains(wearer) {
+            Some("duplicate wearer".to_string())
+        } else if let Some(reason) = check(&hat, supply, is_wearer, eligible) {
+            Some(reason)
+        } else {
+            eligibility::evaluate(criteria, Some(provider), *wearer, hat_id, block).await?.reason
+        };
+        if reason.is_none() {
+            supply = supply.saturating_add(1);
+        }
+        reasons.push(reason);
+    }
+    Ok(reasons)
+}
+
+/// Check a mint of `hat` while it has `supply` wearers
+fn check(hat: &HatView, supply: u32, is_wearer: bool, eligible: bool) -> Option<String> {
+    let reason = if !hat.active {
+        "hat not active"
+    } else if supply >= hat.max_supply {
+        "hat at max supply"
+    } else if is_wearer {
+        "wearer already wears hat"
+    } else if !eligible {
+        "wearer not eligible"
+    } else {
+        return None;
+    };
+    Some(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_sol_types::SolValue;
+    use evm::IMulticall3;
+    use serde_json::Value;
+    use wavs_hats_common::mock::MockTransport;
+    use wstd::runtime::block_on;
+
+    /// Answers the mint checks multicall for a hat with `supply` of 5
+    fn mock_chain(supply: u32, is_wearer: bool, eligible: bool) -> MockTransport {
+        mock_batch(supply, vec![(is_wearer, eligible)])
+    }
+
+    /// Like `mock_chain`, with whether each wearer wears the hat and is eligible
+    fn mock_batch(supply: u32, wearers: Vec<(bool, bool)>) -> MockTransport {
+        MockTransport::new(move |method, _| {
+            assert_eq!(method, "eth_call");
+            let hat = IHats::viewHatCall::abi_encode_returns(&(
+                "Signer".to_string(),
+                5u32,
+                supply,
+                Address::ZERO,
+                Address::ZERO,
+                String::new(),
+                0u16,
+                true,
+                true,
+            ));
+            let mut results = vec![IMulticall3::Result { success: true, returnData: hat.into() }];
+            for (is_wearer, eligible) in &wearers {
+                for value in [is_wearer, eligible] {
+                    results.push(IMulticall3::Result {
+                        success: true,
+                        returnData: value.abi_encode().into(),
+                    });
+                }
+            }
+            let encoded = IMulticall3::aggregate3Call::abi_encode_returns(&(results,));
+            Ok(Value::String(alloy_primitives::hex::encode_prefixed(encoded)))
+        })
+    }
+
+    fn reason(transport: MockTransport) -> Option<String> {
+        block_on(rejection_reason(
+            &transport.provider(),
+            &Criteria::default(),
+            Address::repeat_byte(0x01),
+            U256::from(1) << 224,
+            BlockId::number(1),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_eligible_wearer() {
+        assert_eq!(reason(mock_chain(2, false, true)), None);
+    }
+
+    #[test]
+    fn test_ineligible_wearer() {
+        assert_eq!(reason(mock_chain(2, false, false)).as_deref(), Some("wearer not eligible"));
+    }
+
+    #[test]
+    fn test_max_supply_and_existing_wearer() {
+        assert_eq!(reason(mock_chain(5, false, true)).as_deref(), Some("hat at max supply"));
+        assert_eq!(reason(mock_chain(2, true, true)).as_deref(), Some("wearer already wears hat"));
+    }
+
+    #[test]
+    fn test_batch_mixes_eligible_and_ineligible_wearers() {
+        let wearers = [
+            Address::repeat_byte(0x01),
+            Address::repeat_byte(0x02),
+            Address::repeat_byte(0x03),
+            Address::repeat_byte(0x01),
+            Address::repeat_byte(0x04),
+            Address::repeat_byte(0x05),
+        ];
+        // Three of the five seats are taken, so two more wearers can be approved
+        let transport = mock_batch(
+            3,
+            vec![
+                (false, true),
+                (false, false),
+                (true, true),
+                (false, true),
+                (false, true),
+                (false, true),
+            ],
+        );
+        let reasons = block_on(rejection_reasons(
+            &transport.provider(),
+            &Criteria::default(),
+            &wearers,
+            U256::from(1) << 224,
+            BlockId::number(1),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            reasons,
+            vec![
+                None,
+                Some("wearer not eligible".to_string()),
+                Some("wearer already wears hat".to_string()),
+                Some("duplicate wearer".to_string()),
+                None,
+                Some("hat at max supply".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_criteria_reject_wearer_after_chain_checks() {
+        let wearer = Address::repeat_byte(0x01);
+        let criteria = Criteria { revoked: vec![wearer], ..Criteria::default() };
+        let reasons = block_on(rejection_reasons(
+            &mock_batch(2, vec![(false, true), (false, true)]).provider(),
+            &criteria,
+            &[wearer, Address::repeat_byte(0x02)],
+            U256::from(1) << 224,
+            BlockId::number(1),
+        ))
+        .unwrap();
+        assert_eq!(reasons, vec![Some("wearer in bad standing".to_string()), None]);
+
+        // Failing the chain checks takes precedence over the criteria
+        let reason = block_on(rejection_reason(
+            &mock_chain(2, false, false).provider(),
+            &criteria,
+            wearer,
+            U256::from(1) << 224,
+            BlockId::number(1),
+        ))
+        .unwrap();
+        assert_eq!(reason.as_deref(), Some("wearer not eligible"));
+    }
+}
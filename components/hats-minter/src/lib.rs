@@ -1,59 +1,194 @@
 #[allow(warnings)]
 mod bindings;
+mod checks;
+
+use alloy_rpc_types::BlockId;
 use alloy_sol_types::{sol, SolValue};
-use bindings::{
-    export,
-    wavs::worker::layer_types::{TriggerData, TriggerDataEthContractEvent},
-    Guest, TriggerAction,
-};
-use wavs_wasi_chain::{decode_event_log_data, ethereum::alloy_primitives::Uint};
+use bindings::{export, wavs::worker::layer_types::EthEventLogData, Guest, TriggerAction};
+use wavs_hats_common::eligibility::Criteria;
+use wavs_hats_common::info;
+use wavs_hats_common::{dry_run, evm, hats, idempotency, trigger};
+use wavs_wasi_chain::ethereum::alloy_primitives::{Address, U256};
+use wstd::runtime::block_on;
 
-sol!("../../src/interfaces/IHatsAvsTypes.sol");
+sol!(
+    #[sol(all_derives)]
+    "../../src/interfaces/IHatsAvsTypes.sol"
+);
 
 struct Component;
 
 impl Guest for Component {
     fn run(trigger_action: TriggerAction) -> std::result::Result<Option<Vec<u8>>, String> {
-        match trigger_action.data {
-            TriggerData::EthContractEvent(TriggerDataEthContractEvent { log, .. }) => {
-                // Decode the MintingTrigger event
-                let IHatsAvsTypes::MintingTrigger { triggerId, creator, hatId, wearer } =
-                    decode_event_log_data!(log)
-                        .map_err(|e| format!("Failed to decode event log data: {}", e))?;
-
-                eprintln!("Successfully decoded minting trigger");
-                eprintln!("Trigger ID: {}", u64::from(triggerId));
-                eprintln!("Creator: {}", creator);
-                eprintln!("Hat ID: {}", hatId);
-                eprintln!("Wearer: {}", wearer);
-
-                // Create a default formatted top hat ID (domain 1) if needed
-                let formatted_hat_id = if hatId == Uint::from(1_u8) {
-                    // If it's 1, it's likely meant to be a top hat with domain 1
-                    eprintln!("Converting hat ID 1 to proper format");
-                    Uint::from(1_u8) << 224
-                } else {
-                    hatId
-                };
-
-                // Create HatMintingData with the extracted data
-                let result = IHatsAvsTypes::HatMintingData {
-                    hatId: formatted_hat_id,
-                    wearer,
-                    requestor: creator,
-                    success: true, // Set success to true to allow minting
-                    reason: "".to_string(),
-                };
-
-                // Log success message
-                eprintln!("Hat minter component successfully processed the trigger");
-
-                // Return the ABI-encoded result
-                Ok(Some(result.abi_encode()))
-            }
-            _ => Err("Unsupported trigger data".to_string()),
+        let trigger = wavs_hats_common::trigger_event!(trigger_action.data)?;
+        let block = BlockId::number(trigger.block_height);
+        if trigger::is_event::<IHatsAvsTypes::BatchMintingTrigger>(&trigger.log.topics) {
+            batch_mint(trigger.log, block)
+        } else {
+            mint(trigger.log, block)
         }
     }
 }
 
+/// Handle a `MintingTrigger`, for a single wearer
+fn mint(log: EthEventLogData, block: BlockId) -> Result<Option<Vec<u8>>, String> {
+    // Decode the MintingTrigger event
+    let event: IHatsAvsTypes::MintingTrigger = trigger::decode_event(&log.topics, &log.data)?;
+    let dry_run = dry_run::enabled();
+    if dry_run {
+        dry_run::log("input", &event);
+    }
+    let IHatsAvsTypes::MintingTrigger { triggerId, creator, hatId, wearer } = event;
+
+    info!("Successfully decoded minting trigger");
+    info!("Trigger ID: {}", triggerId);
+    if let Some(output) = idempotency::cached(env!("CARGO_PKG_NAME"), triggerId) {
+        return Ok(Some(output));
+    }
+    info!("Creator: {}", creator);
+    info!("Hat ID: {}", hatId);
+    info!("Wearer: {}", wearer);
+
+    let formatted_hat_id = hats::expand_top_hat_domain(hatId);
+
+    // Verify the mint against the hat and the wearer's eligibility at the trigger block
+    let criteria = Criteria::from_env()?;
+    let provider = wavs_hats_common::eth_provider!(&evm::hats_chain())?;
+    let rejection =
+        block_on(checks::rejection_reason(&provider, &criteria, wearer, formatted_hat_id, block))?;
+    if let Some(reason) = &rejection {
+        info!("Rejecting mint: {}", reason);
+    }
+
+    // Create HatMintingData with the extracted data
+    let mut result = IHatsAvsTypes::HatMintingData {
+        hatId: formatted_hat_id,
+        wearer,
+        requestor: creator,
+        success: rejection.is_none(),
+        reason: rejection.unwrap_or_default(),
+    };
+    if dry_run {
+        dry_run::log("output", &result);
+        dry_run::simulate(&mut result.success, &mut result.reason);
+    }
+
+    // Log success message
+    info!("Hat minter component successfully processed the trigger");
+
+    // Return the ABI-encoded result
+    let output = result.abi_encode();
+    idempotency::record(env!("CARGO_PKG_NAME"), triggerId, &output);
+    Ok(Some(output))
+}
+
+/// Handle a `BatchMintingTrigger`, checking every wearer on its own
+///
+/// Wearers that fail their checks are rejected without failing the batch.
+fn batch_mint(log: EthEventLogData, block: BlockId) -> Result<Option<Vec<u8>>, String> {
+    let event: IHatsAvsTypes::BatchMintingTrigger = trigger::decode_event(&log.topics, &log.data)?;
+    let dry_run = dry_run::enabled();
+    if dry_run {
+        dry_run::log("input", &event);
+    }
+    let IHatsAvsTypes::BatchMintingTrigger { triggerId, creator, hatId, wearers } = event;
+
+    info!("Successfully decoded batch minting trigger");
+    info!("Trigger ID: {}", triggerId);
+    if let Some(output) = idempotency::cached(env!("CARGO_PKG_NAME"), triggerId) {
+        return Ok(Some(output));
+    }
+    info!("Creator: {}", creator);
+    info!("Hat ID: {}", hatId);
+    info!("Wearers: {}", wearers.len());
+
+    let formatted_hat_id = hats::expand_top_hat_domain(hatId);
+
+    let criteria = Criteria::from_env()?;
+    let provider = wavs_hats_common::eth_provider!(&evm::hats_chain())?;
+    let rejections = block_on(checks::rejection_reasons(
+        &provider,
+        &criteria,
+        &wearers,
+        formatted_hat_id,
+        block,
+    ))?;
+    for (wearer, reason) in wearers.iter().zip(&rejections) {
+        if let Some(reason) = reason {
+            info!("Rejecting mint to {}: {}", wearer, reason);
+        }
+    }
+
+    let mut result = batch_minting_data(formatted_hat_id, wearers, rejections);
+    if dry_run {
+        dry_run::log("output", &result);
+        simulate_batch(&mut result);
+    }
+    info!("Hat minter component successfully processed the batch trigger");
+    let output = result.abi_encode();
+    idempotency::record(env!("CARGO_PKG_NAME"), triggerId, &output);
+    Ok(Some(output))
+}
+
+/// Output of a batch, with the rejection reason of each wearer in order
+fn batch_minting_data(
+    hat_id: U256,
+    wearers: Vec<Address>,
+    rejections: Vec<Option<String>>,
+) -> IHatsAvsTypes::HatBatchMintingData {
+    IHatsAvsTypes::HatBatchMintingData {
+        hatId: hat_id,
+        wearers,
+        successes: rejections.iter().map(Option::is_none).collect(),
+        reasons: rejections.into_iter().map(Option::unwrap_or_default).collect(),
+    }
+}
+
+/// Mark every wearer of a batch as simulated, see [`dry_run::simulate`]
+fn simulate_batch(result: &mut IHatsAvsTypes::HatBatchMintingData) {
+    for (success, reason) in result.successes.iter_mut().zip(&mut result.reasons) {
+        dry_run::simulate(success, reason);
+    }
+}
+
 export!(Component with_types_in bindings);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_minting_data_partial_success() {
+        let wearers = vec![Address::repeat_byte(0x01), Address::repeat_byte(0x02)];
+        let result = batch_minting_data(
+            U256::from(1) << 224,
+            wearers.clone(),
+            vec![None, Some("wearer not eligible".to_string())],
+        );
+        assert_eq!(result.wearers, wearers);
+        assert_eq!(result.successes, vec![true, false]);
+        assert_eq!(result.reasons, vec![String::new(), "wearer not eligible".to_string()]);
+
+        let decoded =
+            IHatsAvsTypes::HatBatchMintingData::abi_decode(&result.abi_encode(), true).unwrap();
+        assert_eq!(decoded.successes, vec![true, false]);
+    }
+
+    #[test]
+    fn test_dry_run_batch_is_simulated() {
+        let mut result = batch_minting_data(
+            U256::from(1) << 224,
+            vec![Address::repeat_byte(0x01), Address::repeat_byte(0x02)],
+            vec![None, Some("wearer not eligible".to_string())],
+        );
+        simulate_batch(&mut result);
+        assert_eq!(result.successes, vec![false, false]);
+        assert_eq!(
+            result.reasons,
+            vec![
+                "dry run: would succeed".to_string(),
+                "dry run: would fail: wearer not eligible".to_string()
+            ]
+        );
+    }
+}
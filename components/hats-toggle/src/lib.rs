@@ -1,45 +1,63 @@
 #[allow(warnings)]
 mod bindings;
-use alloy_sol_types::{sol, SolValue};
-use bindings::{
-    export,
-    wavs::worker::layer_types::{TriggerData, TriggerDataEthContractEvent},
-    Guest, TriggerAction,
-};
-use wavs_wasi_chain::decode_event_log_data;
+mod schedule;
 
-sol!("../../src/interfaces/IHatsAvsTypes.sol");
+use alloy_rpc_types::BlockId;
+use alloy_sol_types::sol;
+use bindings::{export, Guest, TriggerAction};
+use schedule::Schedule;
+use wavs_hats_common::info;
+use wavs_hats_common::{dry_run, evm, idempotency};
+use wstd::runtime::block_on;
+
+sol!(
+    #[sol(all_derives)]
+    "../../src/interfaces/IHatsAvsTypes.sol"
+);
 
 struct Component;
 
 impl Guest for Component {
     fn run(trigger_action: TriggerAction) -> std::result::Result<Option<Vec<u8>>, String> {
-        match trigger_action.data {
-            TriggerData::EthContractEvent(TriggerDataEthContractEvent { log, .. }) => {
-                // Decode the StatusCheckTrigger event
-                let IHatsAvsTypes::StatusCheckTrigger { triggerId, creator: _, hatId } =
-                    decode_event_log_data!(log)
-                        .map_err(|e| format!("Failed to decode event log data: {}", e))?;
-
-                eprintln!("Successfully decoded status check trigger");
-                eprintln!("Trigger ID: {}", u64::from(triggerId));
-                eprintln!("Hat ID: {}", hatId);
-
-                // For this simplified implementation, we're just setting active to true
-                // In a real implementation, you would use the hatId to determine if the hat should be active
-                let active = true;
-
-                // Create a StatusResult with the proper triggerId from decoded data
-                let result = IHatsAvsTypes::StatusResult { triggerId, active, hatId };
-
-                // Log success message
-                eprintln!("Hat toggle component successfully processed the trigger");
-
-                // Return the ABI-encoded result
-                Ok(Some(result.abi_encode()))
-            }
-            _ => Err("Unsupported trigger data".to_string()),
+        // Decode the StatusCheckTrigger event
+        let (event, block_height) = wavs_hats_common::decode_trigger!(
+            trigger_action.data,
+            IHatsAvsTypes::StatusCheckTrigger
+        )?;
+        let dry_run = dry_run::enabled();
+        if dry_run {
+            dry_run::log("input", &event);
+        }
+        let IHatsAvsTypes::StatusCheckTrigger { triggerId, creator: _, hatId } = event;
+
+        info!("Successfully decoded status check trigger");
+        info!("Trigger ID: {}", triggerId);
+        if let Some(output) = idempotency::cached(env!("CARGO_PKG_NAME"), triggerId) {
+            return Ok(Some(output));
+        }
+        info!("Hat ID: {}", hatId);
+
+        // Evaluate the schedule in the hat's details as of the trigger block
+        let provider = wavs_hats_common::eth_provider!(&evm::hats_chain())?;
+        let block = BlockId::number(block_height);
+        let active = block_on(async {
+            let hat = evm::view_hat(&provider, hatId, block).await?;
+            Schedule::resolve(&hat.details).await?.evaluate(&provider, block).await
+        })?;
+        info!("Hat active: {}", active);
+
+        // Create a StatusResult with the proper triggerId from decoded data
+        let result = IHatsAvsTypes::StatusResult { triggerId, active, hatId };
+
+        // Log success message
+        info!("Hat toggle component successfully processed the trigger");
+
+        // Return the ABI-encoded result
+        let output = dry_run::output(&result, dry_run);
+        if let Some(output) = &output {
+            idempotency::record(env!("CARGO_PKG_NAME"), triggerId, output);
         }
+        Ok(output)
     }
 }
 
@@ -0,0 +1,177 @@
+//! Activation schedule of a hat, read from the JSON in its details or the
+//! metadata they point at
+
+use alloy_network::Ethereum;
+use alloy_primitives::{Address, U256};
+use alloy_provider::RootProvider;
+use alloy_rpc_types::BlockId;
+use serde::Deserialize;
+use wavs_hats_common::{evm, metadata};
+
+/// When a hat is active, e.g. `{"activeFrom": 1700000000, "activeUntil": 1800000000}`
+///
+/// Times are Unix timestamps in seconds, compared against the trigger block's
+/// timestamp. `activeUntil` is exclusive. A hat without a schedule is active.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Schedule {
+    pub active_from: Option<u64>,
+    pub active_until: Option<u64>,
+    /// Additional on-chain requirement for the hat to be active
+    pub condition: Option<Condition>,
+}
+
+/// Hat is only active while `holder` holds at least `min_balance` of `token`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    pub token: Address,
+    pub holder: Address,
+    #[serde(default = "default_min_balance")]
+    pub min_balance: U256,
+}
+
+fn default_min_balance() -> U256 {
+    U256::from(1)
+}
+
+impl Schedule {
+    /// Parse the schedule from a hat's details
+    ///
+    /// Details that are not a JSON object, such as a plain name, carry no
+    /// schedule. A JSON object with malformed schedule fields is an error.
+    pub fn from_details(details: &str) -> Result<Self, String> {
+        match serde_json::from_str::<serde_json::Value>(details) {
+            Ok(value) if value.is_object() => serde_json::from_value(value)
+                .map_err(|e| format!("Invalid hat schedule in details: {}", e)),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Parse the schedule from a hat's details, fetching the metadata first
+    /// when the details are a URI of it, as by Hats convention
+    pub async fn resolve(details: &str) -> Result<Self, String> {
+        if !metadata::is_remote_uri(details) && !details.starts_with("data:") {
+            return Self::from_details(details);
+        }
+        let body = metadata::fetch(details)
+            .await
+            .and_then(metadata::json_body)
+            .map_err(|e| format!("Failed to fetch hat metadata from {}: {}", details, e))?;
+        let body = String::from_utf8(body).map_err(|e| format!("Invalid hat metadata: {}", e))?;
+        Self::from_details(&body)
+    }
+
+    /// Whether the schedule has a time window
+    pub fn has_window(&self) -> bool {
+        self.active_from.is_some() || self.active_until.is_some()
+    }
+
+    /// Whether `timestamp` falls within the time window
+    pub fn is_active_at(&self, timestamp: u64) -> bool {
+        self.active_from.map_or(true, |from| timestamp >= from)
+            && self.active_until.map_or(true, |until| timestamp < until)
+    }
+
+    /// Evaluate the full schedule as of `block`
+    pub async fn evaluate(
+        &self,
+        provider: &RootProvider<Ethereum>,
+        block: BlockId,
+    ) -> Result<bool, String> {
+        if self.has_window() {
+            let timestamp = evm::block_timestamp(provider, block).await?;
+            if !self.is_active_at(timestamp) {
+                return Ok(false);
+            }
+        }
+
+        match &self.condition {
+            Some(condition) => {
+                let balance =
+                    evm::token_balance(provider, condition.token, condition.holder, block).await?;
+                Ok(balance >= condition.min_balance)
+            }
+            None => Ok(true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use wavs_hats_common::mock::MockTransport;
+    use wstd::runtime::block_on;
+
+    fn window() -> Schedule {
+        Schedule::from_details(r#"{"activeFrom": 1000, "activeUntil": 2000}"#).unwrap()
+    }
+
+    #[test]
+    fn test_before_window() {
+        assert!(!window().is_active_at(999));
+    }
+
+    #[test]
+    fn test_in_window() {
+        assert!(window().is_active_at(1000));
+        assert!(window().is_active_at(1999));
+    }
+
+    #[test]
+    fn test_after_window() {
+        assert!(!window().is_active_at(2000));
+        assert!(!window().is_active_at(5000));
+    }
+
+    #[test]
+    fn test_open_ended_window() {
+        let schedule = Schedule::from_details(r#"{"activeUntil": 2000}"#).unwrap();
+        assert!(schedule.is_active_at(0));
+        assert!(!schedule.is_active_at(2000));
+    }
+
+    #[test]
+    fn test_details_without_schedule() {
+        for details in ["Treasury Signer", "", "[1, 2]", r#"{"name": "Signer"}"#] {
+            let schedule = Schedule::from_details(details).unwrap();
+            assert_eq!(schedule, Schedule::default());
+            assert!(schedule.is_active_at(0));
+        }
+
+        assert!(Schedule::from_details(r#"{"activeFrom": "soon"}"#).is_err());
+    }
+
+    #[test]
+    fn test_details_uri() {
+        // {"name": "Signer", "activeFrom": 1000, "activeUntil": 2000}
+        let uri = "data:application/json;base64,eyJuYW1lIjogIlNpZ25lciIsICJhY3RpdmVGcm9tIjogMTAwMCwgImFjdGl2ZVVudGlsIjogMjAwMH0=";
+        assert_eq!(block_on(Schedule::resolve(uri)).unwrap(), window());
+
+        // Inline details are parsed as they are
+        assert_eq!(block_on(Schedule::resolve("Treasury Signer")).unwrap(), Schedule::default());
+        assert!(block_on(Schedule::resolve("data:application/json;base64,bm90IGpzb24="))
+            .unwrap_err()
+            .contains("metadata is not JSON"));
+    }
+
+    #[test]
+    fn test_condition_check() {
+        let schedule = Schedule::from_details(&format!(
+            r#"{{"condition": {{"token": "{}", "holder": "{}", "minBalance": "0x64"}}}}"#,
+            Address::repeat_byte(0x42),
+            Address::repeat_byte(0x01)
+        ))
+        .unwrap();
+
+        for (balance, active) in [(99u64, false), (100, true)] {
+            let transport = MockTransport::new(move |method, _| {
+                assert_eq!(method, "eth_call");
+                Ok(Value::String(format!("{:#066x}", balance)))
+            });
+            let result = block_on(schedule.evaluate(&transport.provider(), BlockId::number(7)));
+            assert_eq!(result.unwrap(), active);
+        }
+    }
+}
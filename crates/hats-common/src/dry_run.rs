@@ -0,0 +1,98 @@
+//! Dry runs, which compute a component's result without reporting success
+//!
+//! With `WAVS_ENV_DRY_RUN` set, components evaluate triggers as usual, log
+//! their decoded input and computed output, and submit a result that can't
+//! change anything on chain: mints and hat creations are rejected with a
+//! reason starting with [`REASON_PREFIX`]. Eligibility and status results
+//! have no reason to mark them with, and the modules store whatever they
+//! receive, so those components submit nothing at all.
+
+use crate::info;
+use alloy_sol_types::SolValue;
+use std::fmt::Debug;
+
+/// Start of the reason of every result submitted in a dry run
+pub const REASON_PREFIX: &str = "dry run: ";
+
+/// Whether `WAVS_ENV_DRY_RUN` is set
+pub fn enabled() -> bool {
+    crate::env::flag("WAVS_ENV_DRY_RUN", false)
+}
+
+/// Mark a result as simulated, keeping whether it would have succeeded in its reason
+pub fn simulate(success: &mut bool, reason: &mut String) {
+    *reason = simulated_reason(*success, reason);
+    *success = false;
+}
+
+/// Reason of a simulated result that would have succeeded or failed with `reason`
+pub fn simulated_reason(success: bool, reason: &str) -> String {
+    if success {
+        format!("{}would succeed", REASON_PREFIX)
+    } else {
+        format!("{}would fail: {}", REASON_PREFIX, reason)
+    }
+}
+
+/// ABI-encoded `result`, or nothing in a dry run
+///
+/// For results without a reason to mark them as simulated, such as
+/// eligibility and status results, which a dry run only logs.
+pub fn output(result: &(impl SolValue + Debug), dry_run: bool) -> Option<Vec<u8>> {
+    if dry_run {
+        log("output", result);
+        return None;
+    }
+    Some(result.abi_encode())
+}
+
+/// Log a value of a dry run, such as its decoded input or computed output
+pub fn log(label: &str, value: &impl Debug) {
+    info!("{}", describe(label, value));
+}
+
+fn describe(label: &str, value: &impl Debug) -> String {
+    format!("[dry run] {}: {:#?}", label, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulate_marks_results() {
+        let (mut success, mut reason) = (true, String::new());
+        simulate(&mut success, &mut reason);
+        assert!(!success);
+        assert_eq!(reason, "dry run: would succeed");
+
+        let (mut success, mut reason) = (false, "wearer not eligible".to_string());
+        simulate(&mut success, &mut reason);
+        assert!(!success);
+        assert_eq!(reason, "dry run: would fail: wearer not eligible");
+    }
+
+    #[test]
+    fn test_dry_run_submits_no_output() {
+        let result = (7u64, true);
+        assert_eq!(output(&result, true), None);
+
+        let encoded = output(&result, false).unwrap();
+        assert_eq!(<(u64, bool)>::abi_decode(&encoded, true).unwrap(), result);
+    }
+
+    #[test]
+    fn test_describe_logs_full_value() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct MintingTrigger {
+            trigger_id: u64,
+            wearer: &'static str,
+        }
+
+        let line = describe("input", &MintingTrigger { trigger_id: 7, wearer: "0x01" });
+        assert!(line.starts_with("[dry run] input: MintingTrigger {"), "{}", line);
+        assert!(line.contains("trigger_id: 7"), "{}", line);
+        assert!(line.contains("wearer: \"0x01\""), "{}", line);
+    }
+}
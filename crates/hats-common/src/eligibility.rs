@@ -0,0 +1,638 @@
+//! Eligibility criteria for hat wearers, read from the component config
+//!
+//! Shared by the minter and the eligibility component, so a wearer is held to
+//! the same criteria whether they are checked on their own or for a mint.
+
+use crate::nft::NftGate;
+use crate::{evm, metadata};
+use crate::{info, warn};
+use alloy_network::Ethereum;
+use alloy_primitives::{Address, U256};
+use alloy_provider::RootProvider;
+use alloy_rpc_types::BlockId;
+use serde::Deserialize;
+
+/// Outcome of evaluating a wearer against the [`Criteria`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EligibilityDecision {
+    pub eligible: bool,
+    pub standing: bool,
+    /// Why the wearer is ineligible, `None` when eligible
+    pub reason: Option<String>,
+}
+
+impl EligibilityDecision {
+    fn eligible() -> Self {
+        Self { eligible: true, standing: true, reason: None }
+    }
+
+    fn ineligible(standing: bool, reason: impl Into<String>) -> Self {
+        Self { eligible: false, standing, reason: Some(reason.into()) }
+    }
+}
+
+/// Evaluate `wearer` for `hat_id` against `criteria` as of `block`
+///
+/// With metadata gates enabled, the NFT and the combined criteria named by the
+/// hat's metadata replace any configured ones. `provider` is only used when the criteria need an
+/// on-chain read, see [`Criteria::needs_provider`].
+pub async fn evaluate(
+    criteria: &Criteria,
+    provider: Option<&RootProvider<Ethereum>>,
+    wearer: Address,
+    hat_id: U256,
+    block: BlockId,
+) -> Result<EligibilityDecision, String> {
+    if criteria.metadata_gates && !criteria.revoked.contains(&wearer) {
+        let provider = provider
+            .ok_or_else(|| "No provider available to read the hat's NFT gate".to_string())?;
+        if let Some(metadata) = metadata::hat_metadata(provider, hat_id, block).await? {
+            let criteria = Criteria {
+                nft_gate: NftGate::from_metadata(&metadata)?,
+                combined: CombinedCriteria::from_metadata(&metadata)?,
+                ..criteria.clone()
+            };
+            return criteria.evaluate(Some(provider), wearer, block).await;
+        }
+    }
+    criteria.evaluate(provider, wearer, block).await
+}
+
+/// Requirements a wearer must meet to be eligible for a hat
+///
+/// Configured through `WAVS_ENV_ELIGIBILITY_TOKEN`,
+/// `WAVS_ENV_ELIGIBILITY_MIN_BALANCE` or `WAVS_ENV_ELIGIBILITY_MIN_TOKENS`,
+/// `WAVS_ENV_ELIGIBILITY_DENYLIST` and `WAVS_ENV_ELIGIBILITY_METADATA_GATES`,
+/// with `WAVS_ENV_ELIGIBILITY_REVOKED` still read as part of the denylist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Criteria {
+    /// ERC20 or ERC721 token the wearer must hold, if any
+    pub token: Option<Address>,
+    /// Minimum balance of `token`, in the token's base units
+    pub min_balance: U256,
+    /// Minimum balance of an ERC20 `token` in whole tokens, such as `"1.5"`,
+    /// scaled by its decimals and used instead of `min_balance`
+    pub min_tokens: Option<String>,
+    /// Denied wearers, in bad standing and never eligible
+    pub revoked: Vec<Address>,
+    /// NFT the wearer must own, read from the hat's metadata
+    pub nft_gate: Option<NftGate>,
+    /// Criteria combined with `and` or `or`, read from the hat's metadata
+    pub combined: Option<CombinedCriteria>,
+    /// Whether to read `nft_gate` and `combined` from the metadata of the
+    /// evaluated hat
+    pub metadata_gates: bool,
+}
+
+impl Default for Criteria {
+    fn default() -> Self {
+        Self {
+            token: None,
+            min_balance: U256::from(1),
+            min_tokens: None,
+            revoked: Vec::new(),
+            nft_gate: None,
+            combined: None,
+            metadata_gates: false,
+        }
+    }
+}
+
+impl Criteria {
+    /// Read the criteria from the environment, defaulting to no requirements
+    pub fn from_env() -> Result<Self, String> {
+        let mut criteria = Self::default();
+
+        if let Ok(token) = std::env::var("WAVS_ENV_ELIGIBILITY_TOKEN") {
+            criteria.token = Some(
+                token
+                    .trim()
+                    .parse()
+                    .map_err(|e| format!("Invalid eligibility token {}: {}", token, e))?,
+            );
+        }
+
+        if let Ok(min_balance) = std::env::var("WAVS_ENV_ELIGIBILITY_MIN_BALANCE") {
+            criteria.min_balance = min_balance.trim().parse().map_err(|e| {
+                format!("Invalid eligibility minimum balance {}: {}", min_balance, e)
+            })?;
+        }
+
+        if let Ok(min_tokens) = std::env::var("WAVS_ENV_ELIGIBILITY_MIN_TOKENS") {
+            criteria.min_tokens = Some(min_tokens.trim().to_string());
+        }
+
+        for name in ["WAVS_ENV_ELIGIBILITY_DENYLIST", "WAVS_ENV_ELIGIBILITY_REVOKED"] {
+            if let Ok(list) = std::env::var(name) {
+                criteria.revoked.extend(parse_address_list(name, &list)?);
+            }
+        }
+
+        criteria.metadata_gates = crate::env::flag("WAVS_ENV_ELIGIBILITY_METADATA_GATES", false);
+
+        Ok(criteria)
+    }
+
+    /// Whether the criteria require an on-chain read
+    pub fn needs_provider(&self) -> bool {
+        self.token.is_some()
+            || self.nft_gate.is_some()
+            || self.combined.as_ref().is_some_and(CombinedCriteria::needs_provider)
+            || self.metadata_gates
+    }
+
+    /// Evaluate `wearer` as of `block`, ignoring `metadata_gates`
+    ///
+    /// A wearer in bad standing is never eligible. The combined criteria are
+    /// checked last, after the token and NFT gates. `provider` is only used
+    /// when a criterion needs an on-chain read. A failed NFT ownership check
+    /// makes the wearer ineligible rather than failing the evaluation.
+    pub async fn evaluate(
+        &self,
+        provider: Option<&RootProvider<Ethereum>>,
+        wearer: Address,
+        block: BlockId,
+    ) -> Result<EligibilityDecision, String> {
+        if self.revoked.contains(&wearer) {
+            return Ok(EligibilityDecision::ineligible(false, "wearer in bad standing"));
+        }
+
+        if let Some(token) = self.token {
+            let provider = require_provider(provider)?;
+            let eligible = match &self.min_tokens {
+                Some(amount) => {
+                    meets_erc20_threshold(provider, token, wearer, amount, block).await?
+                }
+                None => {
+                    evm::token_balance(provider, token, wearer, block).await? >= self.min_balance
+                }
+            };
+            if !eligible {
+                return Ok(EligibilityDecision::ineligible(true, "wearer below minimum balance"));
+            }
+        }
+
+        if let Some(gate) = &self.nft_gate {
+            if let Some(failure) =
+                nft_gate_failure(gate, require_provider(provider)?, wearer, block).await
+            {
+                return Ok(failure);
+            }
+        }
+
+        match &self.combined {
+            Some(combined) => combined.evaluate(provider, wearer, block).await,
+            None => Ok(EligibilityDecision::eligible()),
+        }
+    }
+}
+
+/// How the criteria of [`CombinedCriteria`] are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Combinator {
+    /// Every criterion must be met
+    And,
+    /// At least one criterion must be met
+    Or,
+}
+
+/// Single requirement of [`CombinedCriteria`], tagged by its `type`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Criterion {
+    /// At least `amount` whole tokens of the ERC20 `token`, such as `"1.5"`
+    Erc20Threshold { token: Address, amount: String },
+    /// Ownership of an NFT, as in the `nftGate` field
+    NftOwnership(NftGate),
+    /// Wearer is one of `addresses`
+    Allowlist { addresses: Vec<Address> },
+    /// Wearer is none of `addresses`, failing puts them in bad standing
+    Denylist { addresses: Vec<Address> },
+}
+
+impl Criterion {
+    fn needs_provider(&self) -> bool {
+        matches!(self, Self::Erc20Threshold { .. } | Self::NftOwnership(_))
+    }
+
+    /// Decision for a `wearer` failing the criterion as of `block`, `None`
+    /// when they meet it
+    async fn failure(
+        &self,
+        provider: Option<&RootProvider<Ethereum>>,
+        wearer: Address,
+        block: BlockId,
+    ) -> Result<Option<EligibilityDecision>, String> {
+        Ok(match self {
+            Self::Erc20Threshold { token, amount } => {
+                let provider = require_provider(provider)?;
+                let met = meets_erc20_threshold(provider, *token, wearer, amount, block).await?;
+                (!met)
+                    .then(|| EligibilityDecision::ineligible(true, "wearer below minimum balance"))
+            }
+            Self::NftOwnership(gate) => {
+                nft_gate_failure(gate, require_provider(provider)?, wearer, block).await
+            }
+            Self::Allowlist { addresses } => (!addresses.contains(&wearer))
+                .then(|| EligibilityDecision::ineligible(true, "wearer not on the allowlist")),
+            Self::Denylist { addresses } => addresses
+                .contains(&wearer)
+                .then(|| EligibilityDecision::ineligible(false, "wearer in bad standing")),
+        })
+    }
+}
+
+/// Criteria combined with `and` or `or`, as given by the `eligibility` field
+/// of a hat's metadata
+///
+/// Criteria are checked in order and evaluation stops as soon as the outcome
+/// is known, so put criteria that need no on-chain read first. An empty list
+/// has no requirements.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CombinedCriteria {
+    pub combinator: Combinator,
+    pub criteria: Vec<Criterion>,
+}
+
+impl CombinedCriteria {
+    /// Read the combined criteria from hat metadata JSON, if it has any
+    pub fn from_metadata(metadata: &[u8]) -> Result<Option<Self>, String> {
+        #[derive(Deserialize)]
+        struct Metadata {
+            eligibility: Option<CombinedCriteria>,
+        }
+
+        let metadata: Metadata = serde_json::from_slice(metadata)
+            .map_err(|e| format!("Invalid eligibility criteria in hat metadata: {}", e))?;
+        for criterion in metadata.eligibility.iter().flat_map(|combined| &combined.criteria) {
+            if let Criterion::NftOwnership(gate) = criterion {
+                gate.validate()?;
+            }
+        }
+        Ok(metadata.eligibility)
+    }
+
+    /// Whether any criterion requires an on-chain read
+    pub fn needs_provider(&self) -> bool {
+        self.criteria.iter().any(Criterion::needs_provider)
+    }
+
+    /// Evaluate `wearer` as of `block`
+    ///
+    /// When no criterion of an `or` is met, the wearer is in bad standing if
+    /// any of the failures put them there.
+    pub async fn evaluate(
+        &self,
+        provider: Option<&RootProvider<Ethereum>>,
+        wearer: Address,
+        block: BlockId,
+    ) -> Result<EligibilityDecision, String> {
+        let mut failures = Vec::new();
+        for criterion in &self.criteria {
+            match (criterion.failure(provider, wearer, block).await?, self.combinator) {
+                (None, Combinator::Or) => return Ok(EligibilityDecision::eligible()),
+                (None, Combinator::And) => {}
+                (Some(failure), Combinator::And) => return Ok(failure),
+                (Some(failure), Combinator::Or) => failures.push(failure),
+            }
+        }
+        if failures.is_empty() {
+            return Ok(EligibilityDecision::eligible());
+        }
+        let standing = failures.iter().all(|failure| failure.standing);
+        let reasons: Vec<_> = failures.into_iter().filter_map(|failure| failure.reason).collect();
+        Ok(EligibilityDecision::ineligible(
+            standing,
+            format!("no criterion met: {}", reasons.join("; ")),
+        ))
+    }
+}
+
+fn require_provider(
+    provider: Option<&RootProvider<Ethereum>>,
+) -> Result<&RootProvider<Ethereum>, String> {
+    provider.ok_or_else(|| "No provider available to check eligibility".to_string())
+}
+
+/// Decision for a `wearer` failing `gate` as of `block`, `None` when they
+/// meet it
+async fn nft_gate_failure(
+    gate: &NftGate,
+    provider: &RootProvider<Ethereum>,
+    wearer: Address,
+    block: BlockId,
+) -> Option<EligibilityDecision> {
+    match gate.is_met(provider, wearer, block).await {
+        Ok(true) => None,
+        Ok(false) => {
+            info!("Wearer {} does not own the gating NFT {}", wearer, gate.collection);
+            Some(EligibilityDecision::ineligible(true, "wearer does not own the gating NFT"))
+        }
+        Err(e) => {
+            warn!("Wearer {} ineligible, NFT ownership check failed: {}", wearer, e);
+            Some(EligibilityDecision::ineligible(
+                true,
+                format!("NFT ownership check failed: {}", e),
+            ))
+        }
+    }
+}
+
+/// Whether `holder` has at least `amount` whole tokens of the ERC20 `token`,
+/// comparing its balance against `amount * 10^decimals`
+pub async fn meets_erc20_threshold(
+    provider: &RootProvider<Ethereum>,
+    token: Address,
+    holder: Address,
+    amount: &str,
+    block: BlockId,
+) -> Result<bool, String> {
+    let decimals = evm::erc20_decimals(provider, token, block).await?;
+    let threshold = evm::scale_amount(amount, decimals)?;
+    let balance = evm::erc20_balance(provider, token, holder, block).await?;
+    Ok(balance >= threshold)
+}
+
+/// Parse a comma separated list of addresses, failing on any malformed entry
+fn parse_address_list(name: &str, list: &str) -> Result<Vec<Address>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(|address| {
+            address.parse().map_err(|e| format!("Invalid address {} in {}: {}", address, name, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+    use serde_json::{json, Value};
+    use wstd::runtime::block_on;
+
+    /// `(eligible, standing)` of a successful evaluation
+    fn outcome(result: Result<EligibilityDecision, String>) -> (bool, bool) {
+        let decision = result.unwrap();
+        assert_eq!(decision.reason.is_none(), decision.eligible);
+        (decision.eligible, decision.standing)
+    }
+
+    fn balance_provider(balance: u64) -> MockTransport {
+        MockTransport::new(move |method, _| {
+            assert_eq!(method, "eth_call");
+            Ok(Value::String(format!("{:#066x}", balance)))
+        })
+    }
+
+    fn token_criteria(min_balance: u64) -> Criteria {
+        Criteria {
+            token: Some(Address::repeat_byte(0x42)),
+            min_balance: U256::from(min_balance),
+            ..Criteria::default()
+        }
+    }
+
+    #[test]
+    fn test_below_threshold_is_ineligible() {
+        let transport = balance_provider(99);
+        let provider = transport.provider();
+        let result = block_on(token_criteria(100).evaluate(
+            Some(&provider),
+            Address::repeat_byte(0x01),
+            BlockId::number(10),
+        ));
+        assert_eq!(outcome(result), (false, true));
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests[0]["params"][0]["to"], json!(Address::repeat_byte(0x42)));
+        assert_eq!(requests[0]["params"][1], "0xa");
+    }
+
+    #[test]
+    fn test_at_or_above_threshold_is_eligible() {
+        for balance in [100, 1000] {
+            let provider = balance_provider(balance).provider();
+            let result = block_on(token_criteria(100).evaluate(
+                Some(&provider),
+                Address::repeat_byte(0x01),
+                BlockId::latest(),
+            ));
+            assert_eq!(outcome(result), (true, true));
+        }
+    }
+
+    #[test]
+    fn test_revoked_wearer_is_in_bad_standing() {
+        let wearer = Address::repeat_byte(0x01);
+        let criteria = Criteria { revoked: vec![wearer], ..token_criteria(1) };
+        // Revocation is decided without touching the chain
+        let result = block_on(criteria.evaluate(None, wearer, BlockId::latest()));
+        assert_eq!(outcome(result), (false, false));
+    }
+
+    #[test]
+    fn test_no_requirements() {
+        let result = block_on(Criteria::default().evaluate(None, Address::ZERO, BlockId::latest()));
+        assert_eq!(outcome(result), (true, true));
+        assert!(!Criteria::default().needs_provider());
+    }
+
+    /// Token with `decimals` where the wearer holds `balance` base units
+    fn erc20_provider(decimals: u8, balance: U256) -> MockTransport {
+        MockTransport::new(move |_, params| {
+            let input = params[0]["input"].as_str().unwrap();
+            Ok(match &input[..10] {
+                // decimals()
+                "0x313ce567" => json!(format!("{:#066x}", decimals)),
+                // balanceOf(address)
+                "0x70a08231" => json!(format!("{:#066x}", balance)),
+                selector => panic!("Unexpected call {}", selector),
+            })
+        })
+    }
+
+    #[test]
+    fn test_erc20_threshold_scales_by_decimals() {
+        let wearer = Address::repeat_byte(0x01);
+        let criteria = Criteria { min_tokens: Some("1.5".to_string()), ..token_criteria(1) };
+        let ten = U256::from(10);
+
+        for (decimals, balance, eligible) in [
+            (6, U256::from(1_500_000), true),
+            (6, U256::from(1_499_999), false),
+            (18, U256::from(15) * ten.pow(U256::from(17)), true),
+            // 1.5 tokens with 6 decimals is far below 1.5 tokens with 18
+            (18, U256::from(1_500_000), false),
+        ] {
+            let provider = erc20_provider(decimals, balance).provider();
+            let result = block_on(criteria.evaluate(Some(&provider), wearer, BlockId::latest()));
+            assert_eq!(outcome(result), (eligible, true), "{} decimals", decimals);
+        }
+    }
+
+    #[test]
+    fn test_nft_gate() {
+        use crate::mock::REVERT;
+        use crate::nft::NftStandard;
+
+        let criteria = Criteria {
+            nft_gate: Some(NftGate {
+                standard: NftStandard::Erc1155,
+                collection: Address::repeat_byte(0x42),
+                token_id: Some(U256::from(7)),
+            }),
+            ..Criteria::default()
+        };
+        assert!(criteria.needs_provider());
+
+        for (response, expected) in [
+            (Ok(json!(format!("{:#066x}", 1))), (true, true)),
+            (Ok(json!(format!("{:#066x}", 0))), (false, true)),
+            // A failing collection makes the wearer ineligible instead of aborting
+            (Err(REVERT.to_string()), (false, true)),
+        ] {
+            let provider = MockTransport::new(move |_, _| response.clone()).provider();
+            let result = block_on(criteria.evaluate(
+                Some(&provider),
+                Address::repeat_byte(0x01),
+                BlockId::latest(),
+            ));
+            assert_eq!(outcome(result), expected);
+        }
+    }
+
+    #[test]
+    fn test_denylist() {
+        let denied = Address::repeat_byte(0x01);
+        let list = format!("{}, {},", denied, Address::repeat_byte(0x02));
+        let criteria = Criteria {
+            revoked: parse_address_list("WAVS_ENV_ELIGIBILITY_DENYLIST", &list).unwrap(),
+            ..Criteria::default()
+        };
+        assert_eq!(criteria.revoked.len(), 2);
+
+        let result = block_on(criteria.evaluate(None, denied, BlockId::latest()));
+        assert_eq!(outcome(result), (false, false));
+        let result =
+            block_on(criteria.evaluate(None, Address::repeat_byte(0x03), BlockId::latest()));
+        assert_eq!(outcome(result), (true, true));
+    }
+
+    #[test]
+    fn test_evaluate_decisions() {
+        let hat_id = U256::from(1) << 224;
+        let wearer = Address::repeat_byte(0x01);
+        let provider = balance_provider(99).provider();
+        let decide = |criteria: Criteria| {
+            block_on(evaluate(&criteria, Some(&provider), wearer, hat_id, BlockId::latest()))
+                .unwrap()
+        };
+
+        assert_eq!(decide(token_criteria(99)), EligibilityDecision::eligible());
+        assert_eq!(
+            decide(token_criteria(100)),
+            EligibilityDecision {
+                eligible: false,
+                standing: true,
+                reason: Some("wearer below minimum balance".to_string()),
+            }
+        );
+        // A denied wearer is rejected before the hat's metadata is read
+        let denied = Criteria { revoked: vec![wearer], metadata_gates: true, ..token_criteria(1) };
+        assert_eq!(
+            block_on(evaluate(&denied, None, wearer, hat_id, BlockId::latest())).unwrap(),
+            EligibilityDecision {
+                eligible: false,
+                standing: false,
+                reason: Some("wearer in bad standing".to_string()),
+            }
+        );
+    }
+
+    /// Combined criteria of hat metadata with `combinator` and `criteria`
+    fn combined_criteria(combinator: &str, criteria: Value) -> CombinedCriteria {
+        let metadata = json!({ "eligibility": { "combinator": combinator, "criteria": criteria } });
+        CombinedCriteria::from_metadata(metadata.to_string().as_bytes()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_and_fails_on_second_criterion() {
+        let wearer = Address::repeat_byte(0x01);
+        let combined = combined_criteria(
+            "and",
+            json!([
+                { "type": "allowlist", "addresses": [wearer] },
+                { "type": "erc20Threshold", "token": Address::repeat_byte(0x42), "amount": "2" },
+                { "type": "nftOwnership", "standard": "ERC721", "collection": Address::repeat_byte(0x43) },
+            ]),
+        );
+        assert_eq!(combined.combinator, Combinator::And);
+        assert!(matches!(combined.criteria[2], Criterion::NftOwnership(_)));
+        assert!(combined.needs_provider());
+
+        let transport = erc20_provider(0, U256::from(1));
+        let provider = transport.provider();
+        let criteria = Criteria { combined: Some(combined), ..Criteria::default() };
+        let decision =
+            block_on(criteria.evaluate(Some(&provider), wearer, BlockId::latest())).unwrap();
+        assert_eq!(decision, EligibilityDecision::ineligible(true, "wearer below minimum balance"));
+        // The NFT is never checked once the threshold fails
+        let requests = transport.requests.lock().unwrap();
+        assert!(requests
+            .iter()
+            .all(|request| request["params"][0]["to"] == json!(Address::repeat_byte(0x42))));
+    }
+
+    #[test]
+    fn test_or_passes_on_first_criterion() {
+        let wearer = Address::repeat_byte(0x01);
+        let combined = combined_criteria(
+            "or",
+            json!([
+                { "type": "allowlist", "addresses": [wearer] },
+                { "type": "erc20Threshold", "token": Address::repeat_byte(0x42), "amount": "2" },
+            ]),
+        );
+        // The allowlist decides without reading the chain
+        let decision = block_on(combined.evaluate(None, wearer, BlockId::latest())).unwrap();
+        assert_eq!(decision, EligibilityDecision::eligible());
+
+        let combined = combined_criteria(
+            "or",
+            json!([
+                { "type": "denylist", "addresses": [wearer] },
+                { "type": "allowlist", "addresses": [Address::repeat_byte(0x02)] },
+            ]),
+        );
+        let decision = block_on(combined.evaluate(None, wearer, BlockId::latest())).unwrap();
+        assert_eq!(
+            decision,
+            EligibilityDecision::ineligible(
+                false,
+                "no criterion met: wearer in bad standing; wearer not on the allowlist"
+            )
+        );
+    }
+
+    #[test]
+    fn test_malformed_combined_criteria() {
+        let collection = Address::repeat_byte(0x42);
+        for criteria in [
+            json!([{ "type": "nftOwnership", "standard": "1155", "collection": collection }]),
+            json!([{ "type": "erc721Threshold", "token": collection }]),
+        ] {
+            let metadata = json!({ "eligibility": { "combinator": "and", "criteria": criteria } });
+            assert!(CombinedCriteria::from_metadata(metadata.to_string().as_bytes()).is_err());
+        }
+        assert_eq!(CombinedCriteria::from_metadata(br#"{"name": "Member"}"#), Ok(None));
+    }
+
+    #[test]
+    fn test_malformed_denylist_entry() {
+        let list = format!("{},0x1234", Address::repeat_byte(0x01));
+        let err = parse_address_list("WAVS_ENV_ELIGIBILITY_DENYLIST", &list).unwrap_err();
+        assert!(err.starts_with("Invalid address 0x1234 in WAVS_ENV_ELIGIBILITY_DENYLIST"));
+    }
+}
@@ -0,0 +1,63 @@
+//! Access to component configuration passed as `WAVS_ENV_*` variables
+
+/// Read a variable that must be set
+pub fn required_var(name: &str) -> Result<String, String> {
+    std::env::var(name).map_err(|e| format!("Missing required variable {}: {}", name, e))
+}
+
+/// Read the first of `names` that is set, for settings that have been renamed
+pub fn required_var_of(names: &[&str]) -> Result<String, String> {
+    first_set(names, |name| std::env::var(name).ok())
+}
+
+fn first_set(names: &[&str], var: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    names
+        .iter()
+        .find_map(|name| var(name))
+        .ok_or_else(|| format!("Missing required variable, tried {}", names.join(", ")))
+}
+
+/// Variables the OpenAI API key is read from, in order
+pub const OPENAI_API_KEY_VARS: [&str; 2] = ["WAVS_ENV_OPENAI_API_KEY", "OPENAI_API_KEY"];
+
+/// OpenAI API key, shared by chat, embeddings and image generation
+///
+/// Deployments still using the unprefixed `OPENAI_API_KEY` are supported.
+pub fn openai_api_key() -> Result<String, String> {
+    required_var_of(&OPENAI_API_KEY_VARS)
+}
+
+/// Read a boolean flag, accepting `true`/`false` and `1`/`0`
+pub fn flag(name: &str, default: bool) -> bool {
+    match std::env::var(name).map(|value| value.trim().to_lowercase()) {
+        Ok(value) if value == "true" || value == "1" => true,
+        Ok(value) if value == "false" || value == "0" => false,
+        _ => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> =
+            vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_first_set_variable() {
+        let both = lookup(&[("WAVS_ENV_OPENAI_API_KEY", "new"), ("OPENAI_API_KEY", "legacy")]);
+        assert_eq!(first_set(&OPENAI_API_KEY_VARS, both).unwrap(), "new");
+
+        let legacy = lookup(&[("OPENAI_API_KEY", "legacy")]);
+        assert_eq!(first_set(&OPENAI_API_KEY_VARS, legacy).unwrap(), "legacy");
+
+        assert_eq!(
+            first_set(&OPENAI_API_KEY_VARS, lookup(&[])).unwrap_err(),
+            "Missing required variable, tried WAVS_ENV_OPENAI_API_KEY, OPENAI_API_KEY"
+        );
+    }
+}
@@ -0,0 +1,1178 @@
+use crate::warn;
+use alloy_network::Ethereum;
+use alloy_primitives::{keccak256, Address, Bytes, TxKind, B256, U256};
+use alloy_provider::{Provider, RootProvider};
+use alloy_rpc_types::{
+    BlockId, BlockTransactionsKind, Filter, Log, TransactionInput, TransactionRequest,
+};
+use alloy_sol_types::{sol, Panic, Revert, SolCall, SolError, SolEvent};
+use alloy_transport::{RpcError, TransportError, TransportErrorKind};
+use std::future::{Future, IntoFuture};
+use std::time::Duration;
+use wavs_wasi_chain::ethereum::new_eth_provider;
+
+sol! {
+    /// Subset of the Hats Protocol v1 interface used by the components
+    ///
+    /// Hats are ERC1155 tokens, so hat IDs are token IDs.
+    interface IHats {
+        function isWearerOfHat(address _user, uint256 _hatId) external view returns (bool isWearer);
+        function viewHat(uint256 _hatId) external view returns (
+            string memory details,
+            uint32 maxSupply,
+            uint32 supply,
+            address eligibility,
+            address toggle,
+            string memory imageURI,
+            uint16 lastHatId,
+            bool mutable_,
+            bool active
+        );
+        function balanceOf(address _wearer, uint256 _hatId) external view returns (uint256 balance);
+        function uri(uint256 id) external view returns (string memory _uri);
+        function isActive(uint256 _hatId) external view returns (bool active);
+        function isEligible(address _wearer, uint256 _hatId) external view returns (bool eligible);
+        function isInGoodStanding(address _wearer, uint256 _hatId) external view returns (bool standing);
+    }
+
+    /// `balanceOf` shared by ERC20 and ERC721 tokens
+    interface IBalanceOf {
+        function balanceOf(address owner) external view returns (uint256 balance);
+    }
+
+    /// ENS registry, mapping name nodes to their resolvers
+    interface IENSRegistry {
+        function resolver(bytes32 node) external view returns (address);
+    }
+
+    /// ENS resolver, for the address records and reverse records of names
+    interface IENSResolver {
+        function addr(bytes32 node) external view returns (address);
+        function name(bytes32 node) external view returns (string memory);
+    }
+
+    /// Optional ERC20 metadata extension
+    interface IERC20Metadata {
+        function decimals() external view returns (uint8);
+    }
+
+    /// Multicall3, see https://www.multicall3.com
+    interface IMulticall3 {
+        struct Call3 {
+            address target;
+            bool allowFailure;
+            bytes callData;
+        }
+
+        struct Result {
+            bool success;
+            bytes returnData;
+        }
+
+        function aggregate3(Call3[] calldata calls) external payable returns (Result[] memory returnData);
+    }
+}
+
+/// Hats Protocol v1, deployed at the same address on every supported chain
+const DEFAULT_HATS_ADDRESS: Address =
+    alloy_primitives::address!("3bc1A0Ad72417f2d411118085256fC53CBdDd137");
+
+/// Multicall3, deployed at the same address on every supported chain
+const DEFAULT_MULTICALL_ADDRESS: Address =
+    alloy_primitives::address!("cA11bde05977b3631167028862bE2a173976CA11");
+
+/// Retries of transient RPC failures used when `WAVS_ENV_RPC_RETRIES` is unset
+pub const DEFAULT_RPC_RETRIES: usize = 2;
+
+/// Pause between retries of a failed RPC call
+const RPC_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// ENS registry, deployed at the same address on mainnet and its testnets
+const DEFAULT_ENS_REGISTRY_ADDRESS: Address =
+    alloy_primitives::address!("00000000000C2E074eC69A0dFb2997BA6C7d2e1e");
+
+/// Widest block range of a log query when `WAVS_ENV_MAX_LOG_RANGE` is unset
+pub const DEFAULT_MAX_LOG_RANGE: u64 = 10_000;
+
+/// Decimals assumed for ERC20 tokens that don't implement `decimals()`
+pub const DEFAULT_ERC20_DECIMALS: u8 = 18;
+
+/// Read the RPC retry count from `WAVS_ENV_RPC_RETRIES`
+pub fn rpc_retries() -> usize {
+    std::env::var("WAVS_ENV_RPC_RETRIES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_RPC_RETRIES)
+}
+
+/// Read an address from the environment variable `name`, falling back to `default`
+fn env_address(name: &str, default: Address) -> Result<Address, String> {
+    match std::env::var(name) {
+        Ok(value) => value.parse().map_err(|e| format!("Invalid {} {}: {}", name, value, e)),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Widest block range [`query_logs`] accepts, overridable with `WAVS_ENV_MAX_LOG_RANGE`
+pub fn max_log_range() -> u64 {
+    std::env::var("WAVS_ENV_MAX_LOG_RANGE")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_MAX_LOG_RANGE)
+}
+
+/// Address of the Hats contract, overridable with `WAVS_ENV_HATS_ADDRESS`
+pub fn hats_address() -> Result<Address, String> {
+    env_address("WAVS_ENV_HATS_ADDRESS", DEFAULT_HATS_ADDRESS)
+}
+
+/// Address of the Multicall3 contract, overridable with `WAVS_ENV_MULTICALL_ADDRESS`
+pub fn multicall_address() -> Result<Address, String> {
+    env_address("WAVS_ENV_MULTICALL_ADDRESS", DEFAULT_MULTICALL_ADDRESS)
+}
+
+/// Chain used for Hats reads, overridable with `WAVS_ENV_HATS_CHAIN`
+///
+/// The name must match a chain configured for the WAVS node.
+pub fn hats_chain() -> String {
+    std::env::var("WAVS_ENV_HATS_CHAIN")
+        .ok()
+        .filter(|chain| !chain.trim().is_empty())
+        .unwrap_or_else(|| "local".to_string())
+}
+
+/// Chain used for ENS resolution, overridable with `WAVS_ENV_ENS_CHAIN`
+///
+/// ENS names live on mainnet, so this is usually not the Hats chain.
+pub fn ens_chain() -> String {
+    std::env::var("WAVS_ENV_ENS_CHAIN")
+        .ok()
+        .filter(|chain| !chain.trim().is_empty())
+        .unwrap_or_else(|| "mainnet".to_string())
+}
+
+/// Address of the ENS registry, overridable with `WAVS_ENV_ENS_REGISTRY_ADDRESS`
+pub fn ens_registry_address() -> Result<Address, String> {
+    env_address("WAVS_ENV_ENS_REGISTRY_ADDRESS", DEFAULT_ENS_REGISTRY_ADDRESS)
+}
+
+/// Create a provider for `chain` from the HTTP endpoint in its node configuration
+///
+/// Usually called through [`eth_provider!`](crate::eth_provider), which looks
+/// up the configuration with the calling component's host bindings.
+pub fn provider_for_chain(
+    chain: &str,
+    http_endpoint: Option<String>,
+) -> Result<RootProvider<Ethereum>, String> {
+    let endpoint = http_endpoint
+        .ok_or_else(|| format!("No HTTP endpoint configured for chain '{}'", chain))?;
+    Ok(new_eth_provider::<Ethereum>(endpoint))
+}
+
+/// Create a provider for a chain configured on the WAVS node
+///
+/// Expands against the calling component's `bindings` module, since each
+/// component has its own generated host bindings.
+///
+/// ```ignore
+/// let provider = wavs_hats_common::eth_provider!(&evm::hats_chain())?;
+/// ```
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)] // `crate::bindings` is intentionally the caller's
+macro_rules! eth_provider {
+    ($chain:expr) => {{
+        let chain: &str = $chain;
+        match crate::bindings::host::get_eth_chain_config(chain) {
+            Some(config) => $crate::evm::provider_for_chain(chain, config.http_endpoint),
+            None => Err(format!("No chain config found for chain '{}'", chain)),
+        }
+    }};
+}
+
+/// Execute an `eth_call` at a specific block
+///
+/// Every operator must compute the same result for a trigger, so reads are
+/// pinned to the trigger's block instead of `latest`, which can move between
+/// operators' reads when a transaction lands in between.
+///
+/// Transient failures are retried against the same block, see [`with_retry`].
+pub async fn call_at_block(
+    provider: &RootProvider<Ethereum>,
+    tx: &TransactionRequest,
+    block: BlockId,
+) -> Result<Bytes, String> {
+    retry_with_backoff(rpc_retries(), RPC_RETRY_BACKOFF, || {
+        provider.call(tx).block(block).into_future()
+    })
+    .await
+    .map_err(|e| describe_rpc_error(&e))
+}
+
+fn call_request(contract: Address, input: Vec<u8>) -> TransactionRequest {
+    TransactionRequest {
+        to: Some(TxKind::Call(contract)),
+        input: TransactionInput { input: Some(input.into()), data: None },
+        ..Default::default()
+    }
+}
+
+/// Run an RPC call, retrying up to `retries` times on transient failures
+///
+/// Connection failures, timeouts and 5xx or 429 responses are retried with a
+/// fixed backoff. Errors returned by the node itself, such as reverts, are
+/// returned immediately. `f` must issue the same request every time, pinned
+/// to the same block, so a retry can't change the result.
+pub async fn with_retry<F, Fut, T>(retries: usize, f: F) -> Result<T, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, TransportError>>,
+{
+    retry_with_backoff(retries, RPC_RETRY_BACKOFF, f).await.map_err(|e| e.to_string())
+}
+
+async fn retry_with_backoff<F, Fut, T>(
+    retries: usize,
+    backoff: Duration,
+    mut f: F,
+) -> Result<T, TransportError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, TransportError>>,
+{
+    let mut attempt = 0;
+    loop {
+        match f().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                warn!("RPC call failed, retrying ({}/{}): {}", attempt, retries, e);
+                if !backoff.is_zero() {
+                    wstd::task::sleep(backoff.into()).await;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether an RPC failure may succeed when retried
+fn is_transient(error: &TransportError) -> bool {
+    match error {
+        RpcError::Transport(TransportErrorKind::HttpError(e)) => e.status >= 500 || e.status == 429,
+        // Connection failures and timeouts surface as custom transport errors
+        RpcError::Transport(TransportErrorKind::Custom(_))
+        | RpcError::Transport(TransportErrorKind::BackendGone)
+        | RpcError::Transport(TransportErrorKind::MissingBatchResponse(_)) => true,
+        _ => false,
+    }
+}
+
+/// Describe an RPC failure, with the reason of a revert when the node returned one
+fn describe_rpc_error(error: &TransportError) -> String {
+    if let RpcError::ErrorResp(payload) = error {
+        if let Some(reason) = payload.as_revert_data().and_then(|data| decode_revert(&data)) {
+            return format!("revert: {}", reason);
+        }
+    }
+    error.to_string()
+}
+
+/// Decode the reason of a revert from its `data`
+///
+/// Recognizes `Error(string)` from `require` and `revert`, and `Panic(uint256)`
+/// from failed assertions and arithmetic. Custom errors are returned as hex,
+/// and empty data, a revert without a reason, as `None`.
+pub fn decode_revert(data: &[u8]) -> Option<String> {
+    if data.is_empty() {
+        return None;
+    }
+    if let Ok(revert) = Revert::abi_decode(data, true) {
+        return Some(revert.reason);
+    }
+    if let Ok(panic) = Panic::abi_decode(data, true) {
+        return Some(match panic.kind() {
+            Some(kind) => format!("panic: {}", kind),
+            None => format!("panic: code {:#x}", panic.code),
+        });
+    }
+    Some(Bytes::copy_from_slice(data).to_string())
+}
+
+/// Estimate the gas `tx` would use if sent at `block`
+///
+/// Lets operators plan writes ahead of sending them. A reverting `tx` fails
+/// with its revert reason, such as `revert: Not admin`.
+pub async fn estimate_gas(
+    provider: &RootProvider<Ethereum>,
+    tx: &TransactionRequest,
+    block: BlockId,
+) -> Result<U256, String> {
+    retry_with_backoff(rpc_retries(), RPC_RETRY_BACKOFF, || {
+        provider.estimate_gas(tx).block(block).into_future()
+    })
+    .await
+    .map(U256::from)
+    .map_err(|e| format!("Failed to estimate gas: {}", describe_rpc_error(&e)))
+}
+
+/// Current gas price of the chain, in wei
+pub async fn gas_price(provider: &RootProvider<Ethereum>) -> Result<U256, String> {
+    retry_with_backoff(rpc_retries(), RPC_RETRY_BACKOFF, || provider.get_gas_price().into_future())
+        .await
+        .map(U256::from)
+        .map_err(|e| format!("Failed to read gas price: {}", describe_rpc_error(&e)))
+}
+
+/// Timestamp of `block`, in seconds since the Unix epoch
+///
+/// Time-based decisions use this rather than the wall clock so every operator
+/// reaches the same result for a trigger.
+pub async fn block_timestamp(
+    provider: &RootProvider<Ethereum>,
+    block: BlockId,
+) -> Result<u64, String> {
+    let block =
+        with_retry(rpc_retries(), || provider.get_block(block, BlockTransactionsKind::Hashes))
+            .await
+            .map_err(|e| format!("Failed to fetch block {}: {}", block, e))?
+            .ok_or_else(|| format!("Block {} not found", block))?;
+    Ok(block.header.timestamp)
+}
+
+/// Timestamp of the block a trigger was emitted in, the run's "now"
+///
+/// Contract event triggers carry the `block_height` of their log but not its
+/// timestamp, so it is read from that block's header. Raw triggers have no
+/// block, pass `None` for them and this returns `None`, leaving it to the
+/// caller whether to fail or skip time-based checks. Components use this
+/// rather than a host clock, which differs between operators.
+pub async fn trigger_timestamp(
+    provider: &RootProvider<Ethereum>,
+    block_height: Option<u64>,
+) -> Result<Option<u64>, String> {
+    match block_height {
+        Some(height) => block_timestamp(provider, BlockId::number(height)).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Execute an `eth_call` against `contract` with ABI encoded `input` at `block`
+pub(crate) async fn eth_call(
+    provider: &RootProvider<Ethereum>,
+    contract: Address,
+    input: Vec<u8>,
+    block: BlockId,
+) -> Result<Bytes, String> {
+    call_at_block(provider, &call_request(contract, input), block).await
+}
+
+/// Check whether `wearer` wears `hat_id` as of `block`
+pub async fn is_wearer_of_hat(
+    provider: &RootProvider<Ethereum>,
+    wearer: Address,
+    hat_id: U256,
+    block: BlockId,
+) -> Result<bool, String> {
+    let call = IHats::isWearerOfHatCall { _user: wearer, _hatId: hat_id };
+    let result = eth_call(provider, hats_address()?, call.abi_encode(), block).await?;
+    IHats::isWearerOfHatCall::abi_decode_returns(&result, false)
+        .map(|ret| ret.isWearer)
+        .map_err(|e| format!("Failed to decode isWearerOfHat result: {}", e))
+}
+
+/// Check whether `wearer` is eligible for `hat_id` according to its eligibility module
+pub async fn is_eligible(
+    provider: &RootProvider<Ethereum>,
+    wearer: Address,
+    hat_id: U256,
+    block: BlockId,
+) -> Result<bool, String> {
+    let call = IHats::isEligibleCall { _wearer: wearer, _hatId: hat_id };
+    let result = eth_call(provider, hats_address()?, call.abi_encode(), block).await?;
+    IHats::isEligibleCall::abi_decode_returns(&result, false)
+        .map(|ret| ret.eligible)
+        .map_err(|e| format!("Failed to decode isEligible result: {}", e))
+}
+
+/// A hat as returned by `viewHat`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HatView {
+    pub details: String,
+    pub max_supply: u32,
+    pub supply: u32,
+    pub eligibility: Address,
+    pub toggle: Address,
+    pub image_uri: String,
+    pub last_hat_id: u16,
+    pub mutable: bool,
+    pub active: bool,
+}
+
+impl HatView {
+    /// Whether the hat was created, as `viewHat` returns zeros for other IDs
+    ///
+    /// The image URI is ignored, since Hats falls back to the image of an
+    /// admin or its base image for hats without one.
+    pub fn exists(&self) -> bool {
+        *self != HatView { image_uri: self.image_uri.clone(), ..Default::default() }
+    }
+}
+
+impl From<IHats::viewHatReturn> for HatView {
+    fn from(hat: IHats::viewHatReturn) -> Self {
+        Self {
+            details: hat.details,
+            max_supply: hat.maxSupply,
+            supply: hat.supply,
+            eligibility: hat.eligibility,
+            toggle: hat.toggle,
+            image_uri: hat.imageURI,
+            last_hat_id: hat.lastHatId,
+            mutable: hat.mutable_,
+            active: hat.active,
+        }
+    }
+}
+
+/// Read a hat's details, supply, modules, and status as of `block`
+///
+/// Fails for a hat that doesn't exist.
+pub async fn view_hat(
+    provider: &RootProvider<Ethereum>,
+    hat_id: U256,
+    block: BlockId,
+) -> Result<HatView, String> {
+    let call = IHats::viewHatCall { _hatId: hat_id };
+    let result = eth_call(provider, hats_address()?, call.abi_encode(), block).await?;
+    let hat = decode_view_hat(&result)?;
+    if !hat.exists() {
+        return Err(format!("Hat {:#x} does not exist", hat_id));
+    }
+    Ok(hat)
+}
+
+/// Number of `hat_id` tokens held by `wearer` as of `block`, 0 or 1 for a live hat
+pub async fn hat_balance(
+    provider: &RootProvider<Ethereum>,
+    wearer: Address,
+    hat_id: U256,
+    block: BlockId,
+) -> Result<U256, String> {
+    let call = IHats::balanceOfCall { _wearer: wearer, _hatId: hat_id };
+    let result = eth_call(provider, hats_address()?, call.abi_encode(), block).await?;
+    IHats::balanceOfCall::abi_decode_returns(&result, false)
+        .map(|ret| ret.balance)
+        .map_err(|e| format!("Failed to decode balanceOf result: {}", e))
+}
+
+/// ERC20 or ERC721 balance of `owner` in `token` as of `block`
+pub async fn token_balance(
+    provider: &RootProvider<Ethereum>,
+    token: Address,
+    owner: Address,
+    block: BlockId,
+) -> Result<U256, String> {
+    let call = IBalanceOf::balanceOfCall { owner };
+    let result = eth_call(provider, token, call.abi_encode(), block).await?;
+    IBalanceOf::balanceOfCall::abi_decode_returns(&result, false)
+        .map(|ret| ret.balance)
+        .map_err(|e| format!("Failed to decode balanceOf result: {}", e))
+}
+
+/// ERC20 balance of `holder` in `token` as of `block`, in the token's base units
+pub async fn erc20_balance(
+    provider: &RootProvider<Ethereum>,
+    token: Address,
+    holder: Address,
+    block: BlockId,
+) -> Result<U256, String> {
+    token_balance(provider, token, holder, block).await
+}
+
+/// Decimals of an ERC20 `token` as of `block`
+///
+/// `decimals()` is optional in ERC20, so a token that reverts on it or returns
+/// nothing decodable is assumed to use [`DEFAULT_ERC20_DECIMALS`]. Failures
+/// to reach the node are still returned as errors.
+pub async fn erc20_decimals(
+    provider: &RootProvider<Ethereum>,
+    token: Address,
+    block: BlockId,
+) -> Result<u8, String> {
+    let tx = call_request(token, IERC20Metadata::decimalsCall {}.abi_encode());
+    let result = retry_with_backoff(rpc_retries(), RPC_RETRY_BACKOFF, || {
+        provider.call(&tx).block(block).into_future()
+    })
+    .await;
+
+    let decoded = match result {
+        Ok(data) => IERC20Metadata::decimalsCall::abi_decode_returns(&data, false)
+            .map(|ret| ret._0)
+            .map_err(|e| e.to_string()),
+        Err(RpcError::ErrorResp(e)) => Err(e.to_string()),
+        Err(e) => return Err(format!("Failed to read decimals of {}: {}", token, e)),
+    };
+    Ok(decoded.unwrap_or_else(|e| {
+        warn!(
+            "Token {} has no usable decimals(), assuming {}: {}",
+            token, DEFAULT_ERC20_DECIMALS, e
+        );
+        DEFAULT_ERC20_DECIMALS
+    }))
+}
+
+/// Convert a decimal token `amount` such as `"1.5"` to base units of a token
+/// with `decimals`
+pub fn scale_amount(amount: &str, decimals: u8) -> Result<U256, String> {
+    let amount = amount.trim();
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+        return Err(format!("Invalid token amount {}", amount));
+    }
+    if fraction.len() > decimals as usize {
+        return Err(format!("Token amount {} has more than {} decimals", amount, decimals));
+    }
+
+    let digits = format!("{}{:0<width$}", whole, fraction, width = decimals as usize);
+    U256::from_str_radix(&digits, 10)
+        .map_err(|e| format!("Token amount {} is out of range: {}", amount, e))
+}
+
+/// Read the ERC1155 metadata URI of a hat as of `block`
+pub async fn hat_uri(
+    provider: &RootProvider<Ethereum>,
+    hat_id: U256,
+    block: BlockId,
+) -> Result<String, String> {
+    let call = IHats::uriCall { id: hat_id };
+    let result = eth_call(provider, hats_address()?, call.abi_encode(), block).await?;
+    IHats::uriCall::abi_decode_returns(&result, false)
+        .map(|ret| ret._uri)
+        .map_err(|e| format!("Failed to decode uri result: {}", e))
+}
+
+/// ENS node of a name, as defined by EIP-137
+///
+/// Labels are lowercased, which covers ASCII names but not the full ENSIP-15
+/// normalization.
+pub fn namehash(name: &str) -> B256 {
+    let name = name.trim().to_lowercase();
+    name.split('.').filter(|label| !label.is_empty()).rev().fold(B256::ZERO, |node, label| {
+        keccak256([node.as_slice(), keccak256(label.as_bytes()).as_slice()].concat())
+    })
+}
+
+/// Resolver of an ENS node as of `block`, if it has one
+async fn ens_resolver(
+    provider: &RootProvider<Ethereum>,
+    node: B256,
+    block: BlockId,
+) -> Result<Option<Address>, String> {
+    let call = IENSRegistry::resolverCall { node };
+    let result = eth_call(provider, ens_registry_address()?, call.abi_encode(), block).await?;
+    let resolver = IENSRegistry::resolverCall::abi_decode_returns(&result, false)
+        .map(|ret| ret._0)
+        .map_err(|e| format!("Failed to decode ENS resolver result: {}", e))?;
+    Ok((resolver != Address::ZERO).then_some(resolver))
+}
+
+/// Resolve an ENS name to its address as of `block`
+///
+/// `provider` must be for the chain holding the ENS registry, see [`ens_chain`].
+/// Nothing is cached, so the result always reflects `block`.
+pub async fn resolve_ens(
+    provider: &RootProvider<Ethereum>,
+    name: &str,
+    block: BlockId,
+) -> Result<Address, String> {
+    let node = namehash(name);
+    let resolver = ens_resolver(provider, node, block)
+        .await?
+        .ok_or_else(|| format!("ENS name {} not found", name))?;
+
+    let call = IENSResolver::addrCall { node };
+    let result = eth_call(provider, resolver, call.abi_encode(), block).await?;
+    let address = decode_ens_addr(&result)?;
+    if address == Address::ZERO {
+        return Err(format!("ENS name {} not found", name));
+    }
+    Ok(address)
+}
+
+/// Decode the result of a resolver's `addr(bytes32)`
+pub fn decode_ens_addr(data: &[u8]) -> Result<Address, String> {
+    IENSResolver::addrCall::abi_decode_returns(data, false)
+        .map(|ret| ret._0)
+        .map_err(|e| format!("Failed to decode ENS addr result: {}", e))
+}
+
+/// Primary ENS name of `address` as of `block`, if it has one
+///
+/// The reverse record is only trusted when the name resolves back to
+/// `address`, since anyone can set any name as their reverse record.
+pub async fn lookup_ens(
+    provider: &RootProvider<Ethereum>,
+    address: Address,
+    block: BlockId,
+) -> Result<Option<String>, String> {
+    let node = namehash(&format!("{:x}.addr.reverse", address));
+    let Some(resolver) = ens_resolver(provider, node, block).await? else {
+        return Ok(None);
+    };
+
+    let call = IENSResolver::nameCall { node };
+    let result = eth_call(provider, resolver, call.abi_encode(), block).await?;
+    let name = IENSResolver::nameCall::abi_decode_returns(&result, false)
+        .map(|ret| ret._0)
+        .map_err(|e| format!("Failed to decode ENS name result: {}", e))?;
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    match resolve_ens(provider, &name, block).await {
+        Ok(resolved) if resolved == address => Ok(Some(name)),
+        _ => Ok(None),
+    }
+}
+
+/// Logs emitted by `address` between `from_block` and `to_block` inclusive
+///
+/// `topics` filters by position, with `None` matching any topic, so the first
+/// entry is usually an event's signature hash. Ranges wider than
+/// [`max_log_range`] are rejected, since nodes time out or refuse them.
+pub async fn query_logs(
+    provider: &RootProvider<Ethereum>,
+    address: Address,
+    topics: &[Option<B256>],
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<Log>, String> {
+    if to_block < from_block {
+        return Err(format!("Invalid block range {} to {}", from_block, to_block));
+    }
+    let max_range = max_log_range();
+    if to_block - from_block >= max_range {
+        return Err(format!(
+            "Block range {} to {} exceeds the maximum of {} blocks",
+            from_block, to_block, max_range
+        ));
+    }
+    if topics.len() > 4 {
+        return Err(format!("Logs have at most 4 topics, got {}", topics.len()));
+    }
+
+    let mut filter = Filter::new().address(address).from_block(from_block).to_block(to_block);
+    for (position, topic) in topics.iter().enumerate() {
+        if let Some(topic) = topic {
+            filter.topics[position] = (*topic).into();
+        }
+    }
+
+    with_retry(rpc_retries(), || provider.get_logs(&filter))
+        .await
+        .map_err(|e| format!("Failed to query logs of {}: {}", address, e))
+}
+
+/// Decode the logs of event `E`, skipping logs of other events
+pub fn decode_logs<E: SolEvent>(logs: &[Log]) -> Result<Vec<E>, String> {
+    logs.iter()
+        .filter(|log| log.topic0() == Some(&E::SIGNATURE_HASH))
+        .map(|log| {
+            E::decode_log(&log.inner, true)
+                .map(|log| log.data)
+                .map_err(|e| format!("Failed to decode {} log: {}", E::SIGNATURE, e))
+        })
+        .collect()
+}
+
+/// Execute several calls in one `eth_call` through Multicall3
+///
+/// All calls are evaluated against the same `block` state, so the results are
+/// consistent with each other. Returns the raw return data of each call in
+/// order, failing if any call reverts.
+pub async fn multicall(
+    provider: &RootProvider<Ethereum>,
+    calls: &[(Address, Vec<u8>)],
+    block: BlockId,
+) -> Result<Vec<Bytes>, String> {
+    if calls.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let call = IMulticall3::aggregate3Call {
+        calls: calls
+            .iter()
+            .map(|(target, call_data)| IMulticall3::Call3 {
+                target: *target,
+                allowFailure: false,
+                callData: call_data.clone().into(),
+            })
+            .collect(),
+    };
+
+    let result = eth_call(provider, multicall_address()?, call.abi_encode(), block).await?;
+    decode_multicall_results(&result)
+}
+
+/// Whether each hat in `hat_ids` is active as of `block`, in order
+pub async fn batch_hat_status(
+    provider: &RootProvider<Ethereum>,
+    hat_ids: &[U256],
+    block: BlockId,
+) -> Result<Vec<bool>, String> {
+    let hats = hats_address()?;
+    let calls: Vec<_> = hat_ids
+        .iter()
+        .map(|hat_id| (hats, IHats::isActiveCall { _hatId: *hat_id }.abi_encode()))
+        .collect();
+
+    multicall(provider, &calls, block)
+        .await?
+        .iter()
+        .map(|data| {
+            IHats::isActiveCall::abi_decode_returns(data, false)
+                .map(|ret| ret.active)
+                .map_err(|e| format!("Failed to decode isActive result: {}", e))
+        })
+        .collect()
+}
+
+fn decode_multicall_results(data: &[u8]) -> Result<Vec<Bytes>, String> {
+    let results = IMulticall3::aggregate3Call::abi_decode_returns(data, false)
+        .map_err(|e| format!("Failed to decode aggregate3 result: {}", e))?
+        .returnData;
+
+    results
+        .into_iter()
+        .enumerate()
+        .map(|(i, result)| {
+            if result.success {
+                Ok(result.returnData)
+            } else {
+                Err(format!("Multicall call {} failed", i))
+            }
+        })
+        .collect()
+}
+
+/// Decode the return data of a `viewHat` call
+pub fn decode_view_hat(data: &[u8]) -> Result<HatView, String> {
+    IHats::viewHatCall::abi_decode_returns(data, false)
+        .map(HatView::from)
+        .map_err(|e| format!("Failed to decode viewHat result: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use wstd::runtime::block_on;
+
+    #[test]
+    fn test_decode_view_hat() {
+        let encoded = IHats::viewHatCall::abi_encode_returns(&(
+            "Top Hat".to_string(),
+            1u32,
+            1u32,
+            Address::ZERO,
+            Address::repeat_byte(0xaa),
+            "ipfs://QmImage".to_string(),
+            3u16,
+            false,
+            true,
+        ));
+
+        let hat = decode_view_hat(&encoded).unwrap();
+        assert_eq!(
+            hat,
+            HatView {
+                details: "Top Hat".to_string(),
+                max_supply: 1,
+                supply: 1,
+                eligibility: Address::ZERO,
+                toggle: Address::repeat_byte(0xaa),
+                image_uri: "ipfs://QmImage".to_string(),
+                last_hat_id: 3,
+                mutable: false,
+                active: true,
+            }
+        );
+        assert!(hat.exists());
+
+        assert!(decode_view_hat(&encoded[..64]).is_err());
+    }
+
+    #[test]
+    fn test_view_missing_hat() {
+        // Hats returns zeros for an unknown hat, apart from a fallback image
+        let transport = crate::mock::MockTransport::new(|_, _| {
+            let encoded = IHats::viewHatCall::abi_encode_returns(&(
+                String::new(),
+                0u32,
+                0u32,
+                Address::ZERO,
+                Address::ZERO,
+                "ipfs://QmBase".to_string(),
+                0u16,
+                false,
+                false,
+            ));
+            Ok(json!(Bytes::from(encoded)))
+        });
+        let result = block_on(view_hat(&transport.provider(), U256::from(5), BlockId::number(1)));
+        assert_eq!(result.unwrap_err(), "Hat 0x5 does not exist");
+    }
+
+    #[test]
+    fn test_call_at_block_pins_block_number() {
+        let transport = crate::mock::MockTransport::new(|method, _| {
+            assert_eq!(method, "eth_call");
+            Ok(json!("0x0000000000000000000000000000000000000000000000000000000000000001"))
+        });
+        let provider = transport.provider();
+
+        let tx = TransactionRequest {
+            to: Some(TxKind::Call(Address::repeat_byte(0x01))),
+            input: TransactionInput { input: Some(vec![0x12, 0x34].into()), data: None },
+            ..Default::default()
+        };
+        let result = block_on(call_at_block(&provider, &tx, BlockId::number(1234))).unwrap();
+        assert_eq!(result[31], 1);
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests[0]["params"][1], "0x4d2");
+    }
+
+    #[test]
+    fn test_trigger_timestamp() {
+        let transport = crate::mock::MockTransport::new(|method, params| {
+            assert_eq!(method, "eth_getBlockByNumber");
+            assert_eq!(params[0], "0x4d2");
+            Ok(json!({
+                "hash": B256::repeat_byte(0x01),
+                "parentHash": B256::ZERO,
+                "sha3Uncles": B256::ZERO,
+                "miner": Address::ZERO,
+                "stateRoot": B256::ZERO,
+                "transactionsRoot": B256::ZERO,
+                "receiptsRoot": B256::ZERO,
+                "logsBloom": format!("0x{}", "00".repeat(256)),
+                "difficulty": "0x0",
+                "number": "0x4d2",
+                "gasLimit": "0x0",
+                "gasUsed": "0x0",
+                "timestamp": "0x67c6b2c0",
+                "extraData": "0x",
+                "mixHash": B256::ZERO,
+                "nonce": "0x0000000000000000",
+                "uncles": [],
+                "transactions": [],
+            }))
+        });
+        let provider = transport.provider();
+
+        assert_eq!(block_on(trigger_timestamp(&provider, Some(1234))).unwrap(), Some(1741075136));
+        assert_eq!(block_on(trigger_timestamp(&provider, None)).unwrap(), None);
+        assert_eq!(transport.requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_retry_transient_failure() {
+        let transport = crate::mock::MockTransport::new({
+            let calls = std::sync::atomic::AtomicUsize::new(0);
+            move |_, _| match calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Err("connection reset".to_string()),
+                _ => Ok(json!("0x01")),
+            }
+        });
+        let provider = transport.provider();
+        let tx = TransactionRequest::default();
+
+        let result = block_on(retry_with_backoff(2, Duration::ZERO, || {
+            provider.call(&tx).block(BlockId::number(1234)).into_future()
+        }));
+        assert_eq!(result.unwrap(), Bytes::from(vec![1]));
+
+        // The retry queries the same block
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0]["params"][1], requests[1]["params"][1]);
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let transport = crate::mock::MockTransport::new(|_, _| Err("timed out".to_string()));
+        let provider = transport.provider();
+        let tx = TransactionRequest::default();
+
+        let result =
+            block_on(retry_with_backoff(2, Duration::ZERO, || provider.call(&tx).into_future()));
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        assert_eq!(transport.requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_node_errors_are_not_retried() {
+        let error: TransportError = RpcError::ErrorResp(alloy_json_rpc::ErrorPayload {
+            code: 3,
+            message: "execution reverted".into(),
+            data: None,
+        });
+        assert!(!is_transient(&error));
+        assert!(is_transient(&TransportErrorKind::custom_str("connection refused")));
+    }
+
+    #[test]
+    fn test_describe_revert() {
+        let revert = Revert::from("Not admin").abi_encode();
+        let data = serde_json::value::to_raw_value(&Bytes::from(revert)).unwrap();
+        let error: TransportError = RpcError::ErrorResp(alloy_json_rpc::ErrorPayload {
+            code: 3,
+            message: "execution reverted: Not admin".into(),
+            data: Some(data),
+        });
+        assert_eq!(describe_rpc_error(&error), "revert: Not admin");
+
+        // Without revert data the node's message is kept
+        let error: TransportError = RpcError::ErrorResp(alloy_json_rpc::ErrorPayload {
+            code: -32000,
+            message: "insufficient funds".into(),
+            data: None,
+        });
+        assert!(describe_rpc_error(&error).contains("insufficient funds"));
+    }
+
+    #[test]
+    fn test_decode_revert() {
+        let error = Revert::from("wearer not eligible").abi_encode();
+        assert_eq!(&error[..4], &[0x08, 0xc3, 0x79, 0xa0]);
+        assert_eq!(decode_revert(&error).as_deref(), Some("wearer not eligible"));
+
+        let panic = Panic::from(U256::from(0x11)).abi_encode();
+        assert_eq!(&panic[..4], &[0x4e, 0x48, 0x7b, 0x71]);
+        assert_eq!(
+            decode_revert(&panic).as_deref(),
+            Some("panic: arithmetic underflow or overflow")
+        );
+        let panic = Panic::from(U256::from(0x99)).abi_encode();
+        assert_eq!(decode_revert(&panic).as_deref(), Some("panic: code 0x99"));
+
+        // Custom errors fall back to hex
+        assert_eq!(decode_revert(&[0xde, 0xad, 0xbe, 0xef, 0x01]).as_deref(), Some("0xdeadbeef01"));
+        assert_eq!(decode_revert(&[]), None);
+    }
+
+    #[test]
+    fn test_call_at_block_decodes_revert() {
+        let transport = crate::mock::MockTransport::new(|_, _| {
+            Err(crate::mock::revert(&Revert::from("wearer not eligible").abi_encode()))
+        });
+        let provider = transport.provider();
+        let error =
+            block_on(is_wearer_of_hat(&provider, Address::ZERO, U256::from(1), BlockId::number(1)))
+                .unwrap_err();
+        assert_eq!(error, "revert: wearer not eligible");
+    }
+
+    #[test]
+    fn test_estimate_gas_and_gas_price() {
+        let transport = crate::mock::MockTransport::new(|method, _| match method {
+            "eth_estimateGas" => Ok(json!("0x5208")),
+            "eth_gasPrice" => Ok(json!("0x3b9aca00")),
+            method => panic!("Unexpected method {}", method),
+        });
+        let provider = transport.provider();
+
+        let gas =
+            block_on(estimate_gas(&provider, &TransactionRequest::default(), BlockId::number(9)));
+        assert_eq!(gas, Ok(U256::from(21_000)));
+        assert_eq!(block_on(gas_price(&provider)), Ok(U256::from(1_000_000_000u64)));
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests[0]["params"][1], "0x9");
+    }
+
+    #[test]
+    fn test_erc20_decimals() {
+        let decimals = |response: Result<Value, String>| {
+            let transport = crate::mock::MockTransport::new(move |_, _| response.clone());
+            block_on(erc20_decimals(&transport.provider(), Address::ZERO, BlockId::latest()))
+        };
+
+        let six = IERC20Metadata::decimalsCall::abi_encode_returns(&(6u8,));
+        assert_eq!(decimals(Ok(json!(Bytes::from(six)))), Ok(6));
+        // Tokens without decimals() revert or return nothing
+        assert_eq!(decimals(Err(crate::mock::REVERT.to_string())), Ok(DEFAULT_ERC20_DECIMALS));
+        assert_eq!(decimals(Ok(json!("0x"))), Ok(DEFAULT_ERC20_DECIMALS));
+    }
+
+    #[test]
+    fn test_scale_amount() {
+        assert_eq!(scale_amount("100", 6), Ok(U256::from(100_000_000u64)));
+        assert_eq!(scale_amount("1.5", 6), Ok(U256::from(1_500_000u64)));
+        assert_eq!(scale_amount("0.000001", 6), Ok(U256::from(1u64)));
+        assert_eq!(scale_amount("1", 18), Ok(U256::from(10u64).pow(U256::from(18u64))));
+        assert_eq!(scale_amount("7", 0), Ok(U256::from(7u64)));
+
+        assert!(scale_amount("0.0000001", 6).unwrap_err().contains("more than 6 decimals"));
+        for invalid in ["", ".", "1e6", "-1", "1.2.3"] {
+            assert!(scale_amount(invalid, 18).is_err(), "{} should be rejected", invalid);
+        }
+    }
+
+    #[test]
+    fn test_decode_multicall_results() {
+        let active = IHats::isActiveCall::abi_encode_returns(&(true,));
+        let inactive = IHats::isActiveCall::abi_encode_returns(&(false,));
+        let encoded = IMulticall3::aggregate3Call::abi_encode_returns(&(vec![
+            IMulticall3::Result { success: true, returnData: active.clone().into() },
+            IMulticall3::Result { success: true, returnData: inactive.clone().into() },
+        ],));
+
+        let results = decode_multicall_results(&encoded).unwrap();
+        assert_eq!(results, vec![Bytes::from(active), Bytes::from(inactive)]);
+        assert!(!IHats::isActiveCall::abi_decode_returns(&results[1], false).unwrap().active);
+
+        let encoded = IMulticall3::aggregate3Call::abi_encode_returns(&(vec![
+            IMulticall3::Result { success: true, returnData: Bytes::new() },
+            IMulticall3::Result { success: false, returnData: Bytes::new() },
+        ],));
+        assert_eq!(decode_multicall_results(&encoded).unwrap_err(), "Multicall call 1 failed");
+    }
+
+    #[test]
+    fn test_namehash() {
+        assert_eq!(namehash(""), B256::ZERO);
+        assert_eq!(
+            namehash("eth"),
+            alloy_primitives::b256!(
+                "93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+            )
+        );
+        assert_eq!(
+            namehash("foo.eth"),
+            alloy_primitives::b256!(
+                "de9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+            )
+        );
+        assert_eq!(namehash("Foo.ETH"), namehash("foo.eth"));
+    }
+
+    #[test]
+    fn test_decode_ens_addr() {
+        let address = Address::repeat_byte(0xab);
+        let encoded = IENSResolver::addrCall::abi_encode_returns(&(address,));
+        assert_eq!(decode_ens_addr(&encoded), Ok(address));
+        assert!(decode_ens_addr(&encoded[..16]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_ens() {
+        let resolver = Address::repeat_byte(0x0e);
+        let resolved = Address::repeat_byte(0xab);
+        let registry = DEFAULT_ENS_REGISTRY_ADDRESS;
+        let transport = crate::mock::MockTransport::new(move |_, params| {
+            let to: Address = serde_json::from_value(params[0]["to"].clone()).unwrap();
+            let encoded = if to == registry {
+                IENSRegistry::resolverCall::abi_encode_returns(&(resolver,))
+            } else {
+                assert_eq!(to, resolver);
+                IENSResolver::addrCall::abi_encode_returns(&(resolved,))
+            };
+            Ok(json!(Bytes::from(encoded)))
+        });
+
+        let result = block_on(resolve_ens(&transport.provider(), "hats.eth", BlockId::number(5)));
+        assert_eq!(result, Ok(resolved));
+
+        // Both reads are pinned to the same block
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request["params"][1] == "0x5"));
+    }
+
+    #[test]
+    fn test_resolve_ens_name_not_found() {
+        let transport = crate::mock::MockTransport::new(|_, _| {
+            Ok(json!(Bytes::from(IENSRegistry::resolverCall::abi_encode_returns(
+                &(Address::ZERO,)
+            ))))
+        });
+        let result = block_on(resolve_ens(&transport.provider(), "nobody.eth", BlockId::latest()));
+        assert_eq!(result.unwrap_err(), "ENS name nobody.eth not found");
+    }
+
+    mod types {
+        alloy_sol_types::sol!("../../src/interfaces/IHatsAvsTypes.sol");
+    }
+    use types::IHatsAvsTypes::{HatCreationRejected, MintingTrigger};
+
+    #[test]
+    fn test_query_and_decode_logs() {
+        let minter = Address::repeat_byte(0x11);
+        let minted = |trigger_id: u64| MintingTrigger {
+            triggerId: trigger_id,
+            creator: Address::repeat_byte(0x22),
+            hatId: U256::from(7),
+            wearer: Address::repeat_byte(0x33),
+        };
+        let rejected = HatCreationRejected { triggerId: 3, reason: "too long".to_string() };
+        let log = |data: alloy_primitives::LogData, index: u64| {
+            json!({
+                "address": minter,
+                "topics": data.topics(),
+                "data": data.data,
+                "blockHash": B256::repeat_byte(0x64),
+                "blockNumber": "0x64",
+                "transactionHash": B256::repeat_byte(0x01),
+                "transactionIndex": "0x0",
+                "logIndex": format!("{:#x}", index),
+                "removed": false,
+            })
+        };
+        let logs = json!([
+            log(minted(1).encode_log_data(), 0),
+            log(rejected.encode_log_data(), 1),
+            log(minted(2).encode_log_data(), 2),
+        ]);
+        let transport = crate::mock::MockTransport::new(move |method, _| {
+            assert_eq!(method, "eth_getLogs");
+            Ok(logs.clone())
+        });
+
+        let topics = [Some(MintingTrigger::SIGNATURE_HASH)];
+        let logs = block_on(query_logs(&transport.provider(), minter, &topics, 100, 199)).unwrap();
+        assert_eq!(logs.len(), 3);
+
+        let events: Vec<MintingTrigger> = decode_logs(&logs).unwrap();
+        assert_eq!(events.iter().map(|e| e.triggerId).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(events[0].wearer, Address::repeat_byte(0x33));
+        let events: Vec<HatCreationRejected> = decode_logs(&logs).unwrap();
+        assert_eq!(events[0].reason, "too long");
+
+        let requests = transport.requests.lock().unwrap();
+        let filter = &requests[0]["params"][0];
+        assert_eq!(filter["fromBlock"], "0x64");
+        assert_eq!(filter["toBlock"], "0xc7");
+        assert_eq!(filter["address"], json!(minter));
+        assert_eq!(filter["topics"][0], json!(MintingTrigger::SIGNATURE_HASH));
+    }
+
+    #[test]
+    fn test_query_logs_rejects_wide_ranges() {
+        let transport = crate::mock::MockTransport::new(|_, _| panic!("No request expected"));
+        let provider = transport.provider();
+
+        let result = block_on(query_logs(&provider, Address::ZERO, &[], 0, DEFAULT_MAX_LOG_RANGE));
+        assert!(result.unwrap_err().contains("exceeds the maximum of 10000 blocks"));
+        let result = block_on(query_logs(&provider, Address::ZERO, &[], 10, 9));
+        assert_eq!(result.unwrap_err(), "Invalid block range 10 to 9");
+    }
+
+    #[test]
+    fn test_provider_for_chain_missing_endpoint() {
+        let err = provider_for_chain("sepolia", None).unwrap_err();
+        assert_eq!(err, "No HTTP endpoint configured for chain 'sepolia'");
+    }
+}
@@ -0,0 +1,140 @@
+//! Hat ID bit layout
+//!
+//! A hat ID is 256 bits. The top 32 bits are the domain of the tree's top
+//! hat, followed by 14 levels of 16 bits each, from the top down. A hat at
+//! level n has the first n level fields set and the rest zero, so a top hat
+//! is at level 0 and its child hats at level 1. Trees linked under another
+//! tree's hat keep their own domain, so levels here are within a domain.
+
+use alloy_primitives::U256;
+
+/// Bits of the top hat domain at the top of a hat ID
+pub const DOMAIN_BITS: usize = 32;
+
+/// Bits of each level below the top hat
+pub const LEVEL_BITS: usize = 16;
+
+/// Levels below the top hat that fit in a hat ID
+pub const MAX_LEVELS: usize = 14;
+
+/// ID of the top hat of `domain`
+pub fn format_top_hat(domain: u32) -> U256 {
+    U256::from(domain) << (256 - DOMAIN_BITS)
+}
+
+/// Expand a bare top hat domain, such as 1, to the ID of its top hat
+///
+/// A hat ID always has a domain in its top bits, so an ID that fits in the
+/// domain bits can only mean a domain. Other IDs are returned as they are.
+pub fn expand_top_hat_domain(hat_id: U256) -> U256 {
+    match u32::try_from(hat_id) {
+        Ok(domain) if domain != 0 => {
+            crate::debug!("Converting hat ID {} to the top hat of domain {}", hat_id, domain);
+            format_top_hat(domain)
+        }
+        _ => hat_id,
+    }
+}
+
+/// Whether `hat_id` is a top hat, with a domain and no level below it set
+pub fn is_top_hat(hat_id: U256) -> bool {
+    domain(hat_id) != 0 && level(hat_id) == 0
+}
+
+/// Domain of the tree `hat_id` belongs to, its top 32 bits
+pub fn domain(hat_id: U256) -> u32 {
+    (hat_id >> (256 - DOMAIN_BITS)).to::<u32>()
+}
+
+/// Level of `hat_id` in its tree, 0 for a top hat
+pub fn level(hat_id: U256) -> usize {
+    (1..=MAX_LEVELS).rev().find(|&level| level_field(hat_id, level) != 0).unwrap_or(0)
+}
+
+/// Admin of `hat_id`, the hat at the level above it
+///
+/// A top hat is its own admin.
+pub fn admin(hat_id: U256) -> U256 {
+    match level(hat_id) {
+        0 => hat_id,
+        level => hat_id & !(U256::MAX >> (DOMAIN_BITS + (level - 1) * LEVEL_BITS)),
+    }
+}
+
+/// The 16 bit field of `hat_id` at `level`, from 1 to [`MAX_LEVELS`]
+fn level_field(hat_id: U256, level: usize) -> u16 {
+    let shift = 256 - DOMAIN_BITS - level * LEVEL_BITS;
+    ((hat_id >> shift) & U256::from(u16::MAX)).to::<u16>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hat(hex: &str) -> U256 {
+        U256::from_str_radix(hex, 16).unwrap()
+    }
+
+    #[test]
+    fn test_top_hat() {
+        let top = hat("0000000100000000000000000000000000000000000000000000000000000000");
+        assert_eq!(domain(top), 1);
+        assert_eq!(level(top), 0);
+        assert_eq!(admin(top), top);
+    }
+
+    #[test]
+    fn test_format_top_hat() {
+        assert_eq!(format_top_hat(1), U256::from(1) << 224);
+        assert_eq!(
+            format_top_hat(42),
+            hat("0000002a00000000000000000000000000000000000000000000000000000000")
+        );
+        assert_eq!(
+            format_top_hat(u32::MAX),
+            hat("ffffffff00000000000000000000000000000000000000000000000000000000")
+        );
+        for domain in [1, 42, u32::MAX] {
+            assert!(is_top_hat(format_top_hat(domain)));
+            assert_eq!(self::domain(format_top_hat(domain)), domain);
+        }
+    }
+
+    #[test]
+    fn test_expand_top_hat_domain() {
+        assert_eq!(expand_top_hat_domain(U256::from(1)), U256::from(1) << 224);
+        assert_eq!(expand_top_hat_domain(U256::from(7)), format_top_hat(7));
+        let child = hat("0000000100010000000000000000000000000000000000000000000000000000");
+        assert_eq!(expand_top_hat_domain(child), child);
+        assert_eq!(expand_top_hat_domain(U256::ZERO), U256::ZERO);
+    }
+
+    #[test]
+    fn test_is_top_hat() {
+        assert!(!is_top_hat(U256::ZERO));
+        // A bare domain number isn't a hat ID
+        assert!(!is_top_hat(U256::from(1)));
+        assert!(!is_top_hat(hat(
+            "0000000100010000000000000000000000000000000000000000000000000000"
+        )));
+        assert!(!is_top_hat(hat(
+            "000000010001000000000000000000000000000000000000000000000000ffff"
+        )));
+    }
+
+    #[test]
+    fn test_child_hats() {
+        let top = hat("0000000100000000000000000000000000000000000000000000000000000000");
+        let child = hat("0000000100010000000000000000000000000000000000000000000000000000");
+        let grandchild = hat("0000000100010002000000000000000000000000000000000000000000000000");
+        assert_eq!((domain(child), level(child), admin(child)), (1, 1, top));
+        assert_eq!((domain(grandchild), level(grandchild), admin(grandchild)), (1, 2, child));
+
+        let deepest = hat("0000002a0001000100010001000100010001000100010001000100010001ffff");
+        let parent = hat("0000002a00010001000100010001000100010001000100010001000100010000");
+        assert_eq!(domain(deepest), 42);
+        assert_eq!(level(deepest), MAX_LEVELS);
+        assert_eq!(admin(deepest), parent);
+        assert_eq!(level(parent), MAX_LEVELS - 1);
+    }
+}
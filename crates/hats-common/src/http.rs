@@ -0,0 +1,135 @@
+//! Minimal HTTP helpers for fetching remote content
+
+use wstd::http::{Client, Request};
+use wstd::io::{empty, AsyncRead};
+
+/// Size of the chunks response bodies are read in
+const CHUNK_SIZE: usize = 8192;
+
+/// GET `url` and return the response body, failing on a non-2xx status
+pub async fn get(url: &str) -> Result<Vec<u8>, String> {
+    send_get(url, usize::MAX).await
+}
+
+/// GET `url` like [`get`], failing once the body passes `max_bytes`
+pub async fn get_capped(url: &str, max_bytes: usize) -> Result<Vec<u8>, String> {
+    let body = send_get(url, max_bytes.saturating_add(1)).await?;
+    if body.len() > max_bytes {
+        return Err(format!(
+            "Failed to read response from {}: body is larger than the limit of {} bytes",
+            url, max_bytes
+        ));
+    }
+    Ok(body)
+}
+
+/// GET `url` like [`get`], returning only the first `max_bytes` of the body
+pub async fn get_prefix(url: &str, max_bytes: usize) -> Result<Vec<u8>, String> {
+    send_get(url, max_bytes).await
+}
+
+async fn send_get(url: &str, max_bytes: usize) -> Result<Vec<u8>, String> {
+    let request = Request::get(url)
+        .body(empty())
+        .map_err(|e| format!("Failed to create request for {}: {}", url, e))?;
+
+    let mut response = Client::new()
+        .send(request)
+        .await
+        .map_err(|e| format!("Request to {} failed: {}", url, e))?;
+
+    let body = read_prefix(response.body_mut(), max_bytes)
+        .await
+        .map_err(|e| format!("Failed to read response from {}: {}", url, e))?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Request to {} failed with status {}: {}",
+            url,
+            response.status(),
+            String::from_utf8_lossy(&body)
+        ));
+    }
+
+    Ok(body)
+}
+
+/// Read `reader` to the end, or until it passes `max_bytes`
+///
+/// Reading stops at the first chunk past the cap, so an oversized body is
+/// never buffered in full.
+pub async fn read_capped(
+    reader: impl AsyncRead,
+    max_bytes: Option<usize>,
+) -> Result<Vec<u8>, String> {
+    let limit = max_bytes.map_or(usize::MAX, |max_bytes| max_bytes.saturating_add(1));
+    let body = read_prefix(reader, limit).await?;
+    match max_bytes {
+        Some(max_bytes) if body.len() > max_bytes => {
+            Err(format!("body is larger than the limit of {} bytes", max_bytes))
+        }
+        _ => Ok(body),
+    }
+}
+
+/// Read `reader` to the end, or only its first `max_bytes`
+///
+/// Reading stops once `max_bytes` have been read, so the rest of a long
+/// body is never buffered.
+pub async fn read_prefix(mut reader: impl AsyncRead, max_bytes: usize) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    let mut chunk = [0; CHUNK_SIZE];
+    while body.len() < max_bytes {
+        let read = reader.read(&mut chunk).await.map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Ok(body);
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(max_bytes);
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wstd::runtime::block_on;
+
+    /// Body that never ends, counting the bytes read from it
+    struct Endless {
+        read: usize,
+    }
+
+    impl AsyncRead for Endless {
+        async fn read(&mut self, buf: &mut [u8]) -> wstd::io::Result<usize> {
+            buf.fill(b'a');
+            self.read += buf.len();
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_read_capped() {
+        let body = block_on(read_capped(wstd::io::Cursor::new(vec![1; 20_000]), Some(20_000)));
+        assert_eq!(body.unwrap().len(), 20_000);
+        let body = block_on(read_capped(wstd::io::Cursor::new(vec![1; 20_000]), None));
+        assert_eq!(body.unwrap().len(), 20_000);
+
+        // Reading stops once the cap is passed
+        let mut endless = Endless { read: 0 };
+        let err = block_on(read_capped(&mut endless, Some(10_000))).unwrap_err();
+        assert_eq!(err, "body is larger than the limit of 10000 bytes");
+        assert!(endless.read <= 10_000 + CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_read_prefix() {
+        let body = block_on(read_prefix(wstd::io::Cursor::new(vec![1; 100]), 1_000));
+        assert_eq!(body.unwrap().len(), 100);
+
+        let mut endless = Endless { read: 0 };
+        let body = block_on(read_prefix(&mut endless, 10_000)).unwrap();
+        assert_eq!(body.len(), 10_000);
+        assert!(endless.read <= 10_000 + CHUNK_SIZE);
+    }
+}
@@ -0,0 +1,101 @@
+//! Opt-in deduplication of triggers delivered more than once, enabled by
+//! `WAVS_ENV_IDEMPOTENT`
+//!
+//! A reorg or replay can deliver the same trigger again, and a component
+//! would recompute it and submit a second result. With deduplication on, the
+//! output of each trigger ID is stored and returned again for a repeat.
+//!
+//! WAVS components are stateless and the host has no key-value binding, so
+//! outputs are stored as files in `WAVS_ENV_IDEMPOTENCY_DIR` (default
+//! `/tmp/wavs-hats-results`). This only deduplicates when the host gives the
+//! component a writable directory that outlives a single execution. Without
+//! one, reads find nothing and every trigger is computed as usual. A failure
+//! to store an output is logged and does not fail the run.
+
+use crate::{debug, dry_run, warn};
+use std::path::PathBuf;
+
+/// Directory outputs are stored in unless `WAVS_ENV_IDEMPOTENCY_DIR` is set
+pub const DEFAULT_DIR: &str = "/tmp/wavs-hats-results";
+
+/// Whether triggers are deduplicated, opted into with `WAVS_ENV_IDEMPOTENT`
+///
+/// Dry runs are never deduplicated, so they always show a fresh result.
+pub fn enabled() -> bool {
+    crate::env::flag("WAVS_ENV_IDEMPOTENT", false) && !dry_run::enabled()
+}
+
+/// Outputs of previous triggers, one file per component and trigger ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultStore {
+    dir: PathBuf,
+}
+
+impl ResultStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Store in `WAVS_ENV_IDEMPOTENCY_DIR`, or [`DEFAULT_DIR`]
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("WAVS_ENV_IDEMPOTENCY_DIR").unwrap_or(DEFAULT_DIR.to_string()))
+    }
+
+    fn path(&self, component: &str, trigger_id: u64) -> PathBuf {
+        self.dir.join(format!("{}-{}.bin", component, trigger_id))
+    }
+
+    /// Output stored for `trigger_id` of `component`, if it was seen before
+    pub fn get(&self, component: &str, trigger_id: u64) -> Option<Vec<u8>> {
+        std::fs::read(self.path(component, trigger_id)).ok()
+    }
+
+    /// Store the output of `trigger_id` of `component`
+    pub fn put(&self, component: &str, trigger_id: u64, output: &[u8]) -> Result<(), String> {
+        std::fs::create_dir_all(&self.dir)
+            .and_then(|()| std::fs::write(self.path(component, trigger_id), output))
+            .map_err(|e| format!("Failed to store output of trigger {}: {}", trigger_id, e))
+    }
+}
+
+/// Output previously computed for `trigger_id`, when deduplication is enabled
+pub fn cached(component: &str, trigger_id: u64) -> Option<Vec<u8>> {
+    if !enabled() {
+        return None;
+    }
+    let output = ResultStore::from_env().get(component, trigger_id)?;
+    debug!("Trigger {} was already processed, returning its stored output", trigger_id);
+    Some(output)
+}
+
+/// Store the output of `trigger_id`, when deduplication is enabled
+pub fn record(component: &str, trigger_id: u64, output: &[u8]) {
+    if !enabled() {
+        return;
+    }
+    if let Err(e) = ResultStore::from_env().put(component, trigger_id, output) {
+        warn!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_seen_and_repeated_triggers() {
+        let dir = std::env::temp_dir().join(format!("wavs-hats-results-{}", std::process::id()));
+        let store = ResultStore::new(&dir);
+
+        // A new trigger is computed, then stored
+        assert_eq!(store.get("minter", 7), None);
+        store.put("minter", 7, b"output").unwrap();
+
+        // A repeat returns the stored output, per component
+        assert_eq!(store.get("minter", 7).as_deref(), Some(&b"output"[..]));
+        assert_eq!(store.get("minter", 8), None);
+        assert_eq!(store.get("creator", 7), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
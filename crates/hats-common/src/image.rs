@@ -0,0 +1,446 @@
+use crate::{debug, error};
+use anyhow::Result;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use wstd::{
+    http::{Client, IntoBody, Request},
+    io::AsyncRead,
+};
+
+// Request structure for stable diffusion
+#[derive(Serialize, Deserialize)]
+struct StableDiffusionRequest {
+    prompt: String,
+    negative_prompt: String,
+    seed: i64,            // Fixed seed for determinism
+    steps: u32,           // Number of inference steps
+    cfg_scale: f32,       // Guidance scale
+    width: u32,           // Image width
+    height: u32,          // Image height
+    sampler_name: String, // Specific sampler to use
+    model: String,        // Specific model checkpoint
+}
+
+// Response structure with flexible parameter handling
+#[derive(Deserialize, Debug)]
+struct StableDiffusionResponse {
+    images: Vec<String>, // Base64 encoded images
+    #[serde(default)] // Make parameters optional
+    #[allow(dead_code)]
+    parameters: Option<serde_json::Value>, // Use generic Value to handle any response structure
+}
+
+/// Generate a deterministic image using Stable Diffusion API
+pub async fn generate_deterministic_image(prompt: &str) -> Result<String, String> {
+    // Get API URL from environment variable
+    let api_url = std::env::var("WAVS_ENV_SD_API_URL")
+        .unwrap_or_else(|_| "http://localhost:7860/sdapi/v1/txt2img".to_string());
+
+    // Get API key from environment variable
+    let api_key = std::env::var("WAVS_ENV_SD_API_KEY").unwrap_or_default();
+
+    // Fixed parameters for deterministic generation
+    let request_data = StableDiffusionRequest {
+        prompt: prompt.to_string(),
+        negative_prompt: "blurry, bad quality, distorted".to_string(),
+        seed: 42, // Always use the same seed
+        steps: 30,
+        cfg_scale: 7.0,
+        width: 512,
+        height: 512,
+        sampler_name: "DPM++ 2M Karras".to_string(),
+        model: "v1-5-pruned-emaonly".to_string(), // Match the model specified by the user
+    };
+
+    // Serialize to JSON
+    let json_data = serde_json::to_string(&request_data)
+        .map_err(|e| format!("JSON serialization error: {}", e))?;
+
+    // For debugging
+    debug!("Sending request to Stable Diffusion API: {}", json_data);
+
+    // Create POST request with headers
+    let mut req = Request::post(&api_url)
+        .header("Content-Type", "application/json")
+        .header("Accept", "application/json")
+        .body(json_data.as_bytes().into_body())
+        .map_err(|e| e.to_string())?;
+
+    // Add API key if provided
+    if !api_key.is_empty() {
+        req = Request::post(&api_url)
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json")
+            .header("Authorization", &format!("Bearer {}", api_key))
+            .body(json_data.as_bytes().into_body())
+            .map_err(|e| e.to_string())?;
+    }
+
+    // Make request
+    let mut response =
+        Client::new().send(req).await.map_err(|e| format!("Request failed: {}", e))?;
+
+    // Check for success
+    if response.status() != 200 {
+        let mut error_body = Vec::new();
+        response
+            .body_mut()
+            .read_to_end(&mut error_body)
+            .await
+            .map_err(|e| format!("Failed to read error response: {}", e))?;
+
+        let error_text = String::from_utf8_lossy(&error_body);
+        return Err(format!("API error: status {} - {}", response.status(), error_text));
+    }
+
+    // Read response body
+    let mut body_buf = Vec::new();
+    response
+        .body_mut()
+        .read_to_end(&mut body_buf)
+        .await
+        .map_err(|e| format!("Failed to read response body: {}", e))?;
+
+    // Try parsing with our structured response first
+    let image_base64 = match serde_json::from_slice::<StableDiffusionResponse>(&body_buf) {
+        Ok(sd_response) => {
+            // Return the first image (base64 encoded)
+            sd_response.images.first().cloned().ok_or_else(|| "No image generated".to_string())?
+        }
+        Err(e) => {
+            // Fallback: try parsing just to get the images array
+            error!("Error parsing full response: {}", e);
+
+            // Try parsing as generic JSON Value
+            let json_value: serde_json::Value = serde_json::from_slice(&body_buf)
+                .map_err(|e| format!("Failed to parse response as JSON: {}", e))?;
+
+            // Extract images array from the generic JSON
+            if let Some(images) = json_value.get("images").and_then(|i| i.as_array()) {
+                if let Some(first_image) = images.first().and_then(|i| i.as_str()) {
+                    first_image.to_string()
+                } else {
+                    return Err("Could not extract image from response".to_string());
+                }
+            } else {
+                return Err("No images array found in response".to_string());
+            }
+        }
+    };
+
+    // Format as data URI with proper MIME type
+    // Stable Diffusion typically returns PNG images
+    Ok(format!("data:image/png;base64,{}", image_base64))
+}
+
+/// OpenAI image generation endpoint
+const OPENAI_IMAGES_URL: &str = "https://api.openai.com/v1/images/generations";
+
+/// Image sizes accepted by [`generate`]
+///
+/// DALL-E 2 serves the smaller sizes and DALL-E 3 the larger ones.
+pub const ALLOWED_SIZES: [&str; 5] = ["256x256", "512x512", "1024x1024", "1792x1024", "1024x1792"];
+
+#[derive(Deserialize)]
+struct OpenAIImagesResponse {
+    data: Vec<OpenAIImage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAIImage {
+    b64_json: Option<String>,
+}
+
+/// Generate a PNG image for `prompt` with DALL-E, `size` being one of [`ALLOWED_SIZES`]
+///
+/// DALL-E takes no seed, so the image differs on every call, unlike
+/// [`generate_deterministic_image`].
+pub async fn generate(prompt: &str, size: &str) -> Result<Vec<u8>, String> {
+    let body = build_generate_body(prompt, size)?;
+    let api_key = crate::env::openai_api_key()?;
+
+    let request = Request::post(OPENAI_IMAGES_URL)
+        .header("Content-Type", "application/json")
+        .header("Authorization", &format!("Bearer {}", api_key))
+        .body(body.to_string().into_body())
+        .map_err(|e| format!("Failed to create image request: {}", e))?;
+
+    let mut response =
+        Client::new().send(request).await.map_err(|e| format!("Request failed: {}", e))?;
+
+    let mut body_buf = Vec::new();
+    response
+        .body_mut()
+        .read_to_end(&mut body_buf)
+        .await
+        .map_err(|e| format!("Failed to read response body: {}", e))?;
+
+    if response.status() != 200 {
+        return Err(format!(
+            "API error: status {} - {}",
+            response.status(),
+            String::from_utf8_lossy(&body_buf)
+        ));
+    }
+
+    parse_generate_response(&body_buf)
+}
+
+fn build_generate_body(prompt: &str, size: &str) -> Result<serde_json::Value, String> {
+    if prompt.trim().is_empty() {
+        return Err("Image prompt cannot be empty".to_string());
+    }
+    if !ALLOWED_SIZES.contains(&size) {
+        return Err(format!(
+            "Unsupported image size {}, expected one of {:?}",
+            size, ALLOWED_SIZES
+        ));
+    }
+
+    let model = if matches!(size, "256x256" | "512x512") { "dall-e-2" } else { "dall-e-3" };
+    Ok(serde_json::json!({
+        "model": model,
+        "prompt": prompt,
+        "n": 1,
+        "size": size,
+        "response_format": "b64_json"
+    }))
+}
+
+fn parse_generate_response(body: &[u8]) -> Result<Vec<u8>, String> {
+    let response: OpenAIImagesResponse = serde_json::from_slice(body)
+        .map_err(|e| format!("Failed to parse image response: {}", e))?;
+    let image = response
+        .data
+        .into_iter()
+        .next()
+        .and_then(|image| image.b64_json)
+        .ok_or_else(|| "No image data in response".to_string())?;
+
+    base64::engine::general_purpose::STANDARD
+        .decode(image)
+        .map_err(|e| format!("Failed to decode image data: {}", e))
+}
+
+/// Image formats accepted for hat images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+}
+
+impl ImageFormat {
+    /// Detect the format from the file signature
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(PNG_SIGNATURE) {
+            Some(Self::Png)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Self::Jpeg)
+        } else {
+            None
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+/// Format and dimensions of a validated image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Check that `bytes` is a PNG or JPEG of at most `max_bytes` and read its dimensions
+pub fn validate(bytes: &[u8], max_bytes: usize) -> Result<ImageInfo, String> {
+    if bytes.len() > max_bytes {
+        return Err(format!("Image is {} bytes, more than the {} allowed", bytes.len(), max_bytes));
+    }
+
+    let format =
+        ImageFormat::detect(bytes).ok_or_else(|| "Image is not a PNG or JPEG".to_string())?;
+    let (width, height) = match format {
+        ImageFormat::Png => png_dimensions(bytes)?,
+        ImageFormat::Jpeg => jpeg_dimensions(bytes)?,
+    };
+
+    if width == 0 || height == 0 {
+        return Err(format!("Image has invalid dimensions {}x{}", width, height));
+    }
+    Ok(ImageInfo { format, width, height })
+}
+
+/// Embed `bytes` as a base64 data URI, typed by its detected format
+pub fn to_data_uri(bytes: &[u8]) -> String {
+    let mime_type =
+        ImageFormat::detect(bytes).map_or("application/octet-stream", |f| f.mime_type());
+    encode_data_uri(bytes, mime_type)
+}
+
+/// Embed `bytes` as a base64 data URI of type `mime`
+pub fn encode_data_uri(bytes: &[u8], mime: &str) -> String {
+    format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// Split a base64 data URI into its MIME type and decoded bytes
+///
+/// Only base64 data URIs are accepted, and a missing type is read as
+/// `text/plain` as in RFC 2397.
+pub fn decode_data_uri(uri: &str) -> Result<(String, Vec<u8>), String> {
+    let rest = uri.strip_prefix("data:").ok_or_else(|| "Not a data URI".to_string())?;
+    let (meta, data) =
+        rest.split_once(',').ok_or_else(|| "Data URI is missing its data".to_string())?;
+    let mime =
+        meta.strip_suffix(";base64").ok_or_else(|| "Data URI is not base64 encoded".to_string())?;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| format!("Invalid base64 in data URI: {}", e))?;
+
+    let mime = if mime.is_empty() { "text/plain" } else { mime };
+    Ok((mime.to_string(), bytes))
+}
+
+/// Read the dimensions from the IHDR chunk, which must come first
+fn png_dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    let ihdr = bytes.get(8..24).ok_or_else(|| "Truncated PNG header".to_string())?;
+    if &ihdr[4..8] != b"IHDR" {
+        return Err("PNG is missing its IHDR chunk".to_string());
+    }
+    let width = u32::from_be_bytes(ihdr[8..12].try_into().unwrap());
+    let height = u32::from_be_bytes(ihdr[12..16].try_into().unwrap());
+    Ok((width, height))
+}
+
+/// Walk the JPEG segments up to the start-of-frame marker holding the dimensions
+fn jpeg_dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    let truncated = || "Truncated JPEG".to_string();
+    let mut offset = 2;
+
+    loop {
+        let marker = bytes.get(offset..offset + 4).ok_or_else(truncated)?;
+        if marker[0] != 0xff {
+            return Err("Invalid JPEG segment marker".to_string());
+        }
+        let length = u16::from_be_bytes([marker[2], marker[3]]) as usize;
+
+        // SOF0-SOF15, except DHT (C4), JPG (C8) and DAC (CC)
+        if matches!(marker[1], 0xc0..=0xcf) && !matches!(marker[1], 0xc4 | 0xc8 | 0xcc) {
+            let frame = bytes.get(offset + 5..offset + 9).ok_or_else(truncated)?;
+            let height = u16::from_be_bytes([frame[0], frame[1]]) as u32;
+            let width = u16::from_be_bytes([frame[2], frame[3]]) as u32;
+            return Ok((width, height));
+        }
+
+        offset += 2 + length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_body() {
+        let body = build_generate_body("A red hat", "1024x1024").unwrap();
+        assert_eq!(body["model"], "dall-e-3");
+        assert_eq!(body["response_format"], "b64_json");
+        assert_eq!(build_generate_body("A red hat", "256x256").unwrap()["model"], "dall-e-2");
+
+        let err = build_generate_body("A red hat", "640x480").unwrap_err();
+        assert!(err.starts_with("Unsupported image size 640x480"));
+        assert!(build_generate_body(" ", "256x256").is_err());
+    }
+
+    #[test]
+    fn test_parse_generate_response() {
+        // Base64 of the PNG signature
+        let body = br#"{"created": 1700000000, "data": [{"b64_json": "iVBORw0KGgo="}]}"#;
+        assert_eq!(parse_generate_response(body).unwrap(), b"\x89PNG\r\n\x1a\n");
+
+        assert!(parse_generate_response(br#"{"data": []}"#).is_err());
+        assert!(parse_generate_response(br#"{"data": [{"b64_json": "!!"}]}"#).is_err());
+    }
+
+    /// PNG signature followed by an IHDR chunk with the given dimensions
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = PNG_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&13u32.to_be_bytes());
+        bytes.extend_from_slice(b"IHDR");
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(&[8, 6, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn test_validate_png() {
+        let info = validate(&png_header(512, 256), 1024).unwrap();
+        assert_eq!(info, ImageInfo { format: ImageFormat::Png, width: 512, height: 256 });
+
+        assert_eq!(validate(&png_header(1, 1)[..20], 1024).unwrap_err(), "Truncated PNG header");
+        assert!(validate(&png_header(0, 1), 1024).unwrap_err().contains("invalid dimensions"));
+        assert!(validate(&png_header(1, 1), 16).unwrap_err().contains("more than the 16 allowed"));
+    }
+
+    #[test]
+    fn test_validate_jpeg() {
+        // SOI, an APP0 segment, then SOF0 with height 300 and width 400
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00];
+        jpeg.extend_from_slice(&[0xff, 0xc0, 0x00, 0x11, 0x08, 0x01, 0x2c, 0x01, 0x90, 0x03]);
+        let info = validate(&jpeg, 1024).unwrap();
+        assert_eq!(info, ImageInfo { format: ImageFormat::Jpeg, width: 400, height: 300 });
+
+        assert_eq!(validate(&jpeg[..10], 1024).unwrap_err(), "Truncated JPEG");
+    }
+
+    #[test]
+    fn test_validate_invalid_bytes() {
+        assert_eq!(validate(b"GIF89a", 1024).unwrap_err(), "Image is not a PNG or JPEG");
+        assert!(validate(b"", 1024).is_err());
+    }
+
+    #[test]
+    fn test_to_data_uri() {
+        assert_eq!(to_data_uri(PNG_SIGNATURE), "data:image/png;base64,iVBORw0KGgo=");
+        assert_eq!(to_data_uri(b"hi"), "data:application/octet-stream;base64,aGk=");
+    }
+
+    #[test]
+    fn test_data_uri_round_trip() {
+        let png = png_header(1, 1);
+        let uri = encode_data_uri(&png, "image/png");
+        assert!(uri.starts_with("data:image/png;base64,iVBORw0KGgo"));
+        let (mime, bytes) = decode_data_uri(&uri).unwrap();
+        assert_eq!(mime, "image/png");
+        assert_eq!(bytes, png);
+        assert_eq!(decode_data_uri(&to_data_uri(&png)).unwrap(), (mime, png));
+
+        assert_eq!(
+            decode_data_uri("data:;base64,aGk=").unwrap(),
+            ("text/plain".to_string(), b"hi".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_invalid_data_uri() {
+        assert_eq!(decode_data_uri("ipfs://bafy").unwrap_err(), "Not a data URI");
+        assert_eq!(
+            decode_data_uri("data:image/png;base64").unwrap_err(),
+            "Data URI is missing its data"
+        );
+        assert_eq!(
+            decode_data_uri("data:image/png,hi").unwrap_err(),
+            "Data URI is not base64 encoded"
+        );
+        let err = decode_data_uri("data:image/png;base64,iVBOR!w0").unwrap_err();
+        assert!(err.starts_with("Invalid base64 in data URI"));
+    }
+}
@@ -0,0 +1,787 @@
+use crate::{debug, error, info, warn};
+use anyhow::Result;
+use serde::Deserialize;
+use std::{
+    fmt::Display,
+    fs::File,
+    future::Future,
+    io::{Read, Write},
+    str::FromStr,
+};
+use wstd::future::FutureExt;
+use wstd::http::{IntoBody, Request};
+use wstd::io::AsyncRead;
+use wstd::time::Duration;
+
+// TODO actually store file? REFACTOR ME
+// async fn download_file(cid: &str, ipfs_url: &str) -> Result<String> {
+//     let url = format!("{}/api/v0/cat?arg={}", ipfs_url, cid);
+//     let response = wstd::http::Client::new().get(url).await?;
+//     let body = response.text().await?;
+//     Ok(body)
+// }
+
+/// Uploads a file using multipart request to IPFS
+async fn upload_to_ipfs(file_path: &str, ipfs_url: &str) -> Result<String> {
+    let api_key = std::env::var("WAVS_ENV_LIGHTHOUSE_API_KEY")
+        .map_err(|e| anyhow::anyhow!("Failed to get API key: {}", e))?;
+
+    info!("Uploading file to IPFS: {}", file_path);
+
+    let mut file = File::open(file_path)?;
+    let mut file_bytes = Vec::new();
+    file.read_to_end(&mut file_bytes)?;
+
+    // define multipart request boundary
+    let boundary = "----RustBoundary";
+
+    // construct the body
+    let body = format!(
+        "--{}\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n",
+        boundary, file_path
+    );
+
+    let mut request_body = body.into_bytes();
+    request_body.extend_from_slice(&file_bytes);
+    request_body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    let request = Request::post(ipfs_url)
+        .header("Authorization", &format!("Bearer {}", api_key))
+        .header("Content-Type", &format!("multipart/form-data; boundary={}", boundary))
+        .body(request_body.into_body())?;
+
+    let mut response = wstd::http::Client::new().send(request).await?;
+
+    if response.status().is_success() {
+        let mut body_buf = Vec::new();
+        response.body_mut().read_to_end(&mut body_buf).await?;
+
+        // Log the raw response for debugging
+        let response_str = std::str::from_utf8(&body_buf)
+            .map_err(|e| anyhow::anyhow!("Failed to convert response to string: {}", e))?;
+        debug!("IPFS API Response: {}", response_str);
+
+        // Parse using Lighthouse's response format (capitalized fields)
+        #[allow(non_snake_case)]
+        #[derive(Debug, Deserialize)]
+        struct LighthouseResponse {
+            Hash: String,
+        }
+
+        let hash = match serde_json::from_slice::<LighthouseResponse>(&body_buf) {
+            Ok(resp) => resp.Hash,
+            Err(e) => {
+                // Simple fallback - just look for the hash in the response text
+                error!("Failed to parse response: {}", e);
+
+                if let Some(start) = response_str.find("\"Hash\":\"") {
+                    if let Some(end) = response_str[start + 8..].find("\"") {
+                        return Ok(response_str[start + 8..start + 8 + end].to_string());
+                    }
+                }
+
+                // If that fails too, try lowercase
+                if let Some(start) = response_str.find("\"hash\":\"") {
+                    if let Some(end) = response_str[start + 8..].find("\"") {
+                        return Ok(response_str[start + 8..start + 8 + end].to_string());
+                    }
+                }
+
+                return Err(anyhow::anyhow!(
+                    "Could not extract hash from response: {}",
+                    response_str
+                ));
+            }
+        };
+
+        // Return the hash directly
+        Ok(hash)
+    } else {
+        let mut body_buf = Vec::new();
+        response.body_mut().read_to_end(&mut body_buf).await?;
+        let error_body = std::str::from_utf8(&body_buf).unwrap_or("unable to read error body");
+        Err(anyhow::anyhow!(
+            "Failed to upload to IPFS. Status: {:?}, Body: {}",
+            response.status(),
+            error_body
+        ))
+    }
+}
+
+/// Uploads JSON data directly to IPFS and returns the CID
+pub async fn upload_json_to_ipfs(json_data: &str, ipfs_url: &str) -> Result<String> {
+    // Create a temporary file to store the JSON data
+    let filename = "nft_metadata.json".to_string();
+    let temp_path = format!("/tmp/{}", filename);
+
+    debug!("Temp path {}", temp_path);
+
+    // Ensure the /tmp directory exists
+    std::fs::create_dir_all("/tmp")
+        .map_err(|e| anyhow::anyhow!("Failed to create /tmp directory: {}", e))?;
+
+    // Write JSON to temporary file
+    let mut file = File::create(&temp_path)?;
+    file.write_all(json_data.as_bytes())?;
+
+    // Upload the file
+    let hash = upload_to_ipfs(&temp_path, ipfs_url).await?;
+
+    // Clean up the temporary file
+    delete_file(&temp_path)?;
+
+    // Return the IPFS URI
+    Ok(get_ipfs_url(&hash, Some(&filename)))
+}
+
+/// Uploads an image to IPFS and returns the CID
+pub async fn upload_image_to_ipfs(
+    image_data: &[u8],
+    filename: &str,
+    ipfs_url: &str,
+) -> Result<String> {
+    // Create a temporary file to store the image data
+    let temp_path = format!("/tmp/{}", filename);
+
+    // Ensure the /tmp directory exists
+    std::fs::create_dir_all("/tmp")
+        .map_err(|e| anyhow::anyhow!("Failed to create /tmp directory: {}", e))?;
+
+    // Write image data to temporary file
+    let mut file = File::create(&temp_path)?;
+    file.write_all(image_data)?;
+
+    // Upload the file
+    let hash = upload_to_ipfs(&temp_path, ipfs_url).await?;
+
+    // Clean up the temporary file
+    delete_file(&temp_path)?;
+
+    // Return the IPFS URI
+    Ok(get_ipfs_url(&hash, Some(filename)))
+}
+
+/// Delete a file from the filesystem
+pub fn delete_file(file_path: &str) -> Result<()> {
+    std::fs::remove_file(file_path)?;
+    debug!("File deleted successfully: {}", file_path);
+    Ok(())
+}
+
+/// Default gateway used to read IPFS content
+const DEFAULT_IPFS_GATEWAY: &str = "https://ipfs.io/ipfs/";
+
+/// Public gateway tried when the primary gateway fails, unless
+/// `WAVS_ENV_IPFS_GATEWAYS` is set
+const FALLBACK_IPFS_GATEWAY: &str = "https://dweb.link/ipfs/";
+
+/// Time a gateway has to return content unless `WAVS_ENV_IPFS_TIMEOUT_SECS` is set
+pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// Largest content fetched unless `WAVS_ENV_IPFS_MAX_BYTES` is set, 10 MiB
+pub const DEFAULT_MAX_BYTES: usize = 10 * 1024 * 1024;
+
+/// Limits of each gateway request made by [`fetch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchLimits {
+    pub timeout_secs: u64,
+    pub max_bytes: usize,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        Self { timeout_secs: DEFAULT_TIMEOUT_SECS, max_bytes: DEFAULT_MAX_BYTES }
+    }
+}
+
+impl FetchLimits {
+    /// Read `WAVS_ENV_IPFS_TIMEOUT_SECS` and `WAVS_ENV_IPFS_MAX_BYTES`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name| std::env::var(name).ok();
+        Self {
+            timeout_secs: parse_positive(
+                "WAVS_ENV_IPFS_TIMEOUT_SECS",
+                var("WAVS_ENV_IPFS_TIMEOUT_SECS"),
+                defaults.timeout_secs,
+            ),
+            max_bytes: parse_positive(
+                "WAVS_ENV_IPFS_MAX_BYTES",
+                var("WAVS_ENV_IPFS_MAX_BYTES"),
+                defaults.max_bytes,
+            ),
+        }
+    }
+}
+
+/// Parse the positive integer `value` of the variable `name`, warning and
+/// using `default` when it is not one
+fn parse_positive<T: FromStr + Default + PartialOrd + Display>(
+    name: &str,
+    value: Option<String>,
+    default: T,
+) -> T {
+    let Some(value) = value else {
+        return default;
+    };
+    match value.trim().parse::<T>() {
+        Ok(parsed) if parsed > T::default() => parsed,
+        _ => {
+            warn!("Invalid {} {:?}, using {}", name, value, default);
+            default
+        }
+    }
+}
+
+/// Multicodec of raw binary content, hashed directly into the CID
+const RAW_CODEC: u64 = 0x55;
+
+/// Multicodec of UnixFS protobuf nodes, the codec of every CIDv0
+const DAG_PB_CODEC: u64 = 0x70;
+
+/// Largest file `ipfs add` stores as a single block with the default chunker
+const MAX_SINGLE_BLOCK_SIZE: usize = 262_144;
+
+/// Multihash code of sha2-256
+const SHA2_256: u64 = 0x12;
+
+/// Normalize `ipfs://CID[/path]`, `/ipfs/CID[/path]` and bare CIDs to `CID[/path]`
+pub fn normalize_cid_path(cid: &str) -> Result<String, String> {
+    let cid = cid.trim();
+    let path = cid
+        .strip_prefix("ipfs://")
+        .or_else(|| cid.strip_prefix("/ipfs/"))
+        .unwrap_or(cid)
+        .trim_matches('/');
+
+    if path.is_empty() || path.contains("://") {
+        return Err(format!("Not an IPFS CID or URI: {}", cid));
+    }
+    Ok(path.to_string())
+}
+
+/// Gateways to read IPFS content from, in the order they are tried
+///
+/// `WAVS_ENV_IPFS_GATEWAYS` is a comma separated list of gateways. Without
+/// it, the primary gateway `WAVS_ENV_IPFS_GATEWAY`, defaulting to ipfs.io, is
+/// tried before dweb.link.
+pub fn gateways() -> Vec<String> {
+    parse_gateways(
+        std::env::var("WAVS_ENV_IPFS_GATEWAYS").ok().as_deref(),
+        std::env::var("WAVS_ENV_IPFS_GATEWAY").ok().as_deref(),
+    )
+}
+
+/// Gateways of the `list`, or of the `primary` gateway and the fallback when
+/// it names none
+///
+/// Empty entries are ignored and a repeated gateway is only kept the first
+/// time, ignoring trailing slashes.
+fn parse_gateways(list: Option<&str>, primary: Option<&str>) -> Vec<String> {
+    let listed: Vec<_> =
+        list.unwrap_or_default().split(',').map(str::trim).filter(|g| !g.is_empty()).collect();
+    let candidates = if listed.is_empty() {
+        vec![primary.unwrap_or(DEFAULT_IPFS_GATEWAY), FALLBACK_IPFS_GATEWAY]
+    } else {
+        listed
+    };
+
+    let mut gateways = Vec::new();
+    for gateway in candidates {
+        let gateway = gateway.trim_end_matches('/').to_string();
+        if !gateways.contains(&gateway) {
+            gateways.push(gateway);
+        }
+    }
+    gateways
+}
+
+/// Gateway URLs to try for `cid`, in the order of [`gateways`]
+pub fn gateway_urls(cid: &str) -> Result<Vec<String>, String> {
+    let path = normalize_cid_path(cid)?;
+    Ok(gateways().iter().map(|gateway| format!("{}/{}", gateway, path)).collect())
+}
+
+/// Fetch IPFS content by CID or `ipfs://` URI, falling back across gateways
+///
+/// Content is checked with [`verify_cid`] where the CID alone determines it,
+/// and a gateway returning other bytes is treated as failed, so every
+/// operator sees the same content.
+///
+/// Each gateway request is limited by [`FetchLimits::from_env`], so a slow
+/// gateway or an oversized file fails over to the next gateway.
+pub async fn fetch(cid: &str) -> Result<Vec<u8>, String> {
+    let path = normalize_cid_path(cid)?;
+    let limits = FetchLimits::from_env();
+    fetch_first(&path, gateway_urls(&path)?, |url| async move {
+        let deadline = wstd::task::sleep(Duration::from_secs(limits.timeout_secs));
+        let request = crate::http::get_capped(&url, limits.max_bytes);
+        within(request, deadline, &url, limits.timeout_secs).await
+    })
+    .await
+}
+
+/// Request `urls` in order with `get` until one returns the content of `path`
+///
+/// When every gateway fails, the error lists why each of them did.
+async fn fetch_first<F, Fut>(path: &str, urls: Vec<String>, mut get: F) -> Result<Vec<u8>, String>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>, String>>,
+{
+    let mut errors = Vec::new();
+    for url in urls {
+        match get(url.clone()).await {
+            Ok(bytes) => match verify_content(path, &bytes) {
+                Ok(()) => return Ok(bytes),
+                Err(e) => errors.push(format!("{}: {}", url, e)),
+            },
+            Err(e) => errors.push(e),
+        }
+    }
+
+    Err(format!("Failed to fetch {} from all IPFS gateways: {}", path, errors.join("; ")))
+}
+
+/// Await the gateway `request` to `url` until `deadline` fires after `secs`
+async fn within(
+    request: impl Future<Output = Result<Vec<u8>, String>>,
+    deadline: impl Future,
+    url: &str,
+    secs: u64,
+) -> Result<Vec<u8>, String> {
+    request
+        .timeout(deadline)
+        .await
+        .unwrap_or_else(|_| Err(format!("Request to {} timed out after {}s", url, secs)))
+}
+
+/// Check whether `bytes` is the content addressed by `cid`
+///
+/// Supports sha2-256 CIDs of raw content (`bafk...`) and of single-block
+/// UnixFS files as created by `ipfs add`, in CIDv0 (`Qm...`) or CIDv1
+/// (`bafy...`) form. Other codecs and hash functions are an error.
+pub fn verify_cid(cid: &str, bytes: &[u8]) -> Result<bool, String> {
+    use sha2::{Digest, Sha256};
+
+    let parsed = cid::Cid::try_from(cid).map_err(|e| format!("Invalid CID {}: {}", cid, e))?;
+    if parsed.hash().code() != SHA2_256 {
+        return Err(format!(
+            "Unsupported CID hash function {:#x} in {}",
+            parsed.hash().code(),
+            cid
+        ));
+    }
+
+    let block = match parsed.codec() {
+        RAW_CODEC => bytes.to_vec(),
+        DAG_PB_CODEC if bytes.len() <= MAX_SINGLE_BLOCK_SIZE => unixfs_file_block(bytes),
+        DAG_PB_CODEC => {
+            return Err(format!("Unsupported multi-block UnixFS content for CID {}", cid));
+        }
+        codec => return Err(format!("Unsupported CID codec {:#x} in {}", codec, cid)),
+    };
+
+    Ok(parsed.hash().digest() == Sha256::digest(block).as_slice())
+}
+
+/// Check fetched content against the CID of `path`, skipping content the CID
+/// alone can't determine
+fn verify_content(path: &str, bytes: &[u8]) -> Result<(), String> {
+    // Content under a directory is addressed through the directory's links
+    if path.contains('/') {
+        return Ok(());
+    }
+
+    match verify_cid(path, bytes) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("Hash mismatch for CID {}", path)),
+        Err(e) if e.starts_with("Unsupported") => {
+            warn!("Skipping content verification: {}", e);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Encode `data` as the dag-pb block `ipfs add` creates for a single-chunk file
+fn unixfs_file_block(data: &[u8]) -> Vec<u8> {
+    // UnixFS Data message: Type = File, Data, filesize
+    let mut unixfs = vec![0x08, 0x02];
+    if !data.is_empty() {
+        unixfs.push(0x12);
+        push_varint(&mut unixfs, data.len() as u64);
+        unixfs.extend_from_slice(data);
+    }
+    unixfs.push(0x18);
+    push_varint(&mut unixfs, data.len() as u64);
+
+    // PBNode with only the Data field
+    let mut block = vec![0x0a];
+    push_varint(&mut block, unixfs.len() as u64);
+    block.extend_from_slice(&unixfs);
+    block
+}
+
+fn push_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Pinata endpoints for pinning JSON and files
+const PINATA_PIN_JSON_URL: &str = "https://api.pinata.cloud/pinning/pinJSONToIPFS";
+const PINATA_PIN_FILE_URL: &str = "https://api.pinata.cloud/pinning/pinFileToIPFS";
+
+/// Boundary of multipart file uploads
+const MULTIPART_BOUNDARY: &str = "----RustBoundary";
+
+/// HTTP request to a pinning service, built separately from sending for testing
+#[derive(Debug)]
+struct PinRequest {
+    url: &'static str,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+/// Pin a JSON document to IPFS through Pinata and return its CID
+///
+/// Authenticates with `WAVS_ENV_PINATA_JWT`. Use [`get_ipfs_url`] to turn the
+/// CID into an `ipfs://` URI, e.g. for a hat's imageURI.
+pub async fn pin_json(value: &serde_json::Value) -> Result<String, String> {
+    send_pin_request(pin_json_request(value)?).await
+}
+
+/// Pin raw bytes to IPFS through Pinata under the file name `name` and return
+/// the CID
+pub async fn pin_bytes(bytes: &[u8], name: &str) -> Result<String, String> {
+    send_pin_request(pin_bytes_request(bytes, name)).await
+}
+
+fn pin_json_request(value: &serde_json::Value) -> Result<PinRequest, String> {
+    let body = serde_json::to_vec(&serde_json::json!({ "pinataContent": value }))
+        .map_err(|e| format!("Failed to serialize JSON to pin: {}", e))?;
+    Ok(PinRequest { url: PINATA_PIN_JSON_URL, content_type: "application/json".to_string(), body })
+}
+
+fn pin_bytes_request(bytes: &[u8], name: &str) -> PinRequest {
+    let mut body = format!(
+        "--{boundary}\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n",
+        boundary = MULTIPART_BOUNDARY,
+        name = name
+    )
+    .into_bytes();
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(
+        format!(
+            "\r\n--{boundary}\r\n\
+             Content-Disposition: form-data; name=\"pinataMetadata\"\r\n\r\n\
+             {metadata}\r\n--{boundary}--\r\n",
+            boundary = MULTIPART_BOUNDARY,
+            metadata = serde_json::json!({ "name": name })
+        )
+        .as_bytes(),
+    );
+
+    PinRequest {
+        url: PINATA_PIN_FILE_URL,
+        content_type: format!("multipart/form-data; boundary={}", MULTIPART_BOUNDARY),
+        body,
+    }
+}
+
+async fn send_pin_request(pin: PinRequest) -> Result<String, String> {
+    let jwt = std::env::var("WAVS_ENV_PINATA_JWT")
+        .map_err(|_| "Missing required variable WAVS_ENV_PINATA_JWT".to_string())?;
+
+    let request = Request::post(pin.url)
+        .header("Authorization", &format!("Bearer {}", jwt))
+        .header("Content-Type", &pin.content_type)
+        .body(pin.body.into_body())
+        .map_err(|e| format!("Failed to create pin request: {}", e))?;
+
+    let mut response = wstd::http::Client::new()
+        .send(request)
+        .await
+        .map_err(|e| format!("Pin request failed: {}", e))?;
+
+    let mut body = Vec::new();
+    response
+        .body_mut()
+        .read_to_end(&mut body)
+        .await
+        .map_err(|e| format!("Failed to read pin response: {}", e))?;
+
+    parse_pin_response(response.status().as_u16(), &body)
+}
+
+/// Extract the CID from a Pinata response
+fn parse_pin_response(status: u16, body: &[u8]) -> Result<String, String> {
+    match status {
+        200..=299 => {}
+        401 | 403 => {
+            return Err(format!(
+                "Pinata rejected the credentials in WAVS_ENV_PINATA_JWT (status {}): {}",
+                status,
+                String::from_utf8_lossy(body)
+            ))
+        }
+        _ => {
+            return Err(format!(
+                "Pinning failed with status {}: {}",
+                status,
+                String::from_utf8_lossy(body)
+            ))
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct PinataResponse {
+        #[serde(rename = "IpfsHash")]
+        ipfs_hash: String,
+    }
+
+    serde_json::from_slice::<PinataResponse>(body).map(|response| response.ipfs_hash).map_err(|e| {
+        format!("Failed to parse pin response {}: {}", String::from_utf8_lossy(body), e)
+    })
+}
+
+/// Get IPFS URL from CID
+/// If filename is provided, constructs a URL that points to a file within a directory
+pub fn get_ipfs_url(cid: &str, filename: Option<&str>) -> String {
+    match filename {
+        Some(name) => format!("ipfs://{}/{}", cid, name),
+        None => format!("ipfs://{}", cid),
+    }
+}
+
+/// Uploads NFT content (metadata and/or image) to IPFS
+/// Returns the IPFS URI (ipfs://CID) for the content
+pub async fn upload_nft_content(
+    content_type: &str,
+    content: &[u8],
+    ipfs_url: &str,
+) -> Result<String> {
+    // Determine if this is JSON metadata or an image
+    let ipfs_uri = if content_type.contains("json") || content_type == "application/json" {
+        // It's JSON metadata
+        let json_str = std::str::from_utf8(content)
+            .map_err(|e| anyhow::anyhow!("Failed to convert JSON bytes to string: {}", e))?;
+
+        // Upload the JSON and return the IPFS URI
+        upload_json_to_ipfs(json_str, ipfs_url).await?
+    } else {
+        // It's an image or other binary content
+        let extension = match content_type {
+            "image/png" => "png",
+            "image/jpeg" => "jpg",
+            "image/gif" => "gif",
+            "image/svg+xml" => "svg",
+            _ => "bin", // Default extension for unknown types
+        };
+
+        let filename = format!("nft_image.{}", extension);
+
+        // Upload the image and return the IPFS URI
+        upload_image_to_ipfs(content, &filename, ipfs_url).await?
+    };
+
+    // Log the upload
+    info!("Uploaded to IPFS with URI: {}", ipfs_uri);
+
+    // Return IPFS URI
+    Ok(ipfs_uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CIDv1 (raw, sha2-256) of "hello world"
+    const HELLO_RAW_CID: &str = "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e";
+
+    #[test]
+    fn test_normalize_cid_path() {
+        for input in ["ipfs://bafy/meta.json", "/ipfs/bafy/meta.json", " bafy/meta.json "] {
+            assert_eq!(normalize_cid_path(input).unwrap(), "bafy/meta.json");
+        }
+        assert!(normalize_cid_path("https://example.com/hat.png").is_err());
+        assert!(normalize_cid_path("ipfs://").is_err());
+    }
+
+    #[test]
+    fn test_gateway_urls() {
+        assert_eq!(
+            gateway_urls("ipfs://bafy/meta.json").unwrap(),
+            vec!["https://ipfs.io/ipfs/bafy/meta.json", "https://dweb.link/ipfs/bafy/meta.json"]
+        );
+    }
+
+    #[test]
+    fn test_gateway_list_order_and_dedup() {
+        let list = " https://b.example/ipfs/, ,https://a.example/ipfs,https://b.example/ipfs,";
+        assert_eq!(
+            parse_gateways(Some(list), Some("https://primary.example/ipfs/")),
+            vec!["https://b.example/ipfs", "https://a.example/ipfs"]
+        );
+
+        // Without a listed gateway, the primary gateway is tried before the fallback
+        for list in [None, Some(""), Some(" , ")] {
+            assert_eq!(
+                parse_gateways(list, Some("https://primary.example/ipfs/")),
+                vec!["https://primary.example/ipfs", "https://dweb.link/ipfs"]
+            );
+        }
+        assert_eq!(
+            parse_gateways(None, Some("https://dweb.link/ipfs")),
+            vec!["https://dweb.link/ipfs"]
+        );
+    }
+
+    #[test]
+    fn test_fetch_tries_gateways_in_order() {
+        use std::future::ready;
+        use wstd::runtime::block_on;
+
+        let urls = vec![
+            "https://a.example/ipfs/cid".to_string(),
+            "https://b.example/ipfs/cid".to_string(),
+            "https://c.example/ipfs/cid".to_string(),
+        ];
+        let mut requested = Vec::new();
+        let fetched = block_on(fetch_first(HELLO_RAW_CID, urls.clone(), |url| {
+            requested.push(url.clone());
+            ready(match url.as_str() {
+                "https://a.example/ipfs/cid" => Err(format!("Request to {} failed: refused", url)),
+                _ => Ok(b"hello world".to_vec()),
+            })
+        }));
+        assert_eq!(fetched.unwrap(), b"hello world");
+        assert_eq!(requested, urls[..2]);
+
+        let err = block_on(fetch_first(HELLO_RAW_CID, urls, |url| {
+            ready(match url.as_str() {
+                "https://b.example/ipfs/cid" => Ok(b"hello w0rld".to_vec()),
+                _ => Err(format!("Request to {} failed: refused", url)),
+            })
+        }))
+        .unwrap_err();
+        assert_eq!(
+            err,
+            format!(
+                "Failed to fetch {cid} from all IPFS gateways: \
+                 Request to https://a.example/ipfs/cid failed: refused; \
+                 https://b.example/ipfs/cid: Hash mismatch for CID {cid}; \
+                 Request to https://c.example/ipfs/cid failed: refused",
+                cid = HELLO_RAW_CID
+            )
+        );
+    }
+
+    #[test]
+    fn test_fetch_limits() {
+        assert_eq!(parse_positive("WAVS_ENV_IPFS_TIMEOUT_SECS", None, 30), 30);
+        assert_eq!(parse_positive("WAVS_ENV_IPFS_TIMEOUT_SECS", Some(" 5 ".to_string()), 30), 5);
+        assert_eq!(parse_positive("WAVS_ENV_IPFS_MAX_BYTES", Some("0".to_string()), 1024), 1024);
+        assert_eq!(parse_positive("WAVS_ENV_IPFS_MAX_BYTES", Some("big".to_string()), 1024), 1024);
+    }
+
+    #[test]
+    fn test_gateway_request_timeout() {
+        use std::future::{pending, ready};
+        use wstd::runtime::block_on;
+
+        let url = "https://ipfs.io/ipfs/bafy";
+        let fetched = block_on(within(ready(Ok(b"{}".to_vec())), pending::<()>(), url, 30));
+        assert_eq!(fetched.unwrap(), b"{}");
+
+        // A gateway that never answers fails once the deadline fires
+        let err = block_on(within(pending(), ready(()), url, 30)).unwrap_err();
+        assert_eq!(err, "Request to https://ipfs.io/ipfs/bafy timed out after 30s");
+    }
+
+    #[test]
+    fn test_verify_cid_vectors() {
+        // Known CIDs from `ipfs add` for "hello world\n" and an empty file
+        assert!(
+            verify_cid("QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o", b"hello world\n").unwrap()
+        );
+        assert!(verify_cid("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH", b"").unwrap());
+        assert!(verify_cid(
+            "bafybeicg2rebjoofv4kbyovkw7af3rpiitvnl6i7ckcywaq6xjcxnc2mby",
+            b"hello world\n"
+        )
+        .unwrap());
+        assert!(verify_cid(HELLO_RAW_CID, b"hello world").unwrap());
+
+        assert!(
+            !verify_cid("QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o", b"hello world").unwrap()
+        );
+        assert!(!verify_cid(HELLO_RAW_CID, b"hello w0rld").unwrap());
+    }
+
+    #[test]
+    fn test_verify_cid_unsupported() {
+        // dag-cbor
+        let err = verify_cid("bafyreidykglsfhoixmivffc5uwhcgshx4j465xwqntbmu43nb2dzqwfvae", b"")
+            .unwrap_err();
+        assert!(err.starts_with("Unsupported CID codec 0x71"));
+
+        let err = verify_cid("QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o", &[0; 300_000])
+            .unwrap_err();
+        assert!(err.starts_with("Unsupported multi-block"));
+    }
+
+    #[test]
+    fn test_pin_requests() {
+        let request = pin_json_request(&serde_json::json!({ "name": "Hat" })).unwrap();
+        assert_eq!(request.url, PINATA_PIN_JSON_URL);
+        assert_eq!(request.content_type, "application/json");
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body, serde_json::json!({ "pinataContent": { "name": "Hat" } }));
+
+        let request = pin_bytes_request(b"\x89PNG", "hat.png");
+        assert_eq!(request.url, PINATA_PIN_FILE_URL);
+        assert_eq!(request.content_type, "multipart/form-data; boundary=----RustBoundary");
+        let body = String::from_utf8_lossy(&request.body);
+        assert!(body.starts_with("------RustBoundary\r\n"));
+        assert!(body.contains("name=\"file\"; filename=\"hat.png\"\r\n"));
+        assert!(body.contains("name=\"pinataMetadata\"\r\n\r\n{\"name\":\"hat.png\"}"));
+        assert!(body.ends_with("------RustBoundary--\r\n"));
+    }
+
+    #[test]
+    fn test_parse_pin_response() {
+        let body = br#"{"IpfsHash":"bafkreihat","PinSize":12,"Timestamp":"2024-01-01T00:00:00Z"}"#;
+        assert_eq!(parse_pin_response(200, body).unwrap(), "bafkreihat");
+        assert_eq!(
+            get_ipfs_url(&parse_pin_response(200, body).unwrap(), None),
+            "ipfs://bafkreihat"
+        );
+
+        assert!(parse_pin_response(401, b"Unauthorized").unwrap_err().contains("credentials"));
+        assert!(parse_pin_response(500, b"oops")
+            .unwrap_err()
+            .starts_with("Pinning failed with status 500"));
+        assert!(parse_pin_response(200, b"{}")
+            .unwrap_err()
+            .starts_with("Failed to parse pin response"));
+    }
+
+    #[test]
+    fn test_verify_content() {
+        assert!(verify_content(HELLO_RAW_CID, b"hello world").is_ok());
+        let err = verify_content(HELLO_RAW_CID, b"hello w0rld").unwrap_err();
+        assert!(err.starts_with("Hash mismatch for CID"));
+
+        // Content under a directory path can't be checked against the root CID
+        assert!(verify_content(&format!("{}/meta.json", HELLO_RAW_CID), b"{}").is_ok());
+        assert!(verify_content("not-a-cid", b"").unwrap_err().starts_with("Invalid CID"));
+    }
+}
@@ -0,0 +1,21 @@
+//! Helpers shared by the Hats AVS components
+//!
+//! Each component generates its own WIT bindings, so anything that needs the
+//! host is exposed as a macro that expands against the calling crate's
+//! `bindings` module.
+
+pub mod dry_run;
+pub mod eligibility;
+pub mod env;
+pub mod evm;
+pub mod hats;
+pub mod http;
+pub mod idempotency;
+pub mod image;
+pub mod ipfs;
+pub mod log;
+pub mod metadata;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+pub mod nft;
+pub mod trigger;
@@ -0,0 +1,132 @@
+//! Leveled logging to stderr, filtered by `WAVS_ENV_LOG_LEVEL`
+//!
+//! Levels are `error`, `warn`, `info` and `debug`, from least to most
+//! verbose, and default to `info`. Log through the macros, which skip
+//! formatting messages below the threshold:
+//!
+//! ```ignore
+//! wavs_hats_common::info!("Trigger ID: {}", trigger_id);
+//! ```
+//!
+//! Never log secrets such as API keys, at any level.
+
+use std::fmt;
+
+/// Severity of a log message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    /// Parse a level name, case insensitively
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "warn" | "warning" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" | "trace" => Some(Self::Debug),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Most verbose level logged, from `WAVS_ENV_LOG_LEVEL`
+pub fn max_level() -> Level {
+    std::env::var("WAVS_ENV_LOG_LEVEL")
+        .ok()
+        .and_then(|name| Level::parse(&name))
+        .unwrap_or(Level::Info)
+}
+
+/// Whether messages at `level` are logged
+pub fn enabled(level: Level) -> bool {
+    passes(level, max_level())
+}
+
+fn passes(level: Level, max_level: Level) -> bool {
+    level <= max_level
+}
+
+/// Log `args` at `level`, used by the logging macros
+pub fn log(level: Level, args: fmt::Arguments<'_>) {
+    if enabled(level) {
+        eprintln!("[{}] {}", level, args);
+    }
+}
+
+/// Log at [`Level::Error`](crate::log::Level::Error)
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Error, format_args!($($arg)*))
+    };
+}
+
+/// Log at [`Level::Warn`](crate::log::Level::Warn)
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Warn, format_args!($($arg)*))
+    };
+}
+
+/// Log at [`Level::Info`](crate::log::Level::Info)
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Info, format_args!($($arg)*))
+    };
+}
+
+/// Log at [`Level::Debug`](crate::log::Level::Debug)
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Debug, format_args!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_levels() {
+        assert_eq!(Level::parse("DEBUG"), Some(Level::Debug));
+        assert_eq!(Level::parse(" warn "), Some(Level::Warn));
+        assert_eq!(Level::parse("error"), Some(Level::Error));
+        assert_eq!(Level::parse("loud"), None);
+    }
+
+    #[test]
+    fn test_below_threshold_suppressed() {
+        assert!(passes(Level::Error, Level::Info));
+        assert!(passes(Level::Info, Level::Info));
+        assert!(!passes(Level::Debug, Level::Info));
+
+        assert!(passes(Level::Error, Level::Error));
+        assert!(!passes(Level::Warn, Level::Error));
+        assert!(!passes(Level::Info, Level::Error));
+
+        assert!(passes(Level::Debug, Level::Debug));
+    }
+}
@@ -0,0 +1,408 @@
+//! Resolving the off-chain metadata of hats
+
+use crate::evm::{self, HatView};
+use crate::{http, image, ipfs};
+use alloy_network::Ethereum;
+use alloy_primitives::U256;
+use alloy_provider::RootProvider;
+use alloy_rpc_types::BlockId;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Fetch the content of an `ipfs://`, `https://` or `http://` URI, or decode
+/// a base64 `data:` URI
+pub async fn fetch(uri: &str) -> Result<Vec<u8>, String> {
+    if uri.starts_with("data:") {
+        image::decode_data_uri(uri).map(|(_, bytes)| bytes)
+    } else if uri.starts_with("ipfs://") {
+        ipfs::fetch(uri).await
+    } else if uri.starts_with("https://") || uri.starts_with("http://") {
+        http::get(uri).await
+    } else {
+        Err(format!("Unsupported metadata URI: {}", uri))
+    }
+}
+
+/// Read the metadata URI of `hat_id` as of `block` and fetch its content
+///
+/// Returns `None` for a hat without a URI. Hats builds the URI on chain as a
+/// `data:` URI of JSON whose `description` is the hat's details, by
+/// convention a URI of the actual metadata, which is then fetched instead.
+/// The content is checked to be JSON with [`json_body`].
+pub async fn hat_metadata(
+    provider: &RootProvider<Ethereum>,
+    hat_id: U256,
+    block: BlockId,
+) -> Result<Option<Vec<u8>>, String> {
+    let uri = evm::hat_uri(provider, hat_id, block).await?;
+    if uri.is_empty() {
+        return Ok(None);
+    }
+    let body = fetch(&uri).await.and_then(json_body)?;
+    if !uri.starts_with("data:") {
+        return Ok(Some(body));
+    }
+    match details_uri(&body) {
+        Some(details) => fetch(&details).await.and_then(json_body).map(Some),
+        None => Ok(Some(body)),
+    }
+}
+
+/// `description` of Hats `uri()` JSON, if it points at metadata elsewhere
+fn details_uri(body: &[u8]) -> Option<String> {
+    let json: Value = serde_json::from_slice(body).ok()?;
+    json["description"].as_str().filter(|details| is_remote_uri(details)).map(String::from)
+}
+
+/// Whether `uri` is fetched from IPFS or over HTTP by [`fetch`]
+pub fn is_remote_uri(uri: &str) -> bool {
+    ["ipfs://", "https://", "http://"].iter().any(|scheme| uri.starts_with(scheme))
+}
+
+/// UTF-8 byte order mark, written by some editors at the start of a file
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Strip a UTF-8 BOM from fetched metadata and check that it is a JSON
+/// object or array
+///
+/// Misconfigured gateways return HTML error pages with a success status, so
+/// content that isn't JSON fails with the type sniffed from its first bytes
+/// rather than a parse error.
+pub fn json_body(mut bytes: Vec<u8>) -> Result<Vec<u8>, String> {
+    if bytes.starts_with(UTF8_BOM) {
+        bytes.drain(..UTF8_BOM.len());
+    }
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(bytes.len());
+    match bytes.get(start) {
+        Some(b'{' | b'[') => Ok(bytes),
+        _ => Err(format!("metadata is not JSON (got {})", sniff_type(&bytes[start..]))),
+    }
+}
+
+/// Content type of a body that isn't JSON, from its first bytes
+fn sniff_type(body: &[u8]) -> &'static str {
+    match body.first() {
+        None => "an empty body",
+        Some(_) if body.starts_with(b"<?xml") => "application/xml",
+        Some(b'<') => "text/html",
+        Some(_) if std::str::from_utf8(body).is_ok() => "text/plain",
+        Some(_) => "application/octet-stream",
+    }
+}
+
+/// Typed metadata JSON of a hat, as found at its URI
+///
+/// Every field is optional, so metadata written for other tools still parses.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HatMetadata {
+    pub name: String,
+    pub description: String,
+    pub image: Option<String>,
+    /// Settings of an agent configured by the hat
+    pub agent: Option<AgentConfig>,
+}
+
+/// Agent settings of hat metadata
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentConfig {
+    pub model: Option<String>,
+    /// Segments of the system prompt, given as a string or a list of strings
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub system_prompt: Option<Vec<String>>,
+    pub temperature: Option<f32>,
+    /// Names of the tools the agent may offer
+    pub tools: Option<Vec<String>>,
+    /// Whether example tool calls open the conversation
+    pub few_shot: Option<bool>,
+    /// JSON schema the agent's answer must match
+    pub output_schema: Option<Value>,
+}
+
+fn string_or_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(Option::<StringOrList>::deserialize(deserializer)?.map(|value| match value {
+        StringOrList::String(segment) => vec![segment],
+        StringOrList::List(segments) => segments,
+    }))
+}
+
+#[derive(Deserialize)]
+struct RawHatMetadata {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    description: String,
+    image: Option<String>,
+    agent: Option<AgentConfig>,
+    #[serde(flatten)]
+    top_level_agent: AgentConfig,
+}
+
+impl HatMetadata {
+    /// Parse metadata JSON
+    ///
+    /// Agent settings are read from the `agent` object, or from top-level
+    /// fields as in metadata written before the object existed.
+    pub fn from_json(json: &[u8]) -> Result<Self, String> {
+        let raw: RawHatMetadata =
+            serde_json::from_slice(json).map_err(|e| format!("Invalid hat metadata: {}", e))?;
+        let top_level_agent =
+            Some(raw.top_level_agent).filter(|agent| *agent != AgentConfig::default());
+        Ok(Self {
+            name: raw.name,
+            description: raw.description,
+            image: raw.image,
+            agent: raw.agent.or(top_level_agent),
+        })
+    }
+}
+
+/// Hat reads memoized for a single run
+///
+/// Tools of a run often look up the same hat more than once. Every read of a
+/// run is pinned to the same block, so a cached result is the result a new
+/// read would return. Lookups of a hat that is not cached yet are not
+/// coalesced, so concurrent first lookups of the same hat each fetch it.
+#[derive(Clone)]
+pub struct MetadataCache {
+    provider: RootProvider<Ethereum>,
+    block: BlockId,
+    hats: Rc<RefCell<HashMap<U256, Rc<HatView>>>>,
+    metadata: Rc<RefCell<HashMap<U256, Option<Rc<Value>>>>>,
+}
+
+impl MetadataCache {
+    /// Cache for reads through `provider` pinned to `block`
+    pub fn new(provider: RootProvider<Ethereum>, block: BlockId) -> Self {
+        Self { provider, block, hats: Rc::default(), metadata: Rc::default() }
+    }
+
+    pub fn provider(&self) -> &RootProvider<Ethereum> {
+        &self.provider
+    }
+
+    pub fn block(&self) -> BlockId {
+        self.block
+    }
+
+    /// `viewHat` of `hat_id`, read on the first lookup of the hat
+    pub async fn get_or_fetch(&self, hat_id: U256) -> Result<Rc<HatView>, String> {
+        if let Some(hat) = self.hats.borrow().get(&hat_id) {
+            return Ok(hat.clone());
+        }
+        let hat = Rc::new(evm::view_hat(&self.provider, hat_id, self.block).await?);
+        self.hats.borrow_mut().insert(hat_id, hat.clone());
+        Ok(hat)
+    }
+
+    /// Metadata JSON of `hat_id`, fetched on the first lookup of the hat
+    ///
+    /// Returns `None` for a hat without a URI. Failures aren't cached.
+    pub async fn metadata(&self, hat_id: U256) -> Result<Option<Rc<Value>>, String> {
+        if let Some(metadata) = self.metadata.borrow().get(&hat_id) {
+            return Ok(metadata.clone());
+        }
+        let metadata = match hat_metadata(&self.provider, hat_id, self.block).await? {
+            Some(raw) => Some(Rc::new(
+                serde_json::from_slice(&raw)
+                    .map_err(|e| format!("Invalid metadata JSON of hat {:#x}: {}", hat_id, e))?,
+            )),
+            None => None,
+        };
+        self.metadata.borrow_mut().insert(hat_id, metadata.clone());
+        Ok(metadata)
+    }
+}
+
+impl fmt::Debug for MetadataCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetadataCache")
+            .field("block", &self.block)
+            .field("hats", &self.hats.borrow().keys().collect::<Vec<_>>())
+            .field("metadata", &self.metadata.borrow().keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::IHats;
+    use crate::mock::MockTransport;
+    use alloy_primitives::{Address, Bytes};
+    use alloy_sol_types::SolCall;
+    use serde_json::json;
+    use wstd::runtime::block_on;
+
+    #[test]
+    fn test_full_hat_metadata() {
+        let metadata = HatMetadata::from_json(
+            br#"{"name": "Agent Hat", "description": "Answers questions", "image": "ipfs://bafy",
+                "agent": {"model": "gpt-4", "systemPrompt": "Be brief.", "temperature": 0.7,
+                    "tools": ["hat_lookup"]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            metadata,
+            HatMetadata {
+                name: "Agent Hat".to_string(),
+                description: "Answers questions".to_string(),
+                image: Some("ipfs://bafy".to_string()),
+                agent: Some(AgentConfig {
+                    model: Some("gpt-4".to_string()),
+                    system_prompt: Some(vec!["Be brief.".to_string()]),
+                    temperature: Some(0.7),
+                    tools: Some(vec!["hat_lookup".to_string()]),
+                    few_shot: None,
+                    output_schema: None,
+                }),
+            }
+        );
+
+        // Top-level agent settings, as in older metadata
+        let metadata =
+            HatMetadata::from_json(br#"{"name": "Agent Hat", "model": "gpt-4"}"#).unwrap();
+        assert_eq!(metadata.agent.unwrap().model.as_deref(), Some("gpt-4"));
+
+        let metadata =
+            HatMetadata::from_json(br#"{"agent": {"systemPrompt": ["Persona", "Rules"]}}"#)
+                .unwrap();
+        assert_eq!(metadata.agent.unwrap().system_prompt.unwrap(), ["Persona", "Rules"]);
+    }
+
+    #[test]
+    fn test_minimal_hat_metadata() {
+        let metadata = HatMetadata::from_json(br#"{"name": "Signer"}"#).unwrap();
+        assert_eq!(metadata, HatMetadata { name: "Signer".to_string(), ..Default::default() });
+        assert_eq!(HatMetadata::from_json(b"{}").unwrap(), HatMetadata::default());
+
+        assert!(HatMetadata::from_json(b"not json").is_err());
+        assert!(HatMetadata::from_json(br#"{"agent": {"temperature": "hot"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_html_metadata_is_not_json() {
+        let page = b"\n<!DOCTYPE html><html><body>504 Gateway Time-out</body></html>".to_vec();
+        assert_eq!(json_body(page).unwrap_err(), "metadata is not JSON (got text/html)");
+        assert_eq!(
+            json_body(b"  ".to_vec()).unwrap_err(),
+            "metadata is not JSON (got an empty body)"
+        );
+        assert_eq!(
+            json_body(b"Not Found".to_vec()).unwrap_err(),
+            "metadata is not JSON (got text/plain)"
+        );
+        assert_eq!(
+            json_body(b"\x89PNG\r\n".to_vec()).unwrap_err(),
+            "metadata is not JSON (got application/octet-stream)"
+        );
+    }
+
+    #[test]
+    fn test_bom_prefixed_metadata() {
+        let body = json_body(b"\xef\xbb\xbf {\"name\": \"Signer\"}".to_vec()).unwrap();
+        assert_eq!(body, b" {\"name\": \"Signer\"}");
+        assert_eq!(HatMetadata::from_json(&body).unwrap().name, "Signer");
+    }
+
+    fn selector(params: &Value) -> String {
+        params[0]["input"].as_str().unwrap_or_default()[..10].to_string()
+    }
+
+    #[test]
+    fn test_cache_fetches_each_hat_once() {
+        let transport = MockTransport::new(|_, params| {
+            let view = format!("0x{}", alloy_primitives::hex::encode(IHats::viewHatCall::SELECTOR));
+            let encoded = if selector(params) == view {
+                IHats::viewHatCall::abi_encode_returns(&(
+                    "Top Hat".to_string(),
+                    1u32,
+                    1u32,
+                    Address::ZERO,
+                    Address::ZERO,
+                    String::new(),
+                    0u16,
+                    false,
+                    true,
+                ))
+            } else {
+                IHats::uriCall::abi_encode_returns(&(String::new(),))
+            };
+            Ok(json!(Bytes::from(encoded)))
+        });
+        let cache = MetadataCache::new(transport.provider(), BlockId::number(7));
+
+        let first = block_on(cache.get_or_fetch(U256::from(1))).unwrap();
+        let second = block_on(cache.get_or_fetch(U256::from(1))).unwrap();
+        assert_eq!(first.details, "Top Hat");
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(transport.requests.lock().unwrap().len(), 1);
+
+        block_on(cache.get_or_fetch(U256::from(2))).unwrap();
+        assert_eq!(transport.requests.lock().unwrap().len(), 2);
+
+        // A hat without a URI has no metadata, and that is cached too
+        assert_eq!(block_on(cache.metadata(U256::from(1))).unwrap(), None);
+        assert_eq!(block_on(cache.metadata(U256::from(1))).unwrap(), None);
+        assert_eq!(transport.requests.lock().unwrap().len(), 3);
+    }
+
+    /// `uri()` return of Hats v1 for a hat with `details`
+    fn hats_uri(details: &str) -> String {
+        use base64::Engine;
+        let json = json!({
+            "name": "Agent Hat",
+            "description": details,
+            "id": "1",
+            "pretty id": "0x00000001",
+            "status": "active",
+            "image": "ipfs://bafyimage",
+        });
+        let encoded = base64::engine::general_purpose::STANDARD.encode(json.to_string());
+        format!("data:application/json;base64,{}", encoded)
+    }
+
+    #[test]
+    fn test_hats_data_uri_metadata() {
+        let uri = hats_uri("Answers questions");
+        let transport = MockTransport::new(move |_, _| {
+            Ok(json!(Bytes::from(IHats::uriCall::abi_encode_returns(&(uri.clone(),)))))
+        });
+
+        let body = block_on(hat_metadata(&transport.provider(), U256::from(1), BlockId::number(7)))
+            .unwrap()
+            .unwrap();
+        let metadata = HatMetadata::from_json(&body).unwrap();
+        assert_eq!(metadata.name, "Agent Hat");
+        assert_eq!(metadata.description, "Answers questions");
+
+        // Details pointing elsewhere are fetched in place of the data URI
+        let body = image::decode_data_uri(&hats_uri("ipfs://bafydetails")).unwrap().1;
+        assert_eq!(details_uri(&body).as_deref(), Some("ipfs://bafydetails"));
+        let body = image::decode_data_uri(&hats_uri("Answers questions")).unwrap().1;
+        assert_eq!(details_uri(&body), None);
+    }
+
+    #[test]
+    fn test_cache_does_not_keep_failures() {
+        let transport = MockTransport::new(|_, _| Err(crate::mock::REVERT.to_string()));
+        let cache = MetadataCache::new(transport.provider(), BlockId::number(7));
+
+        assert!(block_on(cache.get_or_fetch(U256::from(1))).is_err());
+        assert!(block_on(cache.get_or_fetch(U256::from(1))).is_err());
+        assert_eq!(transport.requests.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,77 @@
+//! In-memory JSON-RPC transport for exercising provider calls in native tests
+
+use alloy_json_rpc::{RequestPacket, ResponsePacket};
+use alloy_network::Ethereum;
+use alloy_provider::RootProvider;
+use alloy_rpc_client::RpcClient;
+use alloy_transport::{TransportError, TransportErrorKind, TransportFut};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+type Responder = dyn Fn(&str, &Value) -> Result<Value, String> + Send + Sync;
+
+/// Error message that [`MockTransport`] answers as a JSON-RPC error, as a node
+/// does for a revert, rather than as a transport failure
+pub const REVERT: &str = "execution reverted";
+
+/// Error for a [`MockTransport`] responder that reverts with `data`
+pub fn revert(data: &[u8]) -> String {
+    format!("{}: {}", REVERT, alloy_primitives::Bytes::copy_from_slice(data))
+}
+
+/// Answers each request with `responder(method, params)` and records it
+///
+/// Errors starting with [`REVERT`] become JSON-RPC error responses, with the
+/// revert data of a [`revert`] error, and any other error fails the transport.
+#[derive(Clone)]
+pub struct MockTransport {
+    pub requests: Arc<Mutex<Vec<Value>>>,
+    responder: Arc<Responder>,
+}
+
+impl MockTransport {
+    pub fn new(
+        responder: impl Fn(&str, &Value) -> Result<Value, String> + Send + Sync + 'static,
+    ) -> Self {
+        Self { requests: Arc::default(), responder: Arc::new(responder) }
+    }
+
+    pub fn provider(&self) -> RootProvider<Ethereum> {
+        RootProvider::new(RpcClient::new(self.clone(), true))
+    }
+}
+
+impl tower_service::Service<RequestPacket> for MockTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, packet: RequestPacket) -> Self::Future {
+        let this = self.clone();
+        Box::pin(async move {
+            let request: Value =
+                serde_json::to_value(&packet).map_err(TransportErrorKind::custom)?;
+            this.requests.lock().unwrap().push(request.clone());
+
+            let method = request["method"].as_str().unwrap_or_default();
+            let response = match (this.responder)(method, &request["params"]) {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+                Err(e) if e.starts_with(REVERT) => {
+                    let data = e.strip_prefix(REVERT).and_then(|rest| rest.strip_prefix(": 0x"));
+                    let mut error = json!({ "code": 3, "message": e });
+                    if let Some(data) = data.filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit())) {
+                        error["data"] = json!(format!("0x{}", data));
+                    }
+                    json!({ "jsonrpc": "2.0", "id": request["id"], "error": error })
+                }
+                Err(e) => return Err(TransportErrorKind::custom_str(&e)),
+            };
+            serde_json::from_value(response).map_err(TransportErrorKind::custom)
+        })
+    }
+}
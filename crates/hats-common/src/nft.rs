@@ -0,0 +1,179 @@
+//! NFT ownership checks, for gating hats on ERC721 or ERC1155 holdings
+
+use crate::evm;
+use alloy_network::Ethereum;
+use alloy_primitives::{Address, U256};
+use alloy_provider::RootProvider;
+use alloy_rpc_types::BlockId;
+use alloy_sol_types::{sol, SolCall};
+use serde::Deserialize;
+
+sol! {
+    /// ERC1155 per-token balance
+    interface IERC1155 {
+        function balanceOf(address account, uint256 id) external view returns (uint256 balance);
+    }
+}
+
+/// Whether `owner` holds any token of the ERC721 `collection` as of `block`
+pub async fn owns_any(
+    provider: &RootProvider<Ethereum>,
+    owner: Address,
+    collection: Address,
+    block: BlockId,
+) -> Result<bool, String> {
+    Ok(evm::token_balance(provider, collection, owner, block).await? > U256::ZERO)
+}
+
+/// Whether `owner` holds token `token_id` of the ERC1155 `collection` as of `block`
+pub async fn owns_token(
+    provider: &RootProvider<Ethereum>,
+    owner: Address,
+    collection: Address,
+    token_id: U256,
+    block: BlockId,
+) -> Result<bool, String> {
+    let call = IERC1155::balanceOfCall { account: owner, id: token_id };
+    let result = evm::eth_call(provider, collection, call.abi_encode(), block).await?;
+    IERC1155::balanceOfCall::abi_decode_returns(&result, false)
+        .map(|ret| ret.balance > U256::ZERO)
+        .map_err(|e| format!("Failed to decode ERC1155 balanceOf result: {}", e))
+}
+
+/// Token standard of an NFT collection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum NftStandard {
+    #[serde(alias = "ERC721", alias = "erc721", alias = "721")]
+    Erc721,
+    #[serde(alias = "ERC1155", alias = "erc1155", alias = "1155")]
+    Erc1155,
+}
+
+/// NFT a wearer must own, as given by the `nftGate` field of a hat's metadata
+///
+/// An ERC1155 gate requires `tokenId`, while an ERC721 gate accepts any token
+/// of the collection.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NftGate {
+    pub standard: NftStandard,
+    pub collection: Address,
+    pub token_id: Option<U256>,
+}
+
+impl NftGate {
+    /// Read the gate from hat metadata JSON, if it has one
+    pub fn from_metadata(metadata: &[u8]) -> Result<Option<Self>, String> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Metadata {
+            nft_gate: Option<NftGate>,
+        }
+
+        let metadata: Metadata = serde_json::from_slice(metadata)
+            .map_err(|e| format!("Invalid NFT gate in hat metadata: {}", e))?;
+        if let Some(gate) = &metadata.nft_gate {
+            gate.validate()?;
+        }
+        Ok(metadata.nft_gate)
+    }
+
+    /// Fail on a gate that can't be checked, an ERC1155 gate without `tokenId`
+    pub fn validate(&self) -> Result<(), String> {
+        if self.standard == NftStandard::Erc1155 && self.token_id.is_none() {
+            return Err("ERC1155 NFT gate requires a tokenId".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether `owner` meets the gate as of `block`
+    pub async fn is_met(
+        &self,
+        provider: &RootProvider<Ethereum>,
+        owner: Address,
+        block: BlockId,
+    ) -> Result<bool, String> {
+        match (self.standard, self.token_id) {
+            (NftStandard::Erc1155, Some(token_id)) => {
+                owns_token(provider, owner, self.collection, token_id, block).await
+            }
+            (NftStandard::Erc1155, None) => Err("ERC1155 NFT gate requires a tokenId".to_string()),
+            (NftStandard::Erc721, _) => owns_any(provider, owner, self.collection, block).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+    use serde_json::json;
+    use wstd::runtime::block_on;
+
+    fn balance_provider(balance: u64) -> MockTransport {
+        MockTransport::new(move |_, _| Ok(json!(format!("{:#066x}", balance))))
+    }
+
+    #[test]
+    fn test_parse_nft_gate() {
+        let collection = Address::repeat_byte(0x42);
+        let metadata = json!({
+            "name": "Member",
+            "nftGate": { "standard": "ERC1155", "collection": collection, "tokenId": "7" }
+        });
+        let gate = NftGate::from_metadata(metadata.to_string().as_bytes()).unwrap().unwrap();
+        assert_eq!(gate.standard, NftStandard::Erc1155);
+        assert_eq!(gate.collection, collection);
+        assert_eq!(gate.token_id, Some(U256::from(7)));
+
+        let metadata = json!({ "nftGate": { "standard": "721", "collection": collection } });
+        let gate = NftGate::from_metadata(metadata.to_string().as_bytes()).unwrap().unwrap();
+        assert_eq!(gate.standard, NftStandard::Erc721);
+
+        assert_eq!(NftGate::from_metadata(br#"{"name": "Member"}"#), Ok(None));
+        let metadata = json!({ "nftGate": { "standard": "1155", "collection": collection } });
+        assert!(NftGate::from_metadata(metadata.to_string().as_bytes()).is_err());
+        let metadata = json!({ "nftGate": { "standard": "20", "collection": collection } });
+        assert!(NftGate::from_metadata(metadata.to_string().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_erc721_gate() {
+        let gate = NftGate {
+            standard: NftStandard::Erc721,
+            collection: Address::repeat_byte(0x42),
+            token_id: None,
+        };
+        for (balance, expected) in [(0, false), (2, true)] {
+            let transport = balance_provider(balance);
+            let result =
+                block_on(gate.is_met(&transport.provider(), Address::ZERO, BlockId::latest()));
+            assert_eq!(result, Ok(expected));
+
+            // balanceOf(address)
+            let requests = transport.requests.lock().unwrap();
+            assert!(requests[0]["params"][0]["input"].as_str().unwrap().starts_with("0x70a08231"));
+        }
+    }
+
+    #[test]
+    fn test_erc1155_gate() {
+        let gate = NftGate {
+            standard: NftStandard::Erc1155,
+            collection: Address::repeat_byte(0x42),
+            token_id: Some(U256::from(7)),
+        };
+        for (balance, expected) in [(0, false), (1, true)] {
+            let transport = balance_provider(balance);
+            let result =
+                block_on(gate.is_met(&transport.provider(), Address::ZERO, BlockId::latest()));
+            assert_eq!(result, Ok(expected));
+
+            // balanceOf(address,uint256) for the gate's token
+            let requests = transport.requests.lock().unwrap();
+            let input = requests[0]["params"][0]["input"].as_str().unwrap();
+            assert!(input.starts_with("0x00fdd58e"));
+            assert!(input.ends_with("07"));
+        }
+    }
+}
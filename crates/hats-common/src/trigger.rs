@@ -0,0 +1,212 @@
+//! Helpers for the trigger data a component is run with
+
+use alloy_primitives::{LogData, B256};
+use alloy_sol_types::SolEvent;
+use std::fmt::{self, Debug, Write};
+
+/// Decode the `T` event of a contract event trigger
+///
+/// Returns the event with the `block_height` of its log. Other triggers fail
+/// with [`unsupported`]. Expands against the calling component's `bindings`
+/// module, like [`eth_provider!`](crate::eth_provider). Components that
+/// handle `Raw` triggers too match on the trigger data themselves.
+///
+/// ```ignore
+/// let (event, block_height) =
+///     wavs_hats_common::decode_trigger!(action.data, IHatsAvsTypes::MintingTrigger)?;
+/// ```
+#[macro_export]
+macro_rules! decode_trigger {
+    ($data:expr, $event:ty) => {{
+        $crate::trigger_event!($data).and_then(|trigger| {
+            $crate::trigger::decode_event::<$event>(&trigger.log.topics, &trigger.log.data)
+                .map(|event| (event, trigger.block_height))
+        })
+    }};
+}
+
+/// The contract event of a trigger, for components that handle several events
+///
+/// Other triggers fail with [`unsupported`].
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)] // `crate::bindings` is intentionally the caller's
+macro_rules! trigger_event {
+    ($data:expr) => {{
+        match $data {
+            crate::bindings::wavs::worker::layer_types::TriggerData::EthContractEvent(trigger) => {
+                Ok(trigger)
+            }
+            data => Err($crate::trigger::unsupported(&data)),
+        }
+    }};
+}
+
+/// Decode the `T` event from the raw topics and data of a log
+pub fn decode_event<T: SolEvent>(topics: &[Vec<u8>], data: &[u8]) -> Result<T, String> {
+    let topics: Result<Vec<_>, _> =
+        topics.iter().map(|topic| B256::try_from(topic.as_slice())).collect();
+    let log = topics
+        .ok()
+        .and_then(|topics| LogData::new(topics, data.to_vec().into()))
+        .ok_or_else(|| format!("Failed to decode {} event: invalid topics", T::SIGNATURE))?;
+    T::decode_log_data(&log, true)
+        .map_err(|e| format!("Failed to decode {} event: {}", T::SIGNATURE, e))
+}
+
+/// Whether raw log `topics` are those of a `T` event
+pub fn is_event<T: SolEvent>(topics: &[Vec<u8>]) -> bool {
+    topics.first().is_some_and(|topic| topic.as_slice() == T::SIGNATURE_HASH.as_slice())
+}
+
+/// Name of the variant of a component's `TriggerData`, such as `Raw`
+///
+/// Each component has its own `TriggerData` type, so the name is read from
+/// its `Debug` output. Formatting stops at the variant's payload, so large
+/// raw data isn't formatted.
+pub fn variant_name(data: &impl Debug) -> String {
+    let mut name = VariantName(String::new());
+    // The writer fails on purpose once the name is complete
+    let _ = write!(name, "{:?}", data);
+    name.0.rsplit("::").next().unwrap_or_default().trim().to_string()
+}
+
+/// Error for a trigger a component doesn't handle, naming its variant
+pub fn unsupported(data: &impl Debug) -> String {
+    format!("Unsupported trigger data: {}", variant_name(data))
+}
+
+/// Collects `Debug` output up to the first delimiter of a payload
+struct VariantName(String);
+
+impl Write for VariantName {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match s.find(['(', '{', ' ']) {
+            Some(end) => {
+                self.0.push_str(&s[..end]);
+                Err(fmt::Error)
+            }
+            None => {
+                self.0.push_str(s);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{Address, U256};
+
+    mod types {
+        alloy_sol_types::sol!("../../src/interfaces/IHatsAvsTypes.sol");
+    }
+    use types::IHatsAvsTypes;
+
+    fn raw<T: SolEvent>(event: &T) -> (Vec<Vec<u8>>, Vec<u8>) {
+        let log = event.encode_log_data();
+        (log.topics().iter().map(|t| t.to_vec()).collect(), log.data.to_vec())
+    }
+
+    fn round_trip<T: SolEvent>(event: &T) -> T {
+        let (topics, data) = raw(event);
+        assert!(is_event::<T>(&topics));
+        decode_event(&topics, &data).unwrap()
+    }
+
+    #[test]
+    fn test_decode_each_event() {
+        let creator = Address::repeat_byte(0x01);
+        let wearer = Address::repeat_byte(0x02);
+        let hat_id = U256::from(1) << 224;
+
+        let event =
+            round_trip(&IHatsAvsTypes::StatusCheckTrigger { triggerId: 1, creator, hatId: hat_id });
+        assert_eq!((event.triggerId, event.creator, event.hatId), (1, creator, hat_id));
+
+        let event = round_trip(&IHatsAvsTypes::EligibilityCheckTrigger {
+            triggerId: 2,
+            creator,
+            wearer,
+            hatId: hat_id,
+        });
+        assert_eq!((event.triggerId, event.wearer, event.hatId), (2, wearer, hat_id));
+
+        let event = round_trip(&IHatsAvsTypes::HatCreationTrigger {
+            triggerId: 3,
+            creator,
+            admin: hat_id,
+            details: "Signer".to_string(),
+            maxSupply: 5,
+            eligibility: Address::ZERO,
+            toggle: Address::ZERO,
+            mutable_: true,
+            imageURI: String::new(),
+        });
+        assert_eq!((event.triggerId, event.details.as_str(), event.maxSupply), (3, "Signer", 5));
+
+        let event = round_trip(&IHatsAvsTypes::MintingTrigger {
+            triggerId: 4,
+            creator,
+            hatId: hat_id,
+            wearer,
+        });
+        assert_eq!((event.triggerId, event.wearer), (4, wearer));
+
+        let event = round_trip(&IHatsAvsTypes::BatchMintingTrigger {
+            triggerId: 5,
+            creator,
+            hatId: hat_id,
+            wearers: vec![wearer, creator],
+        });
+        assert_eq!((event.triggerId, event.wearers), (5, vec![wearer, creator]));
+
+        let event = round_trip(&IHatsAvsTypes::NewTrigger { _triggerInfo: vec![1, 2].into() });
+        assert_eq!(event._triggerInfo.to_vec(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_decode_wrong_event() {
+        let (topics, data) = raw(&IHatsAvsTypes::StatusCheckTrigger {
+            triggerId: 1,
+            creator: Address::ZERO,
+            hatId: U256::from(1),
+        });
+        assert!(!is_event::<IHatsAvsTypes::MintingTrigger>(&topics));
+        let error = decode_event::<IHatsAvsTypes::MintingTrigger>(&topics, &data).err().unwrap();
+        assert!(error.starts_with("Failed to decode MintingTrigger("), "{}", error);
+
+        let error =
+            decode_event::<IHatsAvsTypes::MintingTrigger>(&[vec![0; 4]], &data).err().unwrap();
+        assert!(error.ends_with("invalid topics"), "{}", error);
+    }
+
+    /// Mirrors the shape of the generated `TriggerData`
+    #[allow(dead_code)]
+    #[derive(Debug)]
+    enum TriggerData {
+        EthContractEvent { block_height: u64 },
+        Raw(Vec<u8>),
+        BlockInterval,
+    }
+
+    /// Debug output of the generated bindings, which prefix the type name
+    struct Generated;
+
+    impl Debug for Generated {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_tuple("TriggerData::Raw").field(&vec![1u8, 2, 3]).finish()
+        }
+    }
+
+    #[test]
+    fn test_unsupported_names_variant() {
+        assert_eq!(unsupported(&TriggerData::Raw(vec![0; 1024])), "Unsupported trigger data: Raw");
+        assert_eq!(
+            unsupported(&TriggerData::EthContractEvent { block_height: 1 }),
+            "Unsupported trigger data: EthContractEvent"
+        );
+        assert_eq!(variant_name(&TriggerData::BlockInterval), "BlockInterval");
+        assert_eq!(variant_name(&Generated), "Raw");
+    }
+}
@@ -113,6 +113,9 @@ contract HatsAvsHatter is HatsModule, IHatsAvsTypes {
 
             // Emit the event
             emit HatCreationResultReceived(newTriggerId, newHatId, true);
+        } else {
+            nextTriggerId = nextTriggerId + 1;
+            emit HatCreationRejected(nextTriggerId, creationData.reason);
         }
     }
 
@@ -20,6 +20,44 @@ interface IHatsAvsTypes {
         bytes data;
     }
 
+    /**
+     * @notice Struct to store the answer of the hats agent
+     * @param triggerId Unique identifier for the trigger
+     * @param answer Final answer of the model
+     * @param model Name of the model that answered
+     * @param toolCallsCount Number of tool calls executed for the answer
+     */
+    struct AgentResult {
+        uint64 triggerId;
+        string answer;
+        string model;
+        uint32 toolCallsCount;
+    }
+
+    /**
+     * @notice Struct to store a tool call executed by the hats agent
+     * @param name Name of the tool
+     * @param argsHash keccak256 of the JSON arguments of the call
+     */
+    struct ToolCallTrace {
+        string name;
+        bytes32 argsHash;
+    }
+
+    /**
+     * @notice Struct to store the answer of the hats agent with its tool calls
+     * @param triggerId Unique identifier for the trigger
+     * @param answer Final answer of the model
+     * @param model Name of the model that answered
+     * @param trace Tool calls executed for the answer, in order
+     */
+    struct AgentResultWithTrace {
+        uint64 triggerId;
+        string answer;
+        string model;
+        ToolCallTrace[] trace;
+    }
+
     /**
      * @notice Emitted when a new status check trigger is created
      * @param triggerId The ID of the trigger
@@ -102,6 +140,7 @@ interface IHatsAvsTypes {
      * @param requestor The address that requested the hat creation
      * @param hatId The ID of the created hat (0 if not yet created)
      * @param success Whether creation was successful
+     * @param reason Reason the request was rejected, empty on success
      */
     struct HatCreationData {
         uint256 admin;
@@ -114,6 +153,7 @@ interface IHatsAvsTypes {
         address requestor;
         uint256 hatId;
         bool success;
+        string reason;
     }
 
     /**
@@ -152,6 +192,13 @@ interface IHatsAvsTypes {
         bool success
     );
 
+    /**
+     * @notice Emitted when a hat creation request is rejected
+     * @param triggerId The ID of the trigger
+     * @param reason Why the request was rejected
+     */
+    event HatCreationRejected(uint64 indexed triggerId, string reason);
+
     /**
      * @notice Struct to store hat minting data
      * @param hatId The hat ID to mint
@@ -195,4 +242,32 @@ interface IHatsAvsTypes {
         address indexed wearer,
         bool success
     );
+
+    /**
+     * @notice Struct to store batch hat minting data
+     * @param hatId The hat ID to mint
+     * @param wearers The addresses that will wear the hat
+     * @param successes Whether minting was approved for each wearer
+     * @param reasons Reason for failure of each wearer, empty on success
+     */
+    struct HatBatchMintingData {
+        uint256 hatId;
+        address[] wearers;
+        bool[] successes;
+        string[] reasons;
+    }
+
+    /**
+     * @notice Emitted when a new batch hat minting trigger is created
+     * @param triggerId The ID of the trigger
+     * @param creator The address that created the trigger
+     * @param hatId The hat ID to mint
+     * @param wearers The addresses that will wear the hat
+     */
+    event BatchMintingTrigger(
+        uint64 indexed triggerId,
+        address indexed creator,
+        uint256 hatId,
+        address[] wearers
+    );
 }